@@ -1,15 +1,130 @@
 use crate::debugger_command::DebuggerCommand;
+use crate::disassembler::{self, Instruction};
 use crate::inferior::{Inferior, Status};
+use crate::location::Place;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
+use crate::dwarf_data::{DwarfData, Error as DwarfError, Line, Location, Type, TypeEncoding};
+use crate::examine::{self, ExamineSpec, Format};
+use crate::expression::{Context, Expression, Value};
+use crate::registers;
+use crate::script::Script;
+use crate::signals::{self, SignalTable};
+use crate::source::{self, SourcePath};
+use crate::tui::{Layout, Pane, PaneKind, Tui};
+use crate::unwind::Frame;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
 use std::collections::HashMap;
+use std::convert::TryInto;
 
 // Milestone 6: Continuing from breakpoints
 #[derive(Clone, Debug)]
 pub struct Breakpoint {
+    /// Number shown to the user; 0 for the temporary breakpoints deet plants internally
+    pub id: usize,
     pub addr: usize,
     pub orig_byte: u8,
+    pub enabled: bool,
+    pub hit_count: usize,
+    /// Only stop if this evaluates to true
+    pub condition: Option<Expression>,
+    /// Number of upcoming hits to resume from without stopping
+    pub ignore_count: usize,
+    /// Commands to run when the breakpoint stops the inferior
+    pub commands: Vec<String>,
+    /// For an internal breakpoint at a return address, the frame whose return it waits for
+    pub frame_exit: Option<FrameExit>,
+    /// Whether it was set at a raw `*addr`, which stays put wherever the program is loaded
+    pub absolute: bool,
+}
+
+impl Breakpoint {
+    pub fn new(id: usize, addr: usize) -> Breakpoint {
+        Breakpoint {
+            id,
+            addr,
+            orig_byte: 0,
+            enabled: true,
+            hit_count: 0,
+            condition: None,
+            ignore_count: 0,
+            commands: Vec::new(),
+            frame_exit: None,
+            absolute: false,
+        }
+    }
+
+    /// Creates an internal breakpoint that has already been written into the inferior.
+    pub fn temporary(addr: usize, orig_byte: u8) -> Breakpoint {
+        Breakpoint {
+            orig_byte,
+            ..Breakpoint::new(0, addr)
+        }
+    }
+}
+
+/// Where a stack frame returns to. Reaching `addr` only means the frame has returned if the
+/// stack has unwound past its CFA; a deeper, recursive call may return to the same address.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameExit {
+    pub addr: usize,
+    /// The thread the frame belongs to
+    pub thread: usize,
+    pub cfa: usize,
+}
+
+impl FrameExit {
+    /// Returns whether the frame is gone, given the stopped thread and its %rsp.
+    pub fn has_returned(&self, thread: usize, rsp: usize) -> bool {
+        thread == self.thread && rsp >= self.cfa
+    }
+}
+
+/// What kind of access triggers a watchpoint
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+impl WatchKind {
+    fn description(self) -> &'static str {
+        match self {
+            WatchKind::Write => "Hardware watchpoint",
+            WatchKind::Read => "Hardware read watchpoint",
+            WatchKind::Access => "Hardware access (read/write) watchpoint",
+        }
+    }
+}
+
+/// Which process to keep debugging when the inferior forks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FollowForkMode {
+    Parent,
+    Child,
+}
+
+/// A hardware watchpoint, which occupies one of the debug registers DR0-DR3.
+#[derive(Clone, Debug)]
+pub struct Watchpoint {
+    pub id: usize,
+    /// What the user asked to watch, e.g. `global` or `*0x404028`
+    pub expression: String,
+    pub addr: usize,
+    pub entity_type: Type,
+    pub kind: WatchKind,
+    /// Index of the debug register the watchpoint is programmed into
+    pub slot: usize,
+    pub enabled: bool,
+    pub hit_count: usize,
+    /// Whether the watched variable lives in a stack frame, which only exists in this run
+    pub is_local: bool,
+    /// For a local, how to tell that its frame has returned, if it could be unwound
+    pub frame_exit: Option<FrameExit>,
+    /// The value seen when the watchpoint was last set or triggered
+    pub old_value: Vec<u8>,
 }
 
 pub struct Debugger {
@@ -17,23 +132,46 @@ pub struct Debugger {
     history_path: String,
     readline: Editor<()>,
     inferior: Option<Inferior>,
+    /// Number of the current inferior, for telling it apart from the held ones
+    inferior_id: usize,
+    /// Processes from forks that are kept stopped while we debug another one (detach-on-fork off)
+    held: Vec<(usize, Inferior)>,
+    next_inferior_id: usize,
+    follow_fork_mode: FollowForkMode,
+    detach_on_fork: bool,
+    signals: SignalTable,
     debug_data: DwarfData,
     breakpoints: HashMap<usize, Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    next_breakpoint_id: usize,
+    /// The current thread's stack, unwound the first time it's needed after each stop
+    frames: Vec<Frame>,
+    /// Index into `frames` of the frame that print and info locals look at
+    selected_frame: usize,
+    /// Format and unit size of the last `x` command, which later ones default to
+    examine_format: Format,
+    examine_unit: usize,
+    /// Where the last `x` command stopped, for an `x` without an address to carry on from
+    next_examine_addr: Option<usize>,
+    /// Values printed so far, which `$n` refers to
+    values: Vec<Value>,
+    source_path: SourcePath,
+    /// The file and line a `list` without a location carries on from
+    list_position: Option<(String, usize)>,
+    /// The full-screen front-end, when it's enabled; otherwise deet is a plain command line
+    tui: Option<Tui>,
+    /// Command files being run and breakpoint commands waiting to run, innermost last
+    scripts: Vec<Script>,
+    /// Whether to quit once the scripts are done rather than prompting for more commands
+    batch: bool,
 }
 
 impl Debugger {
     /// Initializes the debugger.
     pub fn new(target: &str) -> Debugger {
-        let debug_data = match DwarfData::from_file(target) {
-            Ok(val) => val,
-            Err(DwarfError::ErrorOpeningFile) => {
-                println!("Could not open file {}", target);
-                std::process::exit(1);
-            }
-            Err(DwarfError::DwarfFormatError(err)) => {
-                println!("Could not debugging symbols from {}: {:?}", target, err);
-                std::process::exit(1);
-            }
+        let debug_data = match load_debug_data(target) {
+            Some(val) => val,
+            None => std::process::exit(1),
         };
         debug_data.print();
         // TODO (milestone 3): initialize the DwarfData
@@ -48,39 +186,59 @@ impl Debugger {
             history_path,
             readline,
             inferior: None,
+            inferior_id: 1,
+            held: Vec::new(),
+            next_inferior_id: 2,
+            follow_fork_mode: FollowForkMode::Parent,
+            detach_on_fork: true,
+            signals: SignalTable::default(),
             debug_data,
             breakpoints: HashMap::new(),
+            watchpoints: Vec::new(),
+            next_breakpoint_id: 1,
+            frames: Vec::new(),
+            selected_frame: 0,
+            examine_format: Format::Hex,
+            examine_unit: 4,
+            next_examine_addr: None,
+            values: Vec::new(),
+            source_path: SourcePath::default(),
+            list_position: None,
+            tui: None,
+            scripts: Vec::new(),
+            batch: false,
         }
     }
 
-    pub fn run(&mut self) {
+    /// Runs commands until `quit`, or in batch mode until the command files run out.
+    pub fn run(&mut self, batch: bool) {
+        self.batch = batch;
         loop {
+            // keeps the panes up to date with every stop, frame selection and breakpoint change
+            self.refresh_tui();
             match self.get_next_command() {
                 DebuggerCommand::Run(args) => {
+                    // a process we attached to isn't ours to kill
+                    if let Some(inferior) = self.inferior.as_ref().filter(|inferior| inferior.is_attached()) {
+                        println!(
+                            "Process {} was attached to, not started here. Use `detach` to let it go before running the program.",
+                            inferior.pid()
+                        );
+                        continue;
+                    }
                     // Kill any existing inferiors before starting new ones
                     // , so that there is only one inferior at a time
                     if self.inferior.is_some() {
-                        self.inferior.as_mut().unwrap().kill();
-                        self.inferior = None;
+                        self.kill_inferior();
+                        self.remove_local_watchpoints();
                     }
 
-                    if let Some(inferior) = Inferior::new(&self.target, &args, &mut self.breakpoints) {
+                    if let Some(inferior) = Inferior::new(&self.target, &args, &self.debug_data) {
                         // Create the inferior
-                        self.inferior = Some(inferior);
+                        self.set_inferior(inferior);
                         // TODO (milestone 1): make the inferior run
-                        match self.inferior.as_mut().unwrap().run(&mut self.breakpoints).unwrap() {
-                            Status::Stopped(signal, instruction_ptr) => {
-                                println!("Child stopped (signal {})", signal);
-                                if let Some(lineno) = DwarfData::get_line_from_addr(&self.debug_data, instruction_ptr) {
-                                    println!("Stopped at {}", lineno);
-                                }
-                            }
-                            Status::Exited(exit_code) => {
-                                println!("Child exited (status {})", exit_code);
-                                self.inferior = None;
-                            }
-                            _ => (),
-                        }
+                        let status = self.inferior.as_mut().unwrap().run(&self.debug_data, &self.values, &mut self.breakpoints);
+                        self.handle_status(status, false);
                     } else {
                         println!("Error starting subprocess");
                     }
@@ -95,121 +253,2225 @@ impl Debugger {
                         continue
                     }
 
+                    let status = self.inferior.as_mut().unwrap().run(&self.debug_data, &self.values, &mut self.breakpoints);
+                    self.handle_status(status, false);
+                }
 
-                    match self.inferior.as_mut().unwrap().run(&mut self.breakpoints).unwrap() {
-                        Status::Stopped(signal, instruction_ptr) => {
-                            println!("Child stopped (signal {})", signal);
-                            if let Some(lineno) = DwarfData::get_line_from_addr(&self.debug_data, instruction_ptr) {
-                                println!("Stopped at {}", lineno);
-                            }
-                        }
-                        Status::Exited(exit_code) => {
-                            println!("Child exited (status {})", exit_code);
-                            self.inferior = None;
-                        }
-                        _ => (),
-                    } 
+                // Source-level stepping
+                DebuggerCommand::Step => {
+                    if self.inferior.is_none() {
+                        println!("No inferior is running");
+                        continue
+                    }
+                    let status = self.inferior.as_mut().unwrap().step_line(
+                        &self.debug_data,
+                        &self.values,
+                        &mut self.breakpoints,
+                        true,
+                    );
+                    self.handle_status(status, true);
+                }
+
+                DebuggerCommand::Next => {
+                    if self.inferior.is_none() {
+                        println!("No inferior is running");
+                        continue
+                    }
+                    let status = self.inferior.as_mut().unwrap().step_line(
+                        &self.debug_data,
+                        &self.values,
+                        &mut self.breakpoints,
+                        false,
+                    );
+                    self.handle_status(status, true);
+                }
+
+                // Instruction-level stepping
+                DebuggerCommand::StepInstruction => {
+                    if self.inferior.is_none() {
+                        println!("No inferior is running");
+                        continue
+                    }
+                    let status = self.inferior.as_mut().unwrap().step_instruction(&mut self.breakpoints);
+                    self.handle_status(status, true);
+                    if self.inferior.is_some() {
+                        self.disassemble(0, 1);
+                    }
+                }
+
+                DebuggerCommand::NextInstruction => {
+                    if self.inferior.is_none() {
+                        println!("No inferior is running");
+                        continue
+                    }
+                    let status = self.inferior.as_mut().unwrap().next_instruction(&self.debug_data, &self.values, &mut self.breakpoints);
+                    self.handle_status(status, true);
+                    if self.inferior.is_some() {
+                        self.disassemble(0, 1);
+                    }
+                }
+
+                DebuggerCommand::Disassemble(count) => {
+                    if self.inferior.is_none() {
+                        println!("No inferior is running");
+                        continue
+                    }
+                    self.disassemble(4, count.unwrap_or(8));
+                }
+
+                DebuggerCommand::List(location) => self.list(location.as_deref()),
+
+                DebuggerCommand::Directory(dirs) => {
+                    if dirs.is_empty() {
+                        self.source_path.reset();
+                    }
+                    for dir in &dirs {
+                        self.source_path.add_directory(dir);
+                    }
+                    match self.source_path.directories() {
+                        [] => println!("Source files are only looked for where they were compiled."),
+                        dirs => println!("Source directories searched: {}", dirs.join(":")),
+                    }
+                }
+
+                DebuggerCommand::Source(path) => self.source(&path),
+
+                DebuggerCommand::Commands(id) => self.define_commands(id),
+
+                DebuggerCommand::Tui(true) => {
+                    let layout = self.tui.as_ref().map_or(Layout::Source, |tui| tui.layout);
+                    self.enable_tui(layout);
+                }
+
+                DebuggerCommand::Tui(false) => self.tui = None,
+
+                DebuggerCommand::Layout(layout) => self.enable_tui(layout),
+
+                DebuggerCommand::Finish => {
+                    if self.inferior.is_none() {
+                        println!("No inferior is running");
+                        continue
+                    }
+                    self.finish();
                 }
                 
                 // Milestone 3: Printing a backtrace
                 DebuggerCommand::Backtrace => {
-                    self.inferior.as_mut().unwrap().print_backtrace(&self.debug_data).unwrap();
+                    if self.inferior.is_none() {
+                        println!("No inferior is running");
+                        continue
+                    }
+                    if let Err(err) = self.unwind_stack() {
+                        println!("Error reading the stack: {}", err);
+                        continue
+                    }
+                    for (level, frame) in self.frames.iter().enumerate() {
+                        println!("#{:<2} {}", level, self.describe_frame(frame));
+                    }
+                }
+
+                DebuggerCommand::Up(count) => {
+                    if self.inferior.is_none() {
+                        println!("No stack.");
+                        continue
+                    }
+                    self.move_frame(count as isize);
+                }
+
+                DebuggerCommand::Down(count) => {
+                    if self.inferior.is_none() {
+                        println!("No stack.");
+                        continue
+                    }
+                    self.move_frame(-(count as isize));
+                }
+
+                DebuggerCommand::Frame(level) => {
+                    if self.inferior.is_none() {
+                        println!("No stack.");
+                        continue
+                    }
+                    self.select_frame(level);
                 }
 
                 // Milestone 5: Setting breakpoints
-                DebuggerCommand::Breakpoint(bp_addr) => {
-                    if bp_addr.starts_with('*') {
+                DebuggerCommand::Breakpoint(bp_addr, condition) => {
+                    let condition = match condition.map(|condition| Expression::parse(&condition)) {
+                        Some(Ok(expr)) => Some(expr),
+                        Some(Err(err)) => {
+                            println!("{}", err);
+                            continue;
+                        }
+                        None => None,
+                    };
+                    let addr = if bp_addr.starts_with('*') {
                         // Case 1. raw address
-                        println!("Set breakpoint {} at {}", self.breakpoints.len(), &bp_addr[1..]);
                         match DebuggerCommand::parse_address(&bp_addr[1..]) {
-                            Some(addr) => {
-                                self.breakpoints.insert(addr, Breakpoint { addr, orig_byte: 0 });
-                                println!("Set breakpoint {} at {:#x}", self.breakpoints.len(), addr);
-                            }
+                            Some(addr) => addr,
                             None => {
                                 println!("Please use legal hex number :(");
                                 continue;
                             }
                         }
-                    // } else if let Some(lineno) = DebuggerCommand::parse_address(&bp_addr)  {
-                    } else if let Ok(lineno) = bp_addr.parse() {
-                        // Case 2. line number
-                        match self.debug_data.get_addr_for_line(None, lineno) {
-                            Some(addr) => {
-                                self.breakpoints.insert(addr, Breakpoint { addr, orig_byte: 0 });
-                                println!("Set breakpoint {} at {:#x}", self.breakpoints.len(), addr);
+                    } else if let Some(colon) = bp_addr.rfind(':') {
+                        // Case 2. file:line or file:function
+                        let (file, location) = (&bp_addr[..colon], &bp_addr[colon + 1..]);
+                        let files = self.debug_data.get_matching_files(file);
+                        if files.is_empty() {
+                            println!("No source file named {}.", file);
+                            continue;
+                        }
+                        if files.len() > 1 {
+                            println!("Ambiguous file name \"{}\" matches:", file);
+                            for name in files {
+                                println!("  {}", name);
+                            }
+                            println!("Use a longer path to pick one.");
+                            continue;
+                        }
+                        let addr = match location.parse() {
+                            Ok(lineno) => self.debug_data.get_addr_for_line(Some(files[0]), lineno),
+                            Err(_) => self.debug_data.get_addr_for_function(Some(files[0]), location),
+                        };
+                        match addr {
+                            Some(addr) => addr,
+                            None => {
+                                println!("No line or function \"{}\" in file \"{}\".", location, file);
+                                continue;
                             }
+                        }
+                    } else if let Ok(lineno) = bp_addr.parse() {
+                        // Case 3. line number in the current source file
+                        let file = self.default_source_file();
+                        match self.debug_data.get_addr_for_line(file.as_deref(), lineno) {
+                            Some(addr) => addr,
                             None => {
                                 println!("Please use legal lineno :(");
                                 continue;
                             }
                         }
                     } else {
-                        // Case 3. function name or none of the cases
+                        // Case 4. function name or none of the cases
                         match self.debug_data.get_addr_for_function(None, &bp_addr) {
-                            Some(addr) => {
-                                self.breakpoints.insert(addr, Breakpoint { addr, orig_byte: 0 });
-                                println!("Set breakpoint {} at {:#x}", self.breakpoints.len(), addr);
-                            }
+                            Some(addr) => addr,
                             None => {
                                 println!("Please use legal symbol as the breakpoint :(");
                                 continue;
                             }
                         }
+                    };
+                    self.add_breakpoint(addr, condition, bp_addr.starts_with('*'));
+                }
+
+                DebuggerCommand::InfoBreakpoints => self.print_breakpoints(),
+
+                DebuggerCommand::Watch(expression, kind) => self.add_watchpoint(&expression, kind),
+
+                DebuggerCommand::Delete(id) => match id {
+                    Some(id) => self.delete_breakpoint(id),
+                    None => {
+                        let mut ids: Vec<usize> = self.breakpoints.values().map(|bp| bp.id).collect();
+                        ids.extend(self.watchpoints.iter().map(|wp| wp.id));
+                        ids.sort();
+                        for id in ids {
+                            self.delete_breakpoint(id);
+                        }
+                    }
+                },
+
+                DebuggerCommand::Ignore(id, count) => match self.find_breakpoint_addr(id) {
+                    Some(addr) => {
+                        self.breakpoints.get_mut(&addr).unwrap().ignore_count = count;
+                        match count {
+                            0 => println!("Will stop next time breakpoint {} is reached.", id),
+                            1 => println!("Will ignore next crossing of breakpoint {}.", id),
+                            _ => println!("Will ignore next {} crossings of breakpoint {}.", count, id),
+                        }
+                    }
+                    None => println!("No breakpoint number {}.", id),
+                },
+
+                DebuggerCommand::Enable(id) => self.set_breakpoints_enabled(id, true),
+
+                DebuggerCommand::Disable(id) => self.set_breakpoints_enabled(id, false),
+
+                // Printing variables
+                DebuggerCommand::Print(name) => {
+                    if self.inferior.is_none() {
+                        println!("No inferior is running");
+                        continue
+                    }
+                    if name.is_empty() {
+                        println!("Usage: print <expression>");
+                        continue
                     }
+                    self.print_expression(&name);
                 }
 
-                DebuggerCommand::Quit => {
-                    // Kill any existing inferiors before starting new ones
-                    // , so that there is only one inferior at a time
-                    if self.inferior.is_some() {
-                        self.inferior.as_mut().unwrap().kill();
-                        self.inferior = None;
+                DebuggerCommand::InfoLocals => {
+                    if self.inferior.is_none() {
+                        println!("No inferior is running");
+                        continue
                     }
-                    return;
+                    self.print_frame_variables(false);
                 }
-            }
-        }
-    }
 
-    /// This function prompts the user to enter a command, and continues re-prompting until the user
-    /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
-    ///
-    /// You don't need to read, understand, or modify this function.
-    fn get_next_command(&mut self) -> DebuggerCommand {
-        loop {
-            // Print prompt and get next line of user input
-            match self.readline.readline("(deet) ") {
-                Err(ReadlineError::Interrupted) => {
-                    // User pressed ctrl+c. We're going to ignore it
-                    println!("Type \"quit\" to exit");
+                DebuggerCommand::InfoArgs => {
+                    if self.inferior.is_none() {
+                        println!("No inferior is running");
+                        continue
+                    }
+                    self.print_frame_variables(true);
                 }
-                Err(ReadlineError::Eof) => {
-                    // User pressed ctrl+d, which is the equivalent of "quit" for our purposes
-                    return DebuggerCommand::Quit;
+
+                DebuggerCommand::InfoRegisters(names) => {
+                    if self.inferior.is_none() {
+                        println!("The program has no registers now.");
+                        continue
+                    }
+                    self.print_registers(&names);
                 }
-                Err(err) => {
-                    panic!("Unexpected I/O error: {:?}", err);
+
+                DebuggerCommand::InfoAllRegisters => {
+                    if self.inferior.is_none() {
+                        println!("The program has no registers now.");
+                        continue
+                    }
+                    self.print_registers(&[]);
+                    self.print_fp_registers();
                 }
-                Ok(line) => {
-                    if line.trim().len() == 0 {
-                        continue;
+
+                DebuggerCommand::SetRegister(name, val) => {
+                    if self.inferior.is_none() {
+                        println!("The program has no registers now.");
+                        continue
                     }
-                    self.readline.add_history_entry(line.as_str());
-                    if let Err(err) = self.readline.save_history(&self.history_path) {
-                        println!(
-                            "Warning: failed to save history file at {}: {}",
-                            self.history_path, err
-                        );
+                    self.set_register(&name, val);
+                }
+
+                DebuggerCommand::SetVariable(target, value) => {
+                    if self.inferior.is_none() {
+                        println!("No inferior is running");
+                        continue
                     }
-                    let tokens: Vec<&str> = line.split_whitespace().collect();
-                    if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
-                        return cmd;
-                    } else {
-                        println!("Unrecognized command.");
+                    self.set_variable(&target, &value);
+                }
+
+                DebuggerCommand::Examine(spec, address) => {
+                    if self.inferior.is_none() {
+                        println!("No inferior is running");
+                        continue
+                    }
+                    self.examine(spec, address.as_deref());
+                }
+
+                DebuggerCommand::Attach(pid) => self.attach(pid),
+
+                DebuggerCommand::Detach => {
+                    if self.inferior.is_none() {
+                        println!("The program is not being run.");
+                        continue
+                    }
+                    self.detach();
+                }
+
+                DebuggerCommand::InfoThreads => {
+                    if self.inferior.is_none() {
+                        println!("No threads.");
+                        continue
+                    }
+                    self.print_threads();
+                }
+
+                DebuggerCommand::Thread(id) => {
+                    if self.inferior.is_none() {
+                        println!("The program is not being run.");
+                        continue
+                    }
+                    self.select_thread(id);
+                }
+
+                DebuggerCommand::SetFollowForkMode(mode) => self.follow_fork_mode = mode,
+
+                DebuggerCommand::SetDetachOnFork(detach) => self.detach_on_fork = detach,
+
+                DebuggerCommand::InfoInferiors => self.print_inferiors(),
+
+                DebuggerCommand::Inferior(id) => self.switch_inferior(id),
+
+                DebuggerCommand::Handle(signal, actions) => {
+                    if signal == Signal::SIGTRAP {
+                        println!("SIGTRAP is used by the debugger.");
+                        continue
+                    }
+                    for action in actions {
+                        self.signals.apply(signal, action);
+                    }
+                    if let Some(inferior) = self.inferior.as_mut() {
+                        inferior.set_signal_handling(&self.signals);
+                    }
+                    for (_, inferior) in self.held.iter_mut() {
+                        inferior.set_signal_handling(&self.signals);
                     }
+                    self.print_signals(Some(signal));
+                }
+
+                DebuggerCommand::InfoSignals(signal) => self.print_signals(signal),
+
+                DebuggerCommand::Signal(signal) => {
+                    if self.inferior.is_none() {
+                        println!("The program is not being run.");
+                        continue
+                    }
+                    match signal {
+                        Some(signal) => println!("Continuing with signal {}.", signal),
+                        None => println!("Continuing with no signal."),
+                    }
+                    let inferior = self.inferior.as_mut().unwrap();
+                    inferior.set_pending_signal(signal);
+                    let status = inferior.run(&self.debug_data, &self.values, &mut self.breakpoints);
+                    self.handle_status(status, false);
+                }
+
+                DebuggerCommand::Quit => {
+                    // Kill any existing inferiors before starting new ones
+                    // , so that there is only one inferior at a time. A process we attached to
+                    // is left running instead.
+                    if self.inferior.as_ref().is_some_and(|inferior| inferior.is_attached()) {
+                        self.detach();
+                    } else if self.inferior.is_some() {
+                        self.kill_inferior();
+                    }
+                    for (_, mut inferior) in self.held.drain(..) {
+                        if inferior.is_attached() {
+                            let pid = inferior.pid();
+                            if let Err(err) = inferior.detach(&self.breakpoints) {
+                                println!("Error detaching from process {}: {}", pid, err);
+                            }
+                        } else if let Err(err) = inferior.kill() {
+                            println!("Error killing process {}: {}", inferior.pid(), err);
+                        }
+                    }
+                    return;
                 }
             }
         }
     }
+
+    /// Attaches to the running process `pid`, loading debugging symbols from its executable.
+    pub fn attach(&mut self, pid: i32) {
+        if self.inferior.is_some() {
+            println!("A program is being debugged already. Kill or detach it first.");
+            return;
+        }
+        let exe_path = format!("/proc/{}/exe", pid);
+        let target = match std::fs::read_link(&exe_path) {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(err) => {
+                println!("Cannot attach to process {}: {}", pid, err);
+                return;
+            }
+        };
+        if !self.change_target(&exe_path, target) {
+            return;
+        }
+
+        println!("Attaching to program: {}, process {}", self.target, pid);
+        match Inferior::attach(Pid::from_raw(pid), &self.debug_data) {
+            Ok(inferior) => {
+                self.set_inferior(inferior);
+                match self.inferior.as_ref().unwrap().get_registers() {
+                    Ok(regs) => self.print_stop_location(regs.rip as usize),
+                    Err(err) => println!("Could not read registers: {}", err),
+                }
+            }
+            Err(nix::Error::Sys(nix::errno::Errno::EPERM)) => println!(
+                "Could not attach to process {}: Operation not permitted (check \
+                 /proc/sys/kernel/yama/ptrace_scope)",
+                pid
+            ),
+            Err(err) => println!("Could not attach to process {}: {}", pid, err),
+        }
+    }
+
+    /// Switches to debugging the program `target`, whose symbols are read through `exe_path`.
+    /// Returns false if they can't be loaded.
+    fn change_target(&mut self, exe_path: &str, target: String) -> bool {
+        // `deet -p` has already loaded the symbols through /proc
+        if target != self.target && exe_path != self.target {
+            // read the symbols through /proc in case the file on disk has been replaced
+            self.debug_data = match load_debug_data(exe_path) {
+                Some(val) => val,
+                None => return false,
+            };
+            // addresses from the previous program are meaningless in this one
+            if !self.breakpoints.is_empty() || !self.watchpoints.is_empty() {
+                println!("Deleting breakpoints and watchpoints set in the previous program.");
+                self.breakpoints.clear();
+                self.watchpoints.clear();
+            }
+        }
+        self.target = target;
+        true
+    }
+
+    /// Decides which of the inferior and its newly forked child `child` to keep debugging, and
+    /// detaches from or holds on to the other, as set by follow-fork-mode and detach-on-fork.
+    fn follow_fork(&mut self, child: Pid) {
+        let parent = self.inferior.as_mut().unwrap();
+        let parent_pid = parent.pid();
+        let child = match parent.take_fork_child(child) {
+            Ok(inferior) => inferior,
+            Err(err) => {
+                println!("Cannot take control of child process {}: {}", child, err);
+                return;
+            }
+        };
+        let child_id = self.next_inferior_id;
+        self.next_inferior_id += 1;
+        match self.follow_fork_mode {
+            FollowForkMode::Parent => {
+                if self.detach_on_fork {
+                    self.release(child, "child");
+                } else {
+                    self.hold(child_id, child);
+                }
+            }
+            FollowForkMode::Child => {
+                println!("[Attaching after process {} fork to child process {}]", parent_pid, child.pid());
+                let parent = self.inferior.take().unwrap();
+                if self.detach_on_fork {
+                    self.release(parent, "parent");
+                } else {
+                    self.hold(self.inferior_id, parent);
+                }
+                self.inferior_id = child_id;
+                self.inferior = Some(child);
+                // unlike the breakpoints, the watchpoints aren't copied by fork
+                self.install_watchpoints();
+            }
+        }
+    }
+
+    /// Detaches from `inferior`, the `which` ("parent" or "child") side of a fork.
+    fn release(&self, inferior: Inferior, which: &str) {
+        let pid = inferior.pid();
+        match inferior.detach(&self.breakpoints) {
+            Ok(()) => println!("[Detaching after fork from {} process {}]", which, pid),
+            Err(err) => println!("Error detaching from process {}: {}", pid, err),
+        }
+    }
+
+    /// Keeps `inferior` stopped until the user switches to it. It doesn't keep our breakpoints
+    /// in the meantime, since they may change before then.
+    fn hold(&mut self, id: usize, mut inferior: Inferior) {
+        if let Err(err) = inferior.remove_breakpoints(&self.breakpoints) {
+            println!("Cannot remove breakpoints from process {}: {}", inferior.pid(), err);
+        }
+        println!("[Inferior {} (process {}) is kept stopped]", id, inferior.pid());
+        self.held.push((id, inferior));
+    }
+
+    /// Loads the symbols of the program the inferior has just execed and sets its breakpoints
+    /// again.
+    fn follow_exec(&mut self) {
+        let mut inferior = self.inferior.take().unwrap();
+        let exe_path = format!("/proc/{}/exe", inferior.pid());
+        let target = std::fs::read_link(&exe_path)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| exe_path.clone());
+        println!("process {} is executing new program: {}", inferior.pid(), target);
+        self.remove_local_watchpoints();
+        if self.change_target(&exe_path, target) && inferior.update_load_bias(&self.debug_data).is_none() {
+            println!("Could not find where the new program was loaded");
+        }
+        self.set_inferior(inferior);
+    }
+
+    /// Lists the current inferior and the ones held after forks.
+    fn print_inferiors(&self) {
+        println!("  Num  Description       Executable");
+        let current = self.inferior.as_ref().map(|inferior| (self.inferior_id, inferior));
+        let mut inferiors: Vec<_> = current
+            .into_iter()
+            .chain(self.held.iter().map(|(id, inferior)| (*id, inferior)))
+            .collect();
+        inferiors.sort_by_key(|(id, _)| *id);
+        for (id, inferior) in inferiors {
+            let exe = std::fs::read_link(format!("/proc/{}/exe", inferior.pid()))
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let marker = if id == self.inferior_id { '*' } else { ' ' };
+            println!("{} {:<4} {:<17} {}", marker, id, format!("process {}", inferior.pid()), exe);
+        }
+    }
+
+    /// Makes held inferior `id` the current one, holding the current one in its place.
+    fn switch_inferior(&mut self, id: usize) {
+        let index = match self.held.iter().position(|(held_id, _)| *held_id == id) {
+            Some(index) => index,
+            None if id == self.inferior_id && self.inferior.is_some() => return,
+            None => {
+                println!("Inferior ID {} not known.", id);
+                return;
+            }
+        };
+        if let Some(current) = self.inferior.take() {
+            self.hold(self.inferior_id, current);
+        }
+        let (_, inferior) = self.held.remove(index);
+        let exe_path = format!("/proc/{}/exe", inferior.pid());
+        // it may have execed a different program than the one we're looking at
+        if let Ok(target) = std::fs::read_link(&exe_path) {
+            self.change_target(&exe_path, target.to_string_lossy().into_owned());
+        }
+        println!("[Switching to inferior {} (process {})]", id, inferior.pid());
+        self.inferior_id = id;
+        self.set_inferior(inferior);
+        if let Ok(regs) = self.inferior.as_ref().unwrap().get_registers() {
+            self.print_stop_location(regs.rip as usize);
+        }
+    }
+
+    /// Starts debugging a freshly started or attached inferior: moves our addresses to where
+    /// the program was loaded this time, then installs the breakpoints and watchpoints.
+    fn set_inferior(&mut self, mut inferior: Inferior) {
+        self.reset_frames();
+        let load_bias = inferior.load_bias();
+        inferior.set_signal_handling(&self.signals);
+        self.inferior = Some(inferior);
+        let delta = load_bias.wrapping_sub(self.debug_data.load_bias());
+        if delta != 0 {
+            self.debug_data.set_load_bias(load_bias);
+            self.breakpoints = self
+                .breakpoints
+                .drain()
+                .map(|(addr, mut bp)| {
+                    if !bp.absolute {
+                        bp.addr = addr.wrapping_add(delta);
+                    }
+                    (bp.addr, bp)
+                })
+                .collect();
+            // watchpoints on locals are gone by now, so all of these are static addresses, or
+            // raw ones that stay put
+            for wp in self.watchpoints.iter_mut().filter(|wp| !wp.expression.starts_with('*')) {
+                wp.addr = wp.addr.wrapping_add(delta);
+            }
+        }
+        for (id, err) in self.inferior.as_mut().unwrap().install_breakpoints(&mut self.breakpoints) {
+            println!("Cannot insert breakpoint {}: {}. It has been disabled.", id, err);
+        }
+        self.install_watchpoints();
+    }
+
+    /// Kills the inferior and forgets it.
+    fn kill_inferior(&mut self) {
+        let mut inferior = self.inferior.take().unwrap();
+        if let Err(err) = inferior.kill() {
+            println!("Error killing process {}: {}", inferior.pid(), err);
+        }
+    }
+
+    /// Removes all breakpoints from the inferior and lets it run freely.
+    fn detach(&mut self) {
+        let inferior = self.inferior.take().unwrap();
+        let pid = inferior.pid();
+        match inferior.detach(&self.breakpoints) {
+            Ok(()) => println!("Detaching from program: {}, process {}", self.target, pid),
+            Err(err) => println!("Error detaching from process {}: {}", pid, err),
+        }
+        self.remove_local_watchpoints();
+    }
+
+    /// The file a bare line number refers to: the one the inferior is stopped in, or else the
+    /// one that defines main.
+    fn default_source_file(&self) -> Option<String> {
+        let rip = self
+            .inferior
+            .as_ref()
+            .and_then(|inferior| inferior.get_registers().ok())
+            .map(|regs| regs.rip as usize);
+        let addr = rip
+            .filter(|&rip| self.debug_data.get_line_from_addr(rip).is_some())
+            .or_else(|| self.debug_data.get_addr_for_function(None, "main"))?;
+        let file = self.debug_data.get_line_from_addr(addr)?.file;
+        // the line table may name the file differently from the compilation unit
+        if self.debug_data.get_matching_files(&file).len() == 1 {
+            Some(file)
+        } else {
+            None
+        }
+    }
+
+    /// Adds a breakpoint at `addr`. If the inferior is already running, the breakpoint is
+    /// installed right away; otherwise it is installed when the inferior starts.
+    fn add_breakpoint(&mut self, addr: usize, condition: Option<Expression>, absolute: bool) {
+        if let Some(bp) = self.breakpoints.get(&addr).filter(|bp| bp.id > 0) {
+            println!("Breakpoint {} is already set at {:#x}", bp.id, addr);
+            return;
+        }
+        let mut bp = Breakpoint::new(self.next_breakpoint_id, addr);
+        bp.condition = condition;
+        bp.absolute = absolute;
+        if let Some(internal) = self.breakpoints.get(&addr) {
+            // it takes over the int3 of the breakpoint waiting for a frame to return there
+            bp.orig_byte = internal.orig_byte;
+        } else if let Some(inferior) = self.inferior.as_mut() {
+            match inferior.write_byte(addr, 0xcc) {
+                Ok(orig_byte) => bp.orig_byte = orig_byte,
+                Err(err) => {
+                    println!("Cannot insert breakpoint at {:#x}: {}", addr, err);
+                    return;
+                }
+            }
+        }
+        println!("Set breakpoint {} at {:#x}", bp.id, addr);
+        self.next_breakpoint_id += 1;
+        self.breakpoints.insert(addr, bp);
+    }
+
+    fn find_breakpoint_addr(&self, id: usize) -> Option<usize> {
+        self.breakpoints
+            .values()
+            .find(|bp| bp.id > 0 && bp.id == id)
+            .map(|bp| bp.addr)
+    }
+
+    fn delete_breakpoint(&mut self, id: usize) {
+        if let Some(index) = self.watchpoints.iter().position(|wp| wp.id == id) {
+            self.set_watchpoint_enabled(index, false);
+            self.watchpoints.remove(index);
+            self.update_frame_exit_breakpoints();
+            return;
+        }
+        let addr = match self.find_breakpoint_addr(id) {
+            Some(addr) => addr,
+            None => {
+                println!("No breakpoint number {}.", id);
+                return;
+            }
+        };
+        // put the original instruction back before forgetting the breakpoint
+        self.set_breakpoint_enabled(addr, false);
+        self.breakpoints.remove(&addr);
+        // a watched local's frame may return there
+        self.update_frame_exit_breakpoints();
+    }
+
+    /// Enables or disables breakpoint `id`, or all breakpoints if `id` is None.
+    fn set_breakpoints_enabled(&mut self, id: Option<usize>, enabled: bool) {
+        let (addrs, watchpoints): (Vec<usize>, Vec<usize>) = match id {
+            Some(id) => match self.find_breakpoint_addr(id) {
+                Some(addr) => (vec![addr], vec![]),
+                None => match self.watchpoints.iter().position(|wp| wp.id == id) {
+                    Some(index) => (vec![], vec![index]),
+                    None => {
+                        println!("No breakpoint number {}.", id);
+                        return;
+                    }
+                },
+            },
+            None => (
+                self.breakpoints.values().filter(|bp| bp.id > 0).map(|bp| bp.addr).collect(),
+                (0..self.watchpoints.len()).collect(),
+            ),
+        };
+        for addr in addrs {
+            self.set_breakpoint_enabled(addr, enabled);
+        }
+        for index in watchpoints {
+            self.set_watchpoint_enabled(index, enabled);
+        }
+    }
+
+    /// Updates the breakpoint at `addr`, writing the int3 or the original byte into the running
+    /// inferior if its state changes.
+    fn set_breakpoint_enabled(&mut self, addr: usize, enabled: bool) {
+        let bp = self.breakpoints.get_mut(&addr).unwrap();
+        if bp.enabled == enabled {
+            return;
+        }
+        if let Some(inferior) = self.inferior.as_mut() {
+            let result = if enabled {
+                inferior.write_byte(addr, 0xcc).map(|orig_byte| bp.orig_byte = orig_byte)
+            } else {
+                inferior.write_byte(addr, bp.orig_byte).map(|_| ())
+            };
+            if let Err(err) = result {
+                println!("Cannot update breakpoint {} at {:#x}: {}", bp.id, addr, err);
+                return;
+            }
+        }
+        bp.enabled = enabled;
+    }
+
+    fn print_breakpoints(&self) {
+        if self.breakpoints.values().all(|bp| bp.id == 0) && self.watchpoints.is_empty() {
+            println!("No breakpoints or watchpoints.");
+            return;
+        }
+        // (id, lines) for every breakpoint and watchpoint, listed in the order they were set
+        let mut entries: Vec<(usize, Vec<String>)> = Vec::new();
+        for bp in self.breakpoints.values().filter(|bp| bp.id != 0) {
+            let mut what = String::new();
+            if let Some(func_name) = self.debug_data.get_function_from_addr(bp.addr) {
+                what.push_str(&format!("in {} ", func_name));
+            }
+            if let Some(line) = self.debug_data.get_line_from_addr(bp.addr) {
+                what.push_str(&format!("at {}", line));
+            }
+            let mut lines = vec![format!(
+                "{:<7} {:<15} {:<3} {:#018x} {}",
+                bp.id,
+                "breakpoint",
+                if bp.enabled { "y" } else { "n" },
+                bp.addr,
+                what.trim_end()
+            )];
+            if let Some(condition) = &bp.condition {
+                lines.push(format!("\tstop only if {}", condition));
+            }
+            if bp.hit_count > 0 {
+                lines.push(format!(
+                    "\tbreakpoint already hit {} time{}",
+                    bp.hit_count,
+                    if bp.hit_count == 1 { "" } else { "s" }
+                ));
+            }
+            if bp.ignore_count > 0 {
+                lines.push(format!("\tWill ignore next {} crossings of breakpoint.", bp.ignore_count));
+            }
+            lines.extend(bp.commands.iter().map(|command| format!("        {}", command)));
+            entries.push((bp.id, lines));
+        }
+        for wp in &self.watchpoints {
+            let kind = match wp.kind {
+                WatchKind::Write => "hw watchpoint",
+                WatchKind::Read => "read watchpoint",
+                WatchKind::Access => "acc watchpoint",
+            };
+            let mut lines = vec![format!(
+                "{:<7} {:<15} {:<3} {:<18} {}",
+                wp.id,
+                kind,
+                if wp.enabled { "y" } else { "n" },
+                "",
+                wp.expression
+            )];
+            if wp.hit_count > 0 {
+                lines.push(format!(
+                    "\tbreakpoint already hit {} time{}",
+                    wp.hit_count,
+                    if wp.hit_count == 1 { "" } else { "s" }
+                ));
+            }
+            entries.push((wp.id, lines));
+        }
+        entries.sort_by_key(|(id, _)| *id);
+        println!("Num     Type            Enb Address            What");
+        for (_, lines) in entries {
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+    }
+
+    /// Sets a watchpoint on a variable or on the int at `*addr`. The variable's address is
+    /// resolved now, so a local variable can only be watched in the frame that is currently
+    /// running.
+    fn add_watchpoint(&mut self, expression: &str, kind: WatchKind) {
+        let slot = match (0..4).find(|slot| self.watchpoints.iter().all(|wp| wp.slot != *slot)) {
+            Some(slot) => slot,
+            None => {
+                println!("Hardware watchpoints used exceeds limit (the debug registers DR0-DR3 are in use).");
+                return;
+            }
+        };
+        let (addr, entity_type, is_local) = if let Some(address) = expression.strip_prefix('*') {
+            match DebuggerCommand::parse_address(address) {
+                Some(addr) => (addr, Type::new("int".to_string(), 4, TypeEncoding::Signed), false),
+                None => {
+                    println!("Please use legal hex number :(");
+                    return;
+                }
+            }
+        } else {
+            let frame = match self.inferior {
+                Some(_) => self.current_frame().ok(),
+                None => None,
+            };
+            let (lookup_pc, inline_depth) = frame
+                .as_ref()
+                .map_or((0, 0), |frame| (frame.lookup_pc, frame.inline_depth));
+            let var = match self.debug_data.get_variable(lookup_pc, inline_depth, expression) {
+                Some(var) => var,
+                None => {
+                    println!("No symbol \"{}\" in current context.", expression);
+                    return;
+                }
+            };
+            match (&var.location, &self.inferior, &frame) {
+                (Location::Address(addr), _, _) => (*addr, var.entity_type.clone(), false),
+                (_, Some(inferior), Some(frame)) => match inferior.locate_variable(var, frame, &self.debug_data) {
+                    Ok(Place::Memory(addr)) => (addr, var.entity_type.clone(), true),
+                    Ok(_) => {
+                        println!("Cannot watch \"{}\": it isn't stored in memory here.", expression);
+                        return;
+                    }
+                    Err(err) => {
+                        println!("Cannot find the address of \"{}\": {}", expression, err);
+                        return;
+                    }
+                },
+                (_, _, _) => {
+                    println!("Cannot watch local variable \"{}\" without a running program.", expression);
+                    return;
+                }
+            }
+        };
+        let len = entity_type.size;
+        if !matches!(len, 1 | 2 | 4 | 8) || addr % len != 0 {
+            println!(
+                "Cannot watch \"{}\": the debug registers can only watch an aligned value of 1, 2, 4 or 8 bytes.",
+                expression
+            );
+            return;
+        }
+
+        let mut wp = Watchpoint {
+            id: self.next_breakpoint_id,
+            expression: expression.to_string(),
+            addr,
+            entity_type,
+            kind,
+            slot,
+            enabled: true,
+            hit_count: 0,
+            is_local,
+            frame_exit: if is_local { self.selected_frame_exit() } else { None },
+            old_value: Vec::new(),
+        };
+        if let Some(inferior) = self.inferior.as_mut() {
+            if let Err(err) = inferior.set_watchpoint(slot, addr, len, kind) {
+                println!("Cannot set watchpoint on \"{}\": {}", expression, err);
+                return;
+            }
+            wp.old_value = inferior.read_memory(addr, len).unwrap_or_default();
+        }
+        println!("{} {}: {}", kind.description(), wp.id, expression);
+        self.next_breakpoint_id += 1;
+        self.watchpoints.push(wp);
+        self.update_frame_exit_breakpoints();
+    }
+
+    /// Returns where the selected frame returns to, for deleting the watchpoints on its locals
+    /// when it does.
+    fn selected_frame_exit(&mut self) -> Option<FrameExit> {
+        self.unwind_stack().ok()?;
+        let frame = &self.frames[self.selected_frame];
+        // an inlined function doesn't return; the function it was inlined into does, to the
+        // innermost function at its caller's pc
+        let caller = self.frames[self.selected_frame + 1..]
+            .iter()
+            .find(|frame| frame.inline_depth == 0)?;
+        Some(FrameExit {
+            addr: caller.pc,
+            thread: self.inferior.as_ref()?.current_thread(),
+            cfa: frame.cfa?,
+        })
+    }
+
+    /// Keeps an internal breakpoint at the return address of every frame with a watched local,
+    /// and removes the ones no longer needed. Where several frames return to the same address,
+    /// the breakpoint waits for the innermost of them, which returns first.
+    fn update_frame_exit_breakpoints(&mut self) {
+        let inferior = match self.inferior.as_mut() {
+            Some(inferior) => inferior,
+            None => return,
+        };
+        let mut exits: HashMap<usize, FrameExit> = HashMap::new();
+        for exit in self.watchpoints.iter().filter_map(|wp| wp.frame_exit) {
+            let innermost = exits.entry(exit.addr).or_insert(exit);
+            if exit.cfa < innermost.cfa {
+                *innermost = exit;
+            }
+        }
+        let unused: Vec<usize> = self
+            .breakpoints
+            .values()
+            .filter(|bp| bp.id == 0 && !exits.contains_key(&bp.addr))
+            .map(|bp| bp.addr)
+            .collect();
+        for addr in unused {
+            let bp = self.breakpoints.remove(&addr).unwrap();
+            if let Err(err) = inferior.write_byte(addr, bp.orig_byte) {
+                println!("Cannot remove breakpoint at {:#x}: {}", addr, err);
+            }
+        }
+        for (addr, exit) in exits {
+            match self.breakpoints.get_mut(&addr) {
+                Some(bp) if bp.id == 0 => bp.frame_exit = Some(exit),
+                // the user's breakpoint there stops the inferior already, and the watchpoints
+                // are deleted at the first stop after their frame returns
+                Some(_) => {}
+                None => match inferior.write_byte(addr, 0xcc) {
+                    Ok(orig_byte) => {
+                        let bp = Breakpoint {
+                            frame_exit: Some(exit),
+                            ..Breakpoint::temporary(addr, orig_byte)
+                        };
+                        self.breakpoints.insert(addr, bp);
+                    }
+                    Err(err) => println!("Cannot insert breakpoint at {:#x}: {}", addr, err),
+                },
+            }
+        }
+    }
+
+    /// Deletes the watchpoints on locals of frames that have returned, now that the inferior
+    /// has stopped. Returns whether there were any.
+    fn remove_returned_watchpoints(&mut self) -> bool {
+        let inferior = match self.inferior.as_ref() {
+            Some(inferior) => inferior,
+            None => return false,
+        };
+        let thread = inferior.current_thread();
+        let rsp = match inferior.get_registers() {
+            Ok(regs) => regs.rsp as usize,
+            Err(_) => return false,
+        };
+        let mut removed = false;
+        let mut index = 0;
+        while index < self.watchpoints.len() {
+            if self.watchpoints[index]
+                .frame_exit
+                .is_some_and(|exit| exit.has_returned(thread, rsp))
+            {
+                print_watchpoint_out_of_scope(self.watchpoints[index].id);
+                self.set_watchpoint_enabled(index, false);
+                self.watchpoints.remove(index);
+                removed = true;
+            } else {
+                index += 1;
+            }
+        }
+        if removed {
+            self.update_frame_exit_breakpoints();
+        }
+        removed
+    }
+
+    /// Programs the enabled watchpoints into a freshly started inferior.
+    fn install_watchpoints(&mut self) {
+        let inferior = self.inferior.as_mut().unwrap();
+        for wp in self.watchpoints.iter_mut().filter(|wp| wp.enabled) {
+            if let Err(err) = inferior.set_watchpoint(wp.slot, wp.addr, wp.entity_type.size, wp.kind) {
+                println!("Cannot insert watchpoint {}: {}", wp.id, err);
+            }
+            wp.old_value = inferior.read_memory(wp.addr, wp.entity_type.size).unwrap_or_default();
+        }
+    }
+
+    fn set_watchpoint_enabled(&mut self, index: usize, enabled: bool) {
+        let wp = &mut self.watchpoints[index];
+        if wp.enabled == enabled {
+            return;
+        }
+        if let Some(inferior) = self.inferior.as_mut() {
+            let result = if enabled {
+                inferior.set_watchpoint(wp.slot, wp.addr, wp.entity_type.size, wp.kind)
+            } else {
+                inferior.clear_watchpoint(wp.slot)
+            };
+            if let Err(err) = result {
+                println!("Cannot update watchpoint {}: {}", wp.id, err);
+                return;
+            }
+            if enabled {
+                wp.old_value = inferior.read_memory(wp.addr, wp.entity_type.size).unwrap_or_default();
+            }
+        }
+        wp.enabled = enabled;
+    }
+
+    /// Deletes the watchpoints on local variables once the process they were set in is gone.
+    fn remove_local_watchpoints(&mut self) {
+        for wp in self.watchpoints.iter().filter(|wp| wp.is_local) {
+            print_watchpoint_out_of_scope(wp.id);
+        }
+        self.watchpoints.retain(|wp| !wp.is_local);
+        // and the breakpoints waiting for their frames to return, which went with the process
+        self.breakpoints.retain(|_, bp| bp.id > 0);
+    }
+
+    /// Prints the value of the watchpoint in debug register `slot`, which just triggered.
+    fn report_watchpoint(&mut self, slot: usize) {
+        let inferior = self.inferior.as_ref().unwrap();
+        let wp = match self.watchpoints.iter_mut().find(|wp| wp.slot == slot) {
+            Some(wp) => wp,
+            None => {
+                println!("Child stopped by unknown watchpoint (debug register {})", slot);
+                return;
+            }
+        };
+        wp.hit_count += 1;
+        let new_value = match inferior.read_memory(wp.addr, wp.entity_type.size) {
+            Ok(val) => val,
+            Err(err) => {
+                println!("Cannot access memory at address {:#x}: {}", wp.addr, err);
+                return;
+            }
+        };
+        println!();
+        println!("{} {}: {}", wp.kind.description(), wp.id, wp.expression);
+        println!();
+        if wp.kind != WatchKind::Read && new_value != wp.old_value && !wp.old_value.is_empty() {
+            println!("Old value = {}", wp.entity_type.format_value(&wp.old_value));
+            println!("New value = {}", wp.entity_type.format_value(&new_value));
+        } else {
+            println!("Value = {}", wp.entity_type.format_value(&new_value));
+        }
+        wp.old_value = new_value;
+    }
+
+    /// Whether the read watchpoint in debug register `slot` was triggered by a write. x86 can't
+    /// trap on reads alone, so like gdb, an access that changed the value is taken to be a write
+    /// and skipped, remembering the new value.
+    fn is_write_to_read_watchpoint(&mut self, slot: usize) -> bool {
+        let inferior = self.inferior.as_ref().unwrap();
+        let wp = match self.watchpoints.iter_mut().find(|wp| wp.slot == slot) {
+            Some(wp) if wp.kind == WatchKind::Read => wp,
+            _ => return false,
+        };
+        match inferior.read_memory(wp.addr, wp.entity_type.size) {
+            Ok(value) if value != wp.old_value && !wp.old_value.is_empty() => {
+                wp.old_value = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Reports why the inferior stopped, and forgets about it if it terminated. After a step that
+    /// ended normally only the new location is printed.
+    /// Forks and execs are followed as configured, after which the inferior carries on.
+    fn handle_status(&mut self, mut status: Result<Status, nix::Error>, stepping: bool) {
+        self.reset_frames();
+        // like gdb, resuming from a breakpoint's commands skips whatever comes after the command
+        // that resumed
+        while matches!(self.scripts.last(), Some(script) if script.from_breakpoint) {
+            self.scripts.pop();
+        }
+        loop {
+            match status {
+                Ok(Status::Forked(child)) => self.follow_fork(child),
+                Ok(Status::Execed) => self.follow_exec(),
+                Ok(Status::Watchpoint(slot, instruction_ptr)) if self.is_write_to_read_watchpoint(slot) => {
+                    if stepping {
+                        // the step still ends here; it just isn't a read
+                        status = Ok(Status::Stopped(Signal::SIGTRAP, instruction_ptr));
+                        break;
+                    }
+                }
+                _ => break,
+            }
+            // a step the fork interrupted carries on in the process we follow
+            status = match self.inferior.as_mut() {
+                Some(inferior) => inferior.resume_step(&self.debug_data, &self.values, &mut self.breakpoints),
+                None => return,
+            };
+        }
+        let mut left_frame = false;
+        if let Ok(Status::Stopped(..)) | Ok(Status::Watchpoint(..)) = status {
+            let inferior = self.inferior.as_mut().unwrap();
+            if inferior.take_thread_switch() {
+                let id = inferior.current_thread();
+                let (_, tid) = inferior.threads().into_iter().find(|(thread, _)| *thread == id).unwrap();
+                println!("[Switching to thread {} (LWP {})]", id, tid);
+            }
+            left_frame = self.remove_returned_watchpoints();
+        }
+        match status {
+            Ok(Status::Stopped(signal, instruction_ptr)) => {
+                // stopping because a watched local's frame returned is explained above
+                if !(stepping || left_frame) || signal != Signal::SIGTRAP {
+                    println!("Child stopped (signal {})", signal);
+                }
+                if signal != Signal::SIGTRAP {
+                    let siginfo = self.inferior.as_ref().unwrap().get_siginfo();
+                    if let Some(description) = siginfo.ok().as_ref().and_then(signals::describe_siginfo) {
+                        println!("{}", description);
+                    }
+                }
+                self.print_stop_location(instruction_ptr);
+                if signal == Signal::SIGTRAP {
+                    let bp = self.breakpoints.get(&instruction_ptr);
+                    if let Some(bp) = bp.filter(|bp| bp.id > 0 && !bp.commands.is_empty()) {
+                        self.scripts.push(Script::for_breakpoint(bp.id, &bp.commands));
+                    }
+                }
+            }
+            Ok(Status::Watchpoint(slot, instruction_ptr)) => {
+                self.report_watchpoint(slot);
+                self.print_stop_location(instruction_ptr);
+            }
+            Ok(Status::Forked(_)) | Ok(Status::Execed) => unreachable!(),
+            Ok(Status::Exited(exit_code)) => {
+                println!("Child exited (status {})", exit_code);
+                self.inferior = None;
+                self.remove_local_watchpoints();
+            }
+            Ok(Status::Signaled(signal)) => {
+                println!("Child exited due to signal {}", signal);
+                self.inferior = None;
+                self.remove_local_watchpoints();
+            }
+            Err(err) => println!("Error controlling inferior: {}", err),
+        }
+    }
+
+    /// Prints how `signal` is handled, or all signals if it is None.
+    fn print_signals(&self, signal: Option<Signal>) {
+        let yes_no = |flag| if flag { "Yes" } else { "No" };
+        println!("Signal        Stop\tPass to program\tDescription");
+        let all: Vec<Signal> = match signal {
+            Some(signal) => vec![signal],
+            None => Signal::iterator().collect(),
+        };
+        for signal in all {
+            let handling = self.signals.get(signal);
+            println!(
+                "{:<14}{}\t{}\t\t{}",
+                signal.to_string(),
+                yes_no(handling.stop),
+                yes_no(handling.pass),
+                signals::signal_description(signal)
+            );
+        }
+    }
+
+    /// Lists the inferior's threads and where each of them is stopped, marking the current one.
+    fn print_threads(&mut self) {
+        let inferior = self.inferior.as_mut().unwrap();
+        let current = inferior.current_thread();
+        let mut frames = Vec::new();
+        for (id, tid) in inferior.threads() {
+            inferior.select_thread(id);
+            frames.push((id, tid, inferior.get_registers()));
+        }
+        inferior.select_thread(current);
+        println!("  Id   Target Id      Frame");
+        for (id, tid, regs) in frames {
+            let frame = match regs {
+                Ok(regs) => self.format_frame(regs.rip as usize),
+                Err(err) => format!("<unavailable: {}>", err),
+            };
+            let marker = if id == current { '*' } else { ' ' };
+            println!("{} {:<4} {:<14} {}", marker, id, format!("LWP {}", tid), frame);
+        }
+    }
+
+    /// Makes thread `id` the one that registers, variables and backtraces are shown for.
+    fn select_thread(&mut self, id: usize) {
+        self.reset_frames();
+        let inferior = self.inferior.as_mut().unwrap();
+        if !inferior.select_thread(id) {
+            println!("Invalid thread ID: {}", id);
+            return;
+        }
+        let tid = inferior.threads().into_iter().find(|(thread, _)| *thread == id).unwrap().1;
+        println!("[Switching to thread {} (LWP {})]", id, tid);
+        if let Ok(regs) = inferior.get_registers() {
+            self.print_stop_location(regs.rip as usize);
+        }
+    }
+
+    /// Describes the code at `addr` as "function (file:line)", or as much of that as is known.
+    fn format_frame(&self, addr: usize) -> String {
+        match (
+            self.debug_data.get_function_from_addr(addr),
+            self.debug_data.get_line_from_addr(addr),
+        ) {
+            (Some(func_name), Some(line)) => format!("{} ({})", func_name, line),
+            (Some(func_name), None) => format!("{:#x} in {}", addr, func_name),
+            _ => format!("{:#x}", addr),
+        }
+    }
+
+    /// Forgets the unwound stack, once the inferior has moved on or another thread is selected.
+    fn reset_frames(&mut self) {
+        self.frames.clear();
+        self.selected_frame = 0;
+        self.list_position = None;
+    }
+
+    /// Unwinds the current thread's stack, unless that was already done since it last stopped.
+    fn unwind_stack(&mut self) -> Result<(), nix::Error> {
+        if self.frames.is_empty() {
+            self.frames = self.inferior.as_mut().unwrap().backtrace(&self.debug_data)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the selected stack frame, which variables are looked up in.
+    fn current_frame(&mut self) -> Result<Frame, nix::Error> {
+        self.unwind_stack()?;
+        Ok(self.frames[self.selected_frame].clone())
+    }
+
+    /// Selects the frame at `level` (0 being the innermost) and describes it, or just describes
+    /// the selected frame if `level` is None.
+    fn select_frame(&mut self, level: Option<usize>) {
+        if let Err(err) = self.unwind_stack() {
+            println!("Error reading the stack: {}", err);
+            return;
+        }
+        if let Some(level) = level {
+            if level >= self.frames.len() {
+                println!("No frame at level {}.", level);
+                return;
+            }
+            self.selected_frame = level;
+            self.list_position = None;
+        }
+        let frame = &self.frames[self.selected_frame];
+        println!("#{:<2} {}", self.selected_frame, self.describe_frame(frame));
+    }
+
+    /// Selects the frame `offset` levels up (towards `main`) from the selected one, or down if
+    /// `offset` is negative, stopping at the innermost and outermost frames.
+    fn move_frame(&mut self, offset: isize) {
+        if let Err(err) = self.unwind_stack() {
+            println!("Error reading the stack: {}", err);
+            return;
+        }
+        let outermost = self.frames.len() - 1;
+        if offset > 0 && self.selected_frame == outermost {
+            println!("Initial frame selected; you cannot go up.");
+            return;
+        }
+        if offset < 0 && self.selected_frame == 0 {
+            println!("Bottom (innermost) frame selected; you cannot go down.");
+            return;
+        }
+        let level = (self.selected_frame as isize + offset).max(0).min(outermost as isize);
+        self.select_frame(Some(level as usize));
+    }
+
+    /// Describes an unwound frame for a backtrace, falling back to the ELF symbol for code without
+    /// debugging information, and to ?? for code without either.
+    fn describe_frame(&self, frame: &Frame) -> String {
+        let mut inlined_frames = self.debug_data.get_inlined_frames(frame.lookup_pc);
+        if frame.inline_depth < inlined_frames.len() {
+            if let (Some(func_name), line) = inlined_frames.swap_remove(frame.inline_depth) {
+                return match line {
+                    Some(line) => format!("{} ({})", func_name, line),
+                    None => format!("{:#x} in {}", frame.pc, func_name),
+                };
+            }
+        }
+        let name = frame.symbol.as_deref().unwrap_or("??");
+        match &frame.module {
+            Some(module) => format!("{} ({:#x} in {})", name, frame.pc, module),
+            None => format!("{} ({:#x})", name, frame.pc),
+        }
+    }
+
+    fn print_stop_location(&self, instruction_ptr: usize) {
+        if let Some(lineno) = DwarfData::get_line_from_addr(&self.debug_data, instruction_ptr) {
+            println!("Stopped at {}", lineno);
+            self.print_source_line(&lineno);
+        } else if let Some(func_name) = self.debug_data.get_function_from_addr(instruction_ptr) {
+            println!("Stopped at {:#x} in {}", instruction_ptr, func_name);
+        } else {
+            println!("Stopped at {:#x}", instruction_ptr);
+        }
+    }
+
+    /// Shows the source of a line the inferior stopped at, if the file can be found.
+    fn print_source_line(&self, line: &Line) {
+        let text = self
+            .source_path
+            .read(&line.file)
+            .and_then(|lines| lines.get(line.number.checked_sub(1)?).cloned());
+        if let Some(text) = text {
+            let breakpoint = self.breakpoint_lines(&line.file).contains(&line.number);
+            println!("{}", source::format_line(line.number, &text, true, breakpoint));
+        }
+    }
+
+    /// Lists source lines around `location`, or carries on from the last listing. The first
+    /// listing after the inferior stops is around the selected frame's line.
+    fn list(&mut self, location: Option<&str>) {
+        let position = match location {
+            Some(location) => self
+                .resolve_source_line(location)
+                .map(|(file, number)| (file, source::window_start(number))),
+            None => match self.list_position.take() {
+                Some(position) => Ok(position),
+                None => self
+                    .default_list_line()
+                    .map(|line| (line.file, source::window_start(line.number)))
+                    .ok_or_else(|| "No default source file.".to_string()),
+            },
+        };
+        let (file, first) = match position {
+            Ok(position) => position,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let lines = match self.source_path.read(&file) {
+            Some(lines) => lines,
+            None => {
+                println!("{}: No such file or directory.", file);
+                return;
+            }
+        };
+        if first > lines.len() {
+            println!("Line number {} out of range; \"{}\" has {} lines.", first, file, lines.len());
+            return;
+        }
+        let last = (first + source::LIST_SIZE - 1).min(lines.len());
+        let current = self
+            .selected_line()
+            .filter(|line| line.file == file)
+            .map(|line| line.number);
+        let breakpoint_lines = self.breakpoint_lines(&file);
+        for number in first..=last {
+            let breakpoint = breakpoint_lines.contains(&number);
+            println!("{}", source::format_line(number, &lines[number - 1], current == Some(number), breakpoint));
+        }
+        self.list_position = Some((file, last + 1));
+    }
+
+    /// Works out the file and line a `list` location refers to: `N` (in the file last listed
+    /// or stopped in), `file:N`, `function` or `file:function`.
+    fn resolve_source_line(&mut self, location: &str) -> Result<(String, usize), String> {
+        let (file, location) = match location.rfind(':') {
+            Some(colon) => {
+                let file = &location[..colon];
+                let files = self.debug_data.get_matching_files(file);
+                match files.len() {
+                    0 => return Err(format!("No source file named {}.", file)),
+                    1 => (Some(files[0].to_string()), &location[colon + 1..]),
+                    _ => return Err(format!("Ambiguous file name \"{}\"; use a longer path to pick one.", file)),
+                }
+            }
+            None => (None, location),
+        };
+        if let Ok(number) = location.parse() {
+            let file = match file {
+                Some(file) => file,
+                None => match &self.list_position {
+                    Some((file, _)) => file.clone(),
+                    None => self.default_list_line().ok_or("No default source file.")?.file,
+                },
+            };
+            return Ok((file, number));
+        }
+        let line = self
+            .debug_data
+            .get_addr_for_function(file.as_deref(), location)
+            .and_then(|addr| self.debug_data.get_line_from_addr(addr));
+        match line {
+            Some(line) => Ok((line.file, line.number)),
+            None => Err(format!("Function \"{}\" not defined.", location)),
+        }
+    }
+
+    /// Where `list` starts out: the selected frame's line, or else main.
+    fn default_list_line(&mut self) -> Option<Line> {
+        self.selected_line().or_else(|| {
+            let addr = self.debug_data.get_addr_for_function(None, "main")?;
+            self.debug_data.get_line_from_addr(addr)
+        })
+    }
+
+    /// The line the selected frame is executing, if the inferior is running.
+    fn selected_line(&mut self) -> Option<Line> {
+        self.inferior.as_ref()?;
+        let frame = self.current_frame().ok()?;
+        self.debug_data
+            .get_inlined_frames(frame.lookup_pc)
+            .into_iter()
+            .nth(frame.inline_depth)?
+            .1
+    }
+
+    /// The lines of `file` that have breakpoints set on them.
+    fn breakpoint_lines(&self, file: &str) -> Vec<usize> {
+        self.breakpoints
+            .values()
+            .filter(|bp| bp.id > 0)
+            .filter_map(|bp| self.debug_data.get_line_from_addr(bp.addr))
+            .filter(|line| line.file == file)
+            .map(|line| line.number)
+            .collect()
+    }
+
+    /// Switches to the full-screen front-end with the given panes, or just changes the panes if
+    /// it's on already.
+    pub fn enable_tui(&mut self, layout: Layout) {
+        if let Some(tui) = &mut self.tui {
+            tui.layout = layout;
+            return;
+        }
+        match Tui::open(layout) {
+            Ok(tui) => self.tui = Some(tui),
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    /// Redraws the TUI's panes, if it's enabled, for the selected frame.
+    fn refresh_tui(&mut self) {
+        let layout = match &self.tui {
+            Some(tui) => tui.layout,
+            None => return,
+        };
+        let mut panes = Vec::new();
+        for kind in layout.panes() {
+            panes.push(match kind {
+                PaneKind::Source => self.source_pane(),
+                PaneKind::Assembly => self.assembly_pane(),
+                PaneKind::Registers => self.registers_pane(),
+            });
+        }
+        let status = self.tui_status();
+        self.tui.as_ref().unwrap().draw(&panes, &status);
+    }
+
+    /// The file the selected frame is in, or else the one main is in, highlighting the current
+    /// line and marking breakpoints as `list` does.
+    fn source_pane(&mut self) -> Pane {
+        let current = self.selected_line().is_some();
+        let line = match self.default_list_line() {
+            Some(line) => line,
+            None => return Pane::new("[No Source Available]".to_string(), Vec::new()),
+        };
+        let lines = match self.source_path.read(&line.file) {
+            Some(lines) => lines,
+            None => return Pane::new(format!("{} (not found)", line.file), Vec::new()),
+        };
+        let breakpoint_lines = self.breakpoint_lines(&line.file);
+        let lines = lines
+            .iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let number = i + 1;
+                source::format_line(number, text, current && number == line.number, breakpoint_lines.contains(&number))
+            })
+            .collect();
+        Pane {
+            focus: Some(line.number - 1),
+            highlight: current,
+            ..Pane::new(line.file, lines)
+        }
+    }
+
+    /// The instructions around the selected frame's pc.
+    fn assembly_pane(&mut self) -> Pane {
+        if self.inferior.is_none() {
+            return Pane::new("Disassembly".to_string(), Vec::new());
+        }
+        let frame = match self.current_frame() {
+            Ok(frame) => frame,
+            Err(err) => return Pane::new(format!("Disassembly (error reading the stack: {})", err), Vec::new()),
+        };
+        let instructions = match self.instructions_around(frame.pc, 50, 50) {
+            Ok(instructions) => instructions,
+            Err(err) => return Pane::new(format!("Disassembly ({})", err), Vec::new()),
+        };
+        Pane {
+            focus: instructions.iter().position(|instruction| instruction.address == frame.pc),
+            highlight: true,
+            ..Pane::new(
+                "Disassembly".to_string(),
+                instructions
+                    .iter()
+                    .map(|instruction| self.format_instruction(instruction, frame.pc))
+                    .collect(),
+            )
+        }
+    }
+
+    /// The current thread's general purpose registers, in hex.
+    fn registers_pane(&self) -> Pane {
+        let regs = match self.inferior.as_ref().map(|inferior| inferior.get_registers()) {
+            Some(Ok(regs)) => regs,
+            _ => return Pane::new("Registers".to_string(), Vec::new()),
+        };
+        let entries = registers::GENERAL_REGISTERS
+            .iter()
+            .filter_map(|name| Some(format!("{:<7}{:#x}", name, registers::get_register(&regs, name)?)))
+            .collect();
+        Pane {
+            tiled: true,
+            ..Pane::new("Registers".to_string(), entries)
+        }
+    }
+
+    /// The line under the panes, saying which process and where it's stopped.
+    fn tui_status(&mut self) -> String {
+        let pid = match &self.inferior {
+            Some(inferior) => inferior.pid(),
+            None => return "No process".to_string(),
+        };
+        let frame = match self.current_frame() {
+            Ok(frame) => frame,
+            Err(_) => return format!("process {}", pid),
+        };
+        let func_name = self
+            .debug_data
+            .get_function_from_addr(frame.lookup_pc)
+            .or_else(|| frame.symbol.clone())
+            .unwrap_or_else(|| "??".to_string());
+        let line = match self.selected_line() {
+            Some(line) => format!("L{}", line.number),
+            None => "L??".to_string(),
+        };
+        format!("process {}  In: {}  {}  PC: {:#x}", pid, func_name, line, frame.pc)
+    }
+
+    /// Runs until the current function returns, then prints its return value.
+    fn finish(&mut self) {
+        let inferior = self.inferior.as_mut().unwrap();
+        let rip = match inferior.get_registers() {
+            Ok(regs) => regs.rip as usize,
+            Err(err) => {
+                println!("Could not read registers: {}", err);
+                return;
+            }
+        };
+        let func = match self.debug_data.get_function_at_addr(rip) {
+            Some(func) => func.clone(),
+            None => {
+                println!("\"finish\" needs a function with debugging information");
+                return;
+            }
+        };
+        let (ret_addr, ret_addr_loc) = match inferior.get_return_address() {
+            Ok(Some(val)) => val,
+            Ok(None) => {
+                println!("\"finish\" not meaningful in the outermost frame.");
+                return;
+            }
+            Err(err) => {
+                println!("Could not find the return address: {}", err);
+                return;
+            }
+        };
+        println!("Run till exit from {}", func.name);
+        let status = inferior.run_until(ret_addr, ret_addr_loc, &self.debug_data, &self.values, &mut self.breakpoints);
+        // the run may carry on after a fork, so where it ended up is only known afterwards
+        self.handle_status(status, true);
+        // Stopping anywhere else means we hit a breakpoint on the way
+        let returned = match self.inferior.as_ref().map(|inferior| inferior.get_registers()) {
+            Some(Ok(regs)) => regs.rip as usize == ret_addr,
+            _ => false,
+        };
+        if !returned {
+            return;
+        }
+
+        // structs are returned in ways that depend on their size and members, so leave them out
+        if let Some(return_type) = func.return_type.filter(|return_type| return_type.is_scalar()) {
+            let inferior = self.inferior.as_ref().unwrap();
+            // Integers and pointers are returned in %rax, floats and doubles in %xmm0, and long
+            // doubles in %st(0)
+            let bytes = match return_type.encoding {
+                TypeEncoding::Float if return_type.size > 8 => {
+                    inferior.get_fp_registers().map(|fpregs| words_to_bytes(&fpregs.st_space[..4]))
+                }
+                TypeEncoding::Float => {
+                    inferior.get_fp_registers().map(|fpregs| words_to_bytes(&fpregs.xmm_space[..4]))
+                }
+                _ => inferior.get_registers().map(|regs| regs.rax.to_le_bytes().to_vec()),
+            };
+            match bytes {
+                Ok(mut bytes) => {
+                    print!("Value returned is ");
+                    bytes.truncate(return_type.size);
+                    self.record_value(Value {
+                        entity_type: return_type,
+                        bytes,
+                        address: None,
+                    });
+                }
+                Err(err) => println!("Could not read return value: {}", err),
+            }
+        }
+    }
+
+    /// Prints the instructions around %rip: up to `before` instructions leading up to it (if the
+    /// start of the enclosing function is known, since x86 can't be decoded backwards) and
+    /// `after` instructions starting at it.
+    fn disassemble(&self, before: usize, after: usize) {
+        let rip = match self.inferior.as_ref().unwrap().get_registers() {
+            Ok(regs) => regs.rip as usize,
+            Err(err) => {
+                println!("Could not read registers: {}", err);
+                return;
+            }
+        };
+        match self.instructions_around(rip, before, after) {
+            Ok(instructions) => self.print_instructions(&instructions, rip),
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    /// Decodes up to `before` instructions of the function containing `rip` before it, and
+    /// `after` from it on.
+    fn instructions_around(&self, rip: usize, before: usize, after: usize) -> Result<Vec<Instruction>, String> {
+        let func = self.debug_data.get_function_at_addr(rip);
+        let start = match func {
+            Some(func) if before > 0 => func.address,
+            _ => rip,
+        };
+        // x86-64 instructions are at most 15 bytes long, but don't run past the end of the function
+        let mut len = rip - start + after * 15;
+        if let Some(func) = func {
+            len = len.min(func.address + func.text_length - start);
+        }
+        let bytes = self
+            .read_code(start, len)
+            .map_err(|err| format!("Cannot access memory at address {:#x}: {}", start, err))?;
+
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+        let mut num_after = 0;
+        while offset < bytes.len() && num_after < after {
+            let instruction = disassembler::decode(&bytes[offset..], start + offset);
+            if instruction.address >= rip {
+                num_after += 1;
+            }
+            offset += instruction.length;
+            instructions.push(instruction);
+        }
+        let first_shown = instructions
+            .iter()
+            .position(|i| i.address >= rip)
+            .unwrap_or(0)
+            .saturating_sub(before);
+        Ok(instructions.split_off(first_shown))
+    }
+
+    /// Reads machine code from the inferior, showing the original instructions rather than our
+    /// breakpoints.
+    fn read_code(&self, start: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = self.inferior.as_ref().unwrap().read_memory(start, len)?;
+        for bp in self.breakpoints.values() {
+            if bp.addr >= start && bp.addr < start + bytes.len() && bytes[bp.addr - start] == 0xcc {
+                bytes[bp.addr - start] = bp.orig_byte;
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Prints disassembled instructions, marking the one at `rip`.
+    fn print_instructions(&self, instructions: &[Instruction], rip: usize) {
+        for instruction in instructions {
+            println!("{}", self.format_instruction(instruction, rip));
+        }
+    }
+
+    /// Formats a disassembled instruction with the functions its addresses are in, marking it if
+    /// it's the one at `rip`.
+    fn format_instruction(&self, instruction: &Instruction, rip: usize) -> String {
+        let marker = if instruction.address == rip { "=>" } else { "  " };
+        let mut line = format!(
+            "{} {:#x}{}:\t{}",
+            marker,
+            instruction.address,
+            self.format_symbol(instruction.address),
+            instruction
+        );
+        if let Some(target) = instruction.target {
+            line.push_str(&self.format_symbol(target));
+        }
+        if let Some(addr) = instruction.rip_relative_addr {
+            line.push_str(&format!("        # {:#x}{}", addr, self.format_symbol(addr)));
+        }
+        line
+    }
+
+    /// Shows the inferior's memory as `x/NFU address` does, defaulting to the format of the last
+    /// `x` and to where it left off.
+    fn examine(&mut self, spec: ExamineSpec, address: Option<&str>) {
+        let addr = match address {
+            Some(address) => match self.resolve_address(address) {
+                Ok(addr) => addr,
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            },
+            None => match self.next_examine_addr {
+                Some(addr) => addr,
+                None => {
+                    println!("Argument required (starting display address).");
+                    return;
+                }
+            },
+        };
+        let format = spec.format.unwrap_or(self.examine_format);
+        let unit = match (spec.unit, format) {
+            (Some(unit), _) => unit,
+            // characters are bytes unless asked otherwise
+            (None, Format::Char) => 1,
+            (None, _) => self.examine_unit,
+        };
+        let count = spec.count.unwrap_or(1);
+        self.examine_format = format;
+        if format != Format::Char {
+            self.examine_unit = unit;
+        }
+        let next_addr = match format {
+            Format::String => self.examine_strings(addr, count),
+            Format::Instruction => self.examine_instructions(addr, count),
+            _ => self.examine_units(addr, count, format, unit),
+        };
+        self.next_examine_addr = Some(next_addr);
+    }
+
+    /// Prints `count` units of memory starting at `addr`, and returns the address after them.
+    fn examine_units(&self, addr: usize, count: usize, format: Format, unit: usize) -> usize {
+        let inferior = self.inferior.as_ref().unwrap();
+        let per_line = examine::units_per_line(format, unit);
+        let mut line_addr = addr;
+        for line_start in (0..count).step_by(per_line) {
+            let units = per_line.min(count - line_start);
+            let bytes = match inferior.read_memory(line_addr, units * unit) {
+                Ok(bytes) => bytes,
+                Err(_) => {
+                    println!("Cannot access memory at address {:#x}", line_addr);
+                    return line_addr;
+                }
+            };
+            let values: Vec<String> = bytes.chunks_exact(unit).map(|val| examine::format_unit(val, format)).collect();
+            println!("{:#x}{}:\t{}", line_addr, self.format_symbol(line_addr), values.join("\t"));
+            line_addr += units * unit;
+        }
+        line_addr
+    }
+
+    /// Prints `count` NUL-terminated strings starting at `addr`, and returns the address after them.
+    fn examine_strings(&self, mut addr: usize, count: usize) -> usize {
+        let inferior = self.inferior.as_ref().unwrap();
+        for _ in 0..count {
+            let (bytes, terminated) = match inferior.read_string(addr, examine::MAX_STRING_LEN) {
+                Ok(string) => string,
+                Err(_) => {
+                    println!("Cannot access memory at address {:#x}", addr);
+                    return addr;
+                }
+            };
+            let ellipsis = if terminated { "" } else { "..." };
+            println!("{:#x}:\t{}{}", addr, examine::format_string(&bytes), ellipsis);
+            addr += bytes.len() + terminated as usize;
+        }
+        addr
+    }
+
+    /// Disassembles `count` instructions starting at `addr`, and returns the address after them.
+    fn examine_instructions(&self, addr: usize, count: usize) -> usize {
+        // x86-64 instructions are at most 15 bytes long; read less if that runs into unmapped memory
+        let bytes = match (0..=count)
+            .rev()
+            .find_map(|len| self.read_code(addr, (len * 15).max(15)).ok())
+        {
+            Some(bytes) => bytes,
+            None => {
+                println!("Cannot access memory at address {:#x}", addr);
+                return addr;
+            }
+        };
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() && instructions.len() < count {
+            let instruction = disassembler::decode(&bytes[offset..], addr + offset);
+            offset += instruction.length;
+            instructions.push(instruction);
+        }
+        let rip = self.inferior.as_ref().unwrap().get_registers().map(|regs| regs.rip as usize).unwrap_or(0);
+        self.print_instructions(&instructions, rip);
+        addr + offset
+    }
+
+    /// Works out the address given to a command like `x`, which can be any expression: a number,
+    /// a register (`$rsp`), the address of a variable (`&buf`), a pointer (`p + 1`) and so on.
+    /// Arrays and structs stand for their own address, as they would in C.
+    fn resolve_address(&mut self, address: &str) -> Result<usize, String> {
+        self.evaluate(address)?.as_address()
+    }
+
+    /// Formats an address as ` <function+offset>`, or an empty string if it isn't in a known
+    /// function.
+    fn format_symbol(&self, addr: usize) -> String {
+        match self.debug_data.get_function_from_addr(addr) {
+            Some(func_name) => match self.debug_data.get_function_at_addr(addr) {
+                Some(func) if func.address != addr => {
+                    format!(" <{}+{}>", func_name, addr - func.address)
+                }
+                _ => format!(" <{}>", func_name),
+            },
+            None => String::new(),
+        }
+    }
+
+    /// Prints the given general purpose registers, or all of them if `names` is empty, as
+    /// `info registers` does: in hex and in their natural format.
+    fn print_registers(&self, names: &[String]) {
+        let regs = match self.inferior.as_ref().unwrap().get_registers() {
+            Ok(regs) => regs,
+            Err(err) => {
+                println!("Could not read registers: {}", err);
+                return;
+            }
+        };
+        let names: Vec<&str> = if names.is_empty() {
+            registers::GENERAL_REGISTERS.to_vec()
+        } else {
+            names.iter().map(String::as_str).collect()
+        };
+        for name in names {
+            let val = match registers::get_register(&regs, name) {
+                Some(val) => val,
+                None => {
+                    println!("Invalid register `{}'", name);
+                    continue;
+                }
+            };
+            let natural = match name {
+                "rip" | "pc" => format!("{:#x}{}", val, self.format_symbol(val as usize)),
+                "rsp" | "sp" | "rbp" | "fp" => format!("{:#x}", val),
+                "eflags" => registers::format_eflags(val),
+                _ => format!("{}", val as i64),
+            };
+            println!("{:<15}{:<19}{}", name, format!("{:#x}", val), natural);
+        }
+    }
+
+    /// Prints the x87 and SSE registers, for `info all-registers`.
+    fn print_fp_registers(&self) {
+        let fpregs = match self.inferior.as_ref().unwrap().get_fp_registers() {
+            Ok(fpregs) => fpregs,
+            Err(err) => {
+                println!("Could not read floating point registers: {}", err);
+                return;
+            }
+        };
+        let long_double = Type::new("long double".to_string(), 16, TypeEncoding::Float);
+        for (i, words) in fpregs.st_space.chunks_exact(4).enumerate() {
+            let bytes = words_to_bytes(words);
+            let raw: String = bytes[..10].iter().rev().map(|byte| format!("{:02x}", byte)).collect();
+            println!("{:<15}{:<19}(raw 0x{})", format!("st{}", i), long_double.format_value(&bytes), raw);
+        }
+        let control = [
+            ("fctrl", fpregs.cwd as u64),
+            ("fstat", fpregs.swd as u64),
+            ("ftag", fpregs.ftw as u64),
+            ("fop", fpregs.fop as u64),
+            ("fioff", fpregs.rip),
+            ("fooff", fpregs.rdp),
+            ("mxcsr", fpregs.mxcsr as u64),
+        ];
+        for (name, val) in control.iter() {
+            println!("{:<15}{:<19}{}", name, format!("{:#x}", val), val);
+        }
+        for (i, words) in fpregs.xmm_space.chunks_exact(4).enumerate() {
+            let bytes = words_to_bytes(words);
+            let floats: Vec<String> = words
+                .iter()
+                .map(|word| registers::format_float(f32::from_bits(*word)))
+                .collect();
+            let doubles: Vec<String> = bytes
+                .chunks_exact(8)
+                .map(|half| registers::format_float(f64::from_bits(u64::from_le_bytes(half.try_into().unwrap()))))
+                .collect();
+            let uint128 = u128::from_le_bytes(bytes[..].try_into().unwrap());
+            println!(
+                "{:<15}{{v4_float = {{{}}}, v2_double = {{{}}}, uint128 = {:#x}}}",
+                format!("xmm{}", i),
+                floats.join(", "),
+                doubles.join(", "),
+                uint128
+            );
+        }
+    }
+
+    /// Changes a general purpose register of the current thread (`set $rax = 5`).
+    fn set_register(&mut self, name: &str, val: i64) {
+        let inferior = self.inferior.as_ref().unwrap();
+        let mut regs = match inferior.get_registers() {
+            Ok(regs) => regs,
+            Err(err) => {
+                println!("Could not read registers: {}", err);
+                return;
+            }
+        };
+        if !registers::set_register(&mut regs, name, val as u64) {
+            println!("Invalid register `{}'", name);
+            return;
+        }
+        if let Err(err) = inferior.set_registers(regs) {
+            println!("Could not write register {}: {}", name, err);
+        }
+        // the stack has to be unwound again from the new values
+        self.reset_frames();
+    }
+
+    /// Assigns the value of an expression to a variable or anything else in memory, like
+    /// `set var arr[1] = 3` or `set {int}0x404018 = 5`.
+    fn set_variable(&mut self, target: &str, value: &str) {
+        let (target, value) = match self.evaluate(target).and_then(|target| Ok((target, self.evaluate(value)?))) {
+            Ok(values) => values,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let addr = match target.address {
+            Some(addr) => addr,
+            None => {
+                println!("Left operand of assignment is not an lvalue.");
+                return;
+            }
+        };
+        let bytes = match value.encode(&target.entity_type) {
+            Some(bytes) => bytes,
+            None => {
+                println!("Invalid cast to {}.", target.entity_type.name);
+                return;
+            }
+        };
+        let inferior = self.inferior.as_mut().unwrap();
+        if let Err(err) = inferior.write_memory(addr, &bytes) {
+            println!("Cannot access memory at address {:#x}: {}", addr, err);
+            return;
+        }
+        // the debug registers don't see our writes, so don't report them as changes later
+        for wp in &mut self.watchpoints {
+            if wp.addr < addr + bytes.len() && addr < wp.addr + wp.entity_type.size {
+                wp.old_value = inferior.read_memory(wp.addr, wp.entity_type.size).unwrap_or_default();
+            }
+        }
+    }
+
+    /// Evaluates an expression in the selected stack frame.
+    fn evaluate(&mut self, expression: &str) -> Result<Value, String> {
+        let expression = Expression::parse(expression)?;
+        let frame = self.current_frame().map_err(|err| format!("Error reading the stack: {}", err))?;
+        let ctx = Context {
+            inferior: self.inferior.as_ref().unwrap(),
+            debug_data: &self.debug_data,
+            frame: &frame,
+            history: &self.values,
+        };
+        expression.evaluate(&ctx)
+    }
+
+    /// Adds a value to the value history and prints it as `$n = value`.
+    fn record_value(&mut self, value: Value) {
+        // like gdb, say what pointers point to, unless it's obvious from the string
+        let prefix = if value.entity_type.encoding == TypeEncoding::Pointer && !value.entity_type.is_string() {
+            format!("({}) ", value.entity_type.name)
+        } else {
+            String::new()
+        };
+        let formatted = self.format_value(&value.entity_type, &value.bytes);
+        println!("${} = {}{}", self.values.len() + 1, prefix, formatted);
+        self.values.push(value);
+    }
+
+    /// Formats a value read from the inferior, including the strings that `char *`s point to.
+    fn format_value(&self, entity_type: &Type, bytes: &[u8]) -> String {
+        let inferior = self.inferior.as_ref();
+        entity_type.format_value_with(bytes, &|addr| {
+            let (bytes, terminated) = inferior?.read_string(addr, examine::MAX_STRING_LEN).ok()?;
+            let ellipsis = if terminated { "" } else { "..." };
+            Some(format!("{}{}", examine::format_string(&bytes), ellipsis))
+        })
+    }
+
+    /// Prints the value of an expression, evaluated in the selected stack frame.
+    fn print_expression(&mut self, expression: &str) {
+        match self.evaluate(expression) {
+            Ok(value) => self.record_value(value),
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    /// Prints the name, type and value of every local variable (or, if `print_args` is set, every
+    /// parameter) of the function in the selected stack frame.
+    fn print_frame_variables(&mut self, print_args: bool) {
+        let frame = match self.current_frame() {
+            Ok(frame) => frame,
+            Err(err) => {
+                println!("Error reading the stack: {}", err);
+                return;
+            }
+        };
+        let inferior = self.inferior.as_ref().unwrap();
+        let (parameters, variables) = match self.debug_data.get_frame_variables(frame.lookup_pc, frame.inline_depth) {
+            Some(vars) => vars,
+            None => {
+                println!("No symbol table info available.");
+                return;
+            }
+        };
+        let vars = if print_args { parameters } else { variables };
+        if vars.is_empty() {
+            println!("{}", if print_args { "No arguments." } else { "No locals." });
+        }
+        for var in vars {
+            let value = inferior
+                .locate_variable(var, &frame, &self.debug_data)
+                .and_then(|place| match place {
+                    Place::OptimizedOut => Ok("<optimized out>".to_string()),
+                    place => inferior
+                        .read_place(&place, var.entity_type.size)
+                        .map(|bytes| self.format_value(&var.entity_type, &bytes)),
+                });
+            match value {
+                Ok(value) => println!("{} {} = {}", var.entity_type.name, var.name, value),
+                Err(err) => println!("{} {} = <error: {}>", var.entity_type.name, var.name, err),
+            }
+        }
+    }
+
+    /// Runs the commands in a file, before reading any more from the prompt.
+    pub fn source(&mut self, path: &str) {
+        match Script::from_file(path) {
+            Ok(script) => self.scripts.push(script),
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    /// Reads the commands to run whenever breakpoint `id` (or else the last one set) stops the
+    /// inferior, up to a line saying `end`. An empty list removes its commands.
+    fn define_commands(&mut self, id: Option<usize>) {
+        let id = id.unwrap_or(self.next_breakpoint_id - 1);
+        let addr = match id {
+            0 => None,
+            id => self.find_breakpoint_addr(id),
+        };
+        if addr.is_some() && self.scripts.is_empty() && !self.batch {
+            println!("Type commands for breakpoint {}, one per line.", id);
+            println!("End with a line saying just \"end\".");
+        }
+        // the commands are read even if there's no such breakpoint, so that they aren't run now
+        let mut commands = Vec::new();
+        while let Some(line) = self.next_line(">") {
+            let line = line.trim();
+            if line == "end" {
+                break;
+            }
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if DebuggerCommand::from_tokens(&tokens).is_none() {
+                println!("Unrecognized command: {}", line);
+                continue;
+            }
+            commands.push(line.to_string());
+        }
+        match addr {
+            Some(addr) => self.breakpoints.get_mut(&addr).unwrap().commands = commands,
+            None if id == 0 => println!("No breakpoints specified."),
+            None => println!("No breakpoint number {}.", id),
+        }
+    }
+
+    /// This function prompts the user to enter a command, and continues re-prompting until the user
+    /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
+    fn get_next_command(&mut self) -> DebuggerCommand {
+        loop {
+            let line = match self.next_line("(deet) ") {
+                Some(line) => line,
+                None => return DebuggerCommand::Quit,
+            };
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
+                return cmd;
+            }
+            match self.scripts.pop() {
+                // like gdb, give up on a command file at its first bad line
+                Some(script) => println!("{}: Unrecognized command.", script.location()),
+                None => println!("Unrecognized command."),
+            }
+        }
+    }
+
+    /// Reads the next line of commands: from the innermost script being run, or else from the
+    /// user. Returns None at the end of input, which is ctrl+d, or the end of the scripts in batch
+    /// mode.
+    fn next_line(&mut self, prompt: &str) -> Option<String> {
+        while let Some(script) = self.scripts.last_mut() {
+            match script.next_line() {
+                Some(line) => return Some(line),
+                None => {
+                    self.scripts.pop();
+                }
+            }
+        }
+        if self.batch {
+            return None;
+        }
+        loop {
+            // Print prompt and get next line of user input
+            match self.readline.readline(prompt) {
+                Err(ReadlineError::Interrupted) => {
+                    // User pressed ctrl+c. We're going to ignore it
+                    println!("Type \"quit\" to exit");
+                }
+                Err(ReadlineError::Eof) => {
+                    // User pressed ctrl+d, which is the equivalent of "quit" for our purposes
+                    return None;
+                }
+                Err(err) => {
+                    panic!("Unexpected I/O error: {:?}", err);
+                }
+                Ok(line) => {
+                    if line.trim().len() == 0 {
+                        continue;
+                    }
+                    self.readline.add_history_entry(line.as_str());
+                    if let Err(err) = self.readline.save_history(&self.history_path) {
+                        println!(
+                            "Warning: failed to save history file at {}: {}",
+                            self.history_path, err
+                        );
+                    }
+                    return Some(line);
+                }
+            }
+        }
+    }
+}
+
+/// Loads the debugging symbols of `target`, printing what went wrong if that fails.
+fn load_debug_data(target: &str) -> Option<DwarfData> {
+    match DwarfData::from_file(target) {
+        Ok(val) => Some(val),
+        Err(DwarfError::ErrorOpeningFile) => {
+            println!("Could not open file {}", target);
+            None
+        }
+        Err(DwarfError::DwarfFormatError(err)) => {
+            println!("Could not debugging symbols from {}: {:?}", target, err);
+            None
+        }
+    }
+}
+
+fn print_watchpoint_out_of_scope(id: usize) {
+    println!(
+        "Watchpoint {} deleted because the program has left the block in\nwhich its expression is valid.",
+        id
+    );
+}
+
+fn words_to_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect()
 }
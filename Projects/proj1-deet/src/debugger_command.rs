@@ -1,9 +1,69 @@
+use crate::debugger::{FollowForkMode, WatchKind};
+use crate::examine::ExamineSpec;
+use crate::signals::{self, HandleAction};
+use crate::tui::Layout;
+use nix::sys::signal::Signal;
+
 pub enum DebuggerCommand {
     Quit,
     Run(Vec<String>),
     Continue,  
     Backtrace,
-    Breakpoint(String), 
+    /// Select the frame this many levels further from (up) or closer to (down) the innermost one
+    Up(usize),
+    Down(usize),
+    /// Select the given frame, or describe the selected one
+    Frame(Option<usize>),
+    /// Location and optional condition (`break foo if i == 42`)
+    Breakpoint(String, Option<String>),
+    Print(String),
+    InfoLocals,
+    InfoArgs,
+    /// Show the given registers, or all the general purpose ones
+    InfoRegisters(Vec<String>),
+    InfoAllRegisters,
+    /// `set $reg = value`
+    SetRegister(String, i64),
+    /// `set var name = value` or `set {type}address = value`
+    SetVariable(String, String),
+    /// `x/NFU address`; without an address, carries on after the last examined memory
+    Examine(ExamineSpec, Option<String>),
+    Step,
+    Next,
+    Finish,
+    StepInstruction,
+    NextInstruction,
+    Disassemble(Option<usize>),
+    /// `list`, `list N`, `list function` or `list file:N`
+    List(Option<String>),
+    /// Add directories to look for source files in, or forget them all if there are none
+    Directory(Vec<String>),
+    /// Run the commands in a file
+    Source(String),
+    /// Set the commands run when a breakpoint (or else the last one set) is hit, read up to `end`
+    Commands(Option<usize>),
+    /// `tui enable` or `tui disable`
+    Tui(bool),
+    /// Enable the TUI with the given panes
+    Layout(Layout),
+    InfoBreakpoints,
+    Delete(Option<usize>),
+    Disable(Option<usize>),
+    Enable(Option<usize>),
+    Ignore(usize, usize),
+    Watch(String, WatchKind),
+    Attach(i32),
+    Detach,
+    InfoThreads,
+    Thread(usize),
+    SetFollowForkMode(FollowForkMode),
+    SetDetachOnFork(bool),
+    InfoInferiors,
+    Inferior(usize),
+    Handle(Signal, Vec<HandleAction>),
+    InfoSignals(Option<Signal>),
+    /// Continue, delivering the given signal instead of the one the inferior stopped with
+    Signal(Option<Signal>),
 }
 
 impl DebuggerCommand {
@@ -16,6 +76,41 @@ impl DebuggerCommand {
         usize::from_str_radix(addr_without_0x, 16).ok()
     }
 
+    /// Parses a decimal or 0x-prefixed hexadecimal integer, which may be negative.
+    pub fn parse_integer(text: &str) -> Option<i64> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text),
+        };
+        let val = if digits.to_lowercase().starts_with("0x") {
+            u64::from_str_radix(&digits[2..], 16).ok()? as i64
+        } else {
+            digits.parse().ok()?
+        };
+        Some(if negative { val.wrapping_neg() } else { val })
+    }
+
+    /// Splits `target = value` into a SetVariable command.
+    fn parse_assignment(tokens: &[&str]) -> Option<DebuggerCommand> {
+        let assignment = tokens.join(" ");
+        let mut sides = assignment.splitn(2, '=');
+        let target = sides.next()?.trim().to_string();
+        let value = sides.next()?.trim().to_string();
+        if target.is_empty() || value.is_empty() {
+            return None;
+        }
+        Some(DebuggerCommand::SetVariable(target, value))
+    }
+
+    /// Parses the optional breakpoint number following delete/disable/enable. Returns None if it
+    /// is not a number.
+    fn parse_breakpoint_id(tokens: &[&str]) -> Option<Option<usize>> {
+        match tokens.get(1) {
+            Some(id) => Some(Some(id.parse().ok()?)),
+            None => Some(None),
+        }
+    }
+
     pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
         match tokens[0] {
             "q" | "quit" => Some(DebuggerCommand::Quit),
@@ -27,7 +122,126 @@ impl DebuggerCommand {
             }
             "c" | "cont" | "continue" => Some(DebuggerCommand::Continue),
             "bt" | "back" | "backtrace" => Some(DebuggerCommand::Backtrace),
-            "b" | "break" => Some(DebuggerCommand::Breakpoint(tokens[1].to_string())),
+            "up" | "down" => {
+                let count = match tokens.get(1) {
+                    Some(count) => count.parse().ok()?,
+                    None => 1,
+                };
+                if tokens[0] == "up" {
+                    Some(DebuggerCommand::Up(count))
+                } else {
+                    Some(DebuggerCommand::Down(count))
+                }
+            }
+            "f" | "frame" => match tokens.get(1) {
+                Some(level) => Some(DebuggerCommand::Frame(Some(level.parse().ok()?))),
+                None => Some(DebuggerCommand::Frame(None)),
+            },
+            "b" | "break" => {
+                let location = tokens.get(1)?.to_string();
+                let condition = match tokens.get(2) {
+                    Some(&"if") => Some(tokens[3..].join(" ")),
+                    Some(_) => return None,
+                    None => None,
+                };
+                Some(DebuggerCommand::Breakpoint(location, condition))
+            }
+            "p" | "print" => Some(DebuggerCommand::Print(tokens[1..].join(" "))),
+            "s" | "step" => Some(DebuggerCommand::Step),
+            "n" | "next" => Some(DebuggerCommand::Next),
+            "fin" | "finish" => Some(DebuggerCommand::Finish),
+            "si" | "stepi" => Some(DebuggerCommand::StepInstruction),
+            "ni" | "nexti" => Some(DebuggerCommand::NextInstruction),
+            "disas" | "disassemble" => match tokens.get(1) {
+                Some(count) => Some(DebuggerCommand::Disassemble(Some(count.parse().ok()?))),
+                None => Some(DebuggerCommand::Disassemble(None)),
+            },
+            "l" | "list" => Some(DebuggerCommand::List(tokens.get(1).map(|location| location.to_string()))),
+            "dir" | "directory" => Some(DebuggerCommand::Directory(
+                tokens[1..].iter().map(|dir| dir.to_string()).collect(),
+            )),
+            "source" => Some(DebuggerCommand::Source(tokens.get(1)?.to_string())),
+            "commands" => Some(DebuggerCommand::Commands(Self::parse_breakpoint_id(tokens)?)),
+            "tui" => match tokens.get(1) {
+                Some(&"enable") => Some(DebuggerCommand::Tui(true)),
+                Some(&"disable") => Some(DebuggerCommand::Tui(false)),
+                _ => None,
+            },
+            "layout" => Some(DebuggerCommand::Layout(Layout::from_name(tokens.get(1)?)?)),
+            "watch" | "rwatch" | "awatch" => {
+                let kind = match tokens[0] {
+                    "watch" => WatchKind::Write,
+                    "rwatch" => WatchKind::Read,
+                    _ => WatchKind::Access,
+                };
+                tokens.get(1)?;
+                Some(DebuggerCommand::Watch(tokens[1..].join(" "), kind))
+            }
+            command if command == "x" || command.starts_with("x/") => {
+                // the format may also be a separate word: `x /4gx $rsp`
+                let (spec, rest) = match tokens.get(1) {
+                    Some(spec) if command == "x" && spec.starts_with('/') => (*spec, &tokens[2..]),
+                    _ => (&command[1..], &tokens[1..]),
+                };
+                let spec = ExamineSpec::parse(spec.trim_start_matches('/'))?;
+                let address = if rest.is_empty() { None } else { Some(rest.join(" ")) };
+                Some(DebuggerCommand::Examine(spec, address))
+            }
+            "attach" => Some(DebuggerCommand::Attach(tokens.get(1)?.parse().ok()?)),
+            "detach" => Some(DebuggerCommand::Detach),
+            "handle" => Some(DebuggerCommand::Handle(
+                signals::parse_signal(tokens.get(1)?)?,
+                tokens[2..].iter().map(|action| HandleAction::parse(action)).collect::<Option<_>>()?,
+            )),
+            "signal" => match *tokens.get(1)? {
+                "0" => Some(DebuggerCommand::Signal(None)),
+                name => Some(DebuggerCommand::Signal(Some(signals::parse_signal(name)?))),
+            },
+            "inferior" => Some(DebuggerCommand::Inferior(tokens.get(1)?.parse().ok()?)),
+            "set" if matches!(tokens.get(1), Some(token) if token.starts_with('$')) => {
+                // allow both `set $rax = 5` and `set $rax=5`
+                let assignment = tokens[1..].join(" ");
+                let mut sides = assignment.splitn(2, '=');
+                let register = sides.next()?.trim()[1..].to_string();
+                let val = Self::parse_integer(sides.next()?.trim())?;
+                Some(DebuggerCommand::SetRegister(register, val))
+            }
+            "set" if matches!(tokens.get(1), Some(&"var") | Some(&"variable")) => Self::parse_assignment(&tokens[2..]),
+            "set" if matches!(tokens.get(1), Some(token) if token.starts_with('{')) => {
+                Self::parse_assignment(&tokens[1..])
+            }
+            "set" => match (tokens.get(1), tokens.get(2)) {
+                (Some(&"follow-fork-mode"), Some(&"parent")) => {
+                    Some(DebuggerCommand::SetFollowForkMode(FollowForkMode::Parent))
+                }
+                (Some(&"follow-fork-mode"), Some(&"child")) => {
+                    Some(DebuggerCommand::SetFollowForkMode(FollowForkMode::Child))
+                }
+                (Some(&"detach-on-fork"), Some(&"on")) => Some(DebuggerCommand::SetDetachOnFork(true)),
+                (Some(&"detach-on-fork"), Some(&"off")) => Some(DebuggerCommand::SetDetachOnFork(false)),
+                _ => None,
+            },
+            "thread" => Some(DebuggerCommand::Thread(tokens.get(1)?.parse().ok()?)),
+            "d" | "delete" => Some(DebuggerCommand::Delete(Self::parse_breakpoint_id(tokens)?)),
+            "disable" => Some(DebuggerCommand::Disable(Self::parse_breakpoint_id(tokens)?)),
+            "ignore" => Some(DebuggerCommand::Ignore(tokens.get(1)?.parse().ok()?, tokens.get(2)?.parse().ok()?)),
+            "enable" => Some(DebuggerCommand::Enable(Self::parse_breakpoint_id(tokens)?)),
+            "i" | "info" => match tokens.get(1) {
+                Some(&"locals") => Some(DebuggerCommand::InfoLocals),
+                Some(&"args") => Some(DebuggerCommand::InfoArgs),
+                Some(&"r") | Some(&"reg") | Some(&"registers") => Some(DebuggerCommand::InfoRegisters(
+                    tokens[2..].iter().map(|name| name.trim_start_matches('$').to_string()).collect(),
+                )),
+                Some(&"all-registers") => Some(DebuggerCommand::InfoAllRegisters),
+                Some(&"b") | Some(&"break") | Some(&"breakpoints") => Some(DebuggerCommand::InfoBreakpoints),
+                Some(&"threads") => Some(DebuggerCommand::InfoThreads),
+                Some(&"inferiors") => Some(DebuggerCommand::InfoInferiors),
+                Some(&"signals") => match tokens.get(2) {
+                    Some(name) => Some(DebuggerCommand::InfoSignals(Some(signals::parse_signal(name)?))),
+                    None => Some(DebuggerCommand::InfoSignals(None)),
+                },
+                _ => None,
+            },
             // Default case:
             _ => None,
         }
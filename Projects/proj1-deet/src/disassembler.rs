@@ -0,0 +1,1145 @@
+//! A small x86-64 disassembler. It understands the general purpose and scalar SSE instructions
+//! that gcc and clang emit for ordinary user-space C code, and prints them in AT&T syntax (the
+//! same syntax gdb and objdump use). Anything it doesn't recognize is shown as "(bad)".
+
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    pub address: usize,
+    pub length: usize,
+    pub mnemonic: String,
+    pub operands: String,
+    /// Destination of a direct call or jump
+    pub target: Option<usize>,
+    /// Address referenced by a %rip-relative memory operand
+    pub rip_relative_addr: Option<usize>,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.operands.is_empty() {
+            write!(f, "{}", self.mnemonic)
+        } else {
+            write!(f, "{:6} {}", self.mnemonic, self.operands)
+        }
+    }
+}
+
+/// Decodes the instruction at the start of `bytes`, which were read from address `addr`.
+pub fn decode(bytes: &[u8], addr: usize) -> Instruction {
+    let mut decoder = Decoder {
+        bytes,
+        pos: 0,
+        rex: 0,
+        opsize_prefix: false,
+        rep_prefix: None,
+        segment: None,
+        lock: false,
+        suffix_size: None,
+        indirect: false,
+    };
+    match decoder.decode() {
+        Some((mnemonic, operands)) => decoder.finish(addr, mnemonic, operands),
+        None => Instruction {
+            address: addr,
+            length: 1,
+            mnemonic: "(bad)".to_string(),
+            operands: String::new(),
+            target: None,
+            rip_relative_addr: None,
+        },
+    }
+}
+
+const REGS_64: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+const REGS_32: [&str; 16] = [
+    "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d", "r12d",
+    "r13d", "r14d", "r15d",
+];
+const REGS_16: [&str; 16] = [
+    "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w", "r12w", "r13w",
+    "r14w", "r15w",
+];
+const REGS_8_REX: [&str; 16] = [
+    "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b",
+    "r13b", "r14b", "r15b",
+];
+const REGS_8: [&str; 8] = ["al", "cl", "dl", "bl", "ah", "ch", "dh", "bh"];
+const REGS_XMM: [&str; 16] = [
+    "xmm0", "xmm1", "xmm2", "xmm3", "xmm4", "xmm5", "xmm6", "xmm7", "xmm8", "xmm9", "xmm10",
+    "xmm11", "xmm12", "xmm13", "xmm14", "xmm15",
+];
+
+const CONDITIONS: [&str; 16] = [
+    "o", "no", "b", "ae", "e", "ne", "be", "a", "s", "ns", "p", "np", "l", "ge", "le", "g",
+];
+const ALU_OPS: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+const SHIFT_OPS: [&str; 8] = ["rol", "ror", "rcl", "rcr", "shl", "shr", "shl", "sar"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RegKind {
+    Gpr(usize),
+    Xmm,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Reg(&'static str),
+    Imm(u64),
+    Mem {
+        base: Option<&'static str>,
+        index: Option<(&'static str, u8)>,
+        /// None if the instruction has no displacement bytes
+        disp: Option<i64>,
+        rip_relative: bool,
+    },
+    Target(i64),
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    rex: u8,
+    opsize_prefix: bool,
+    rep_prefix: Option<u8>,
+    segment: Option<&'static str>,
+    lock: bool,
+    /// Operand size to append as a mnemonic suffix if no register operand implies it
+    suffix_size: Option<usize>,
+    /// Whether the operand of a call/jmp is an indirect target (printed with a leading `*`)
+    indirect: bool,
+}
+
+impl<'a> Decoder<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    /// Reads a little-endian, sign-extended immediate of `size` bytes.
+    fn imm(&mut self, size: usize) -> Option<i64> {
+        let bytes = self.bytes.get(self.pos..self.pos + size)?;
+        self.pos += size;
+        let val = bytes
+            .iter()
+            .rev()
+            .fold(0u64, |acc, &byte| (acc << 8) | byte as u64);
+        let shift = 64 - 8 * size as u32;
+        Some(((val << shift) as i64) >> shift)
+    }
+
+    fn rex_w(&self) -> bool {
+        self.rex & 0x8 != 0
+    }
+
+    fn rex_r(&self) -> u8 {
+        (self.rex & 0x4) << 1
+    }
+
+    fn rex_x(&self) -> u8 {
+        (self.rex & 0x2) << 2
+    }
+
+    fn rex_b(&self) -> u8 {
+        (self.rex & 0x1) << 3
+    }
+
+    /// Size of a "v"-sized operand: 8 bytes with REX.W, 2 with an operand size prefix, else 4.
+    fn operand_size(&self) -> usize {
+        if self.rex_w() {
+            8
+        } else if self.opsize_prefix {
+            2
+        } else {
+            4
+        }
+    }
+
+    fn reg(&self, kind: RegKind, num: u8) -> &'static str {
+        let num = num as usize;
+        match kind {
+            RegKind::Gpr(8) => REGS_64[num],
+            RegKind::Gpr(4) => REGS_32[num],
+            RegKind::Gpr(2) => REGS_16[num],
+            RegKind::Gpr(_) if self.rex != 0 || num >= 8 => REGS_8_REX[num],
+            RegKind::Gpr(_) => REGS_8[num],
+            RegKind::Xmm => REGS_XMM[num],
+        }
+    }
+
+    /// Decodes a ModRM byte (and any SIB byte and displacement that follow it). Returns the
+    /// extended `reg` field along with the r/m operand, using `rm_kind` to name a register r/m.
+    fn modrm(&mut self, rm_kind: RegKind) -> Option<(u8, Operand)> {
+        let modrm = self.byte()?;
+        let md = modrm >> 6;
+        let reg = ((modrm >> 3) & 7) | self.rex_r();
+        let rm = modrm & 7;
+        if md == 3 {
+            return Some((reg, Operand::Reg(self.reg(rm_kind, rm | self.rex_b()))));
+        }
+
+        let mut base = None;
+        let mut index = None;
+        let mut rip_relative = false;
+        let disp_size;
+        if rm == 4 {
+            let sib = self.byte()?;
+            let scale = 1 << (sib >> 6);
+            let index_num = ((sib >> 3) & 7) | self.rex_x();
+            if index_num != 4 {
+                index = Some((REGS_64[index_num as usize], scale));
+            }
+            let base_num = sib & 7;
+            if base_num == 5 && md == 0 {
+                disp_size = 4;
+            } else {
+                base = Some(REGS_64[(base_num | self.rex_b()) as usize]);
+                disp_size = [0, 1, 4][md as usize];
+            }
+        } else if rm == 5 && md == 0 {
+            rip_relative = true;
+            disp_size = 4;
+        } else {
+            base = Some(REGS_64[(rm | self.rex_b()) as usize]);
+            disp_size = [0, 1, 4][md as usize];
+        }
+        let disp = if disp_size > 0 {
+            Some(self.imm(disp_size)?)
+        } else {
+            None
+        };
+        Some((
+            reg,
+            Operand::Mem {
+                base,
+                index,
+                disp,
+                rip_relative,
+            },
+        ))
+    }
+
+    /// Decodes a ModRM byte where both operands are general purpose registers/memory of `size`.
+    fn gpr_modrm(&mut self, size: usize) -> Option<(Operand, Operand)> {
+        let (reg, rm) = self.modrm(RegKind::Gpr(size))?;
+        Some((Operand::Reg(self.reg(RegKind::Gpr(size), reg)), rm))
+    }
+
+    /// Decodes prefixes and the instruction itself. Operands are returned in Intel order
+    /// (destination first).
+    fn decode(&mut self) -> Option<(String, Vec<Operand>)> {
+        loop {
+            match *self.bytes.get(self.pos)? {
+                0x66 => self.opsize_prefix = true,
+                0xf2 | 0xf3 => self.rep_prefix = Some(self.bytes[self.pos]),
+                0xf0 => self.lock = true,
+                0x2e | 0x3e | 0x26 | 0x36 => {}
+                0x64 => self.segment = Some("fs"),
+                0x65 => self.segment = Some("gs"),
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        if let Some(byte) = self.bytes.get(self.pos) {
+            if byte & 0xf0 == 0x40 {
+                self.rex = *byte;
+                self.pos += 1;
+            }
+        }
+
+        let opcode = self.byte()?;
+        let size = self.operand_size();
+        match opcode {
+            // add/or/adc/sbb/and/sub/xor/cmp in their six basic forms
+            0x00..=0x3f if opcode & 7 < 6 => {
+                let op = ALU_OPS[(opcode >> 3) as usize & 7];
+                let operands = match opcode & 7 {
+                    0 => {
+                        let (reg, rm) = self.gpr_modrm(1)?;
+                        vec![rm, reg]
+                    }
+                    1 => {
+                        let (reg, rm) = self.gpr_modrm(size)?;
+                        vec![rm, reg]
+                    }
+                    2 => {
+                        let (reg, rm) = self.gpr_modrm(1)?;
+                        vec![reg, rm]
+                    }
+                    3 => {
+                        let (reg, rm) = self.gpr_modrm(size)?;
+                        vec![reg, rm]
+                    }
+                    4 => vec![Operand::Reg("al"), self.imm_operand(1, 1)?],
+                    _ => vec![
+                        Operand::Reg(self.reg(RegKind::Gpr(size), 0)),
+                        self.imm_operand(size.min(4), size)?,
+                    ],
+                };
+                Some((op.to_string(), operands))
+            }
+            0x50..=0x57 => Some((
+                "push".to_string(),
+                vec![Operand::Reg(REGS_64[((opcode & 7) | self.rex_b()) as usize])],
+            )),
+            0x58..=0x5f => Some((
+                "pop".to_string(),
+                vec![Operand::Reg(REGS_64[((opcode & 7) | self.rex_b()) as usize])],
+            )),
+            0x63 => {
+                let (reg, rm) = self.modrm(RegKind::Gpr(4))?;
+                Some((
+                    "movslq".to_string(),
+                    vec![Operand::Reg(self.reg(RegKind::Gpr(size), reg)), rm],
+                ))
+            }
+            0x68 => Some(("push".to_string(), vec![self.imm_operand(4, 8)?])),
+            0x6a => Some(("push".to_string(), vec![self.imm_operand(1, 8)?])),
+            0x69 | 0x6b => {
+                let (reg, rm) = self.gpr_modrm(size)?;
+                let imm_size = if opcode == 0x69 { size.min(4) } else { 1 };
+                let imm = self.imm_operand(imm_size, size)?;
+                Some(("imul".to_string(), vec![reg, rm, imm]))
+            }
+            0x70..=0x7f => {
+                let rel = self.imm(1)?;
+                Some((
+                    format!("j{}", CONDITIONS[(opcode & 0xf) as usize]),
+                    vec![Operand::Target(rel)],
+                ))
+            }
+            0x80 | 0x81 | 0x83 => {
+                let op_size = if opcode == 0x80 { 1 } else { size };
+                let (reg, rm) = self.modrm(RegKind::Gpr(op_size))?;
+                let imm_size = match opcode {
+                    0x81 => op_size.min(4),
+                    _ => 1,
+                };
+                let imm = self.imm_operand(imm_size, op_size)?;
+                self.suffix_size = Some(op_size);
+                Some((ALU_OPS[(reg & 7) as usize].to_string(), vec![rm, imm]))
+            }
+            0x84..=0x87 => {
+                let op_size = if opcode & 1 == 0 { 1 } else { size };
+                let (reg, rm) = self.gpr_modrm(op_size)?;
+                let op = if opcode < 0x86 { "test" } else { "xchg" };
+                Some((op.to_string(), vec![rm, reg]))
+            }
+            0x88..=0x8b => {
+                let op_size = if opcode & 1 == 0 { 1 } else { size };
+                let (reg, rm) = self.gpr_modrm(op_size)?;
+                let operands = if opcode < 0x8a {
+                    vec![rm, reg]
+                } else {
+                    vec![reg, rm]
+                };
+                Some(("mov".to_string(), operands))
+            }
+            0x8d => {
+                let (reg, rm) = self.gpr_modrm(size)?;
+                Some(("lea".to_string(), vec![reg, rm]))
+            }
+            0x8f => {
+                let (_, rm) = self.modrm(RegKind::Gpr(8))?;
+                Some(("pop".to_string(), vec![rm]))
+            }
+            0x90 if self.rex_b() == 0 && !self.opsize_prefix => {
+                if self.rep_prefix == Some(0xf3) {
+                    Some(("pause".to_string(), vec![]))
+                } else {
+                    Some(("nop".to_string(), vec![]))
+                }
+            }
+            0x90..=0x97 => Some((
+                "xchg".to_string(),
+                vec![
+                    Operand::Reg(self.reg(RegKind::Gpr(size), (opcode & 7) | self.rex_b())),
+                    Operand::Reg(self.reg(RegKind::Gpr(size), 0)),
+                ],
+            )),
+            0x98 => Some((
+                match size {
+                    8 => "cltq",
+                    2 => "cbtw",
+                    _ => "cwtl",
+                }
+                .to_string(),
+                vec![],
+            )),
+            0x99 => Some((
+                match size {
+                    8 => "cqto",
+                    2 => "cwtd",
+                    _ => "cltd",
+                }
+                .to_string(),
+                vec![],
+            )),
+            0xa4 | 0xa5 | 0xaa | 0xab => {
+                let op_size = if opcode & 1 == 0 { 1 } else { size };
+                let prefix = if self.rep_prefix.is_some() { "rep " } else { "" };
+                let op = if opcode < 0xaa { "movs" } else { "stos" };
+                let dest = Operand::Mem {
+                    base: Some("rdi"),
+                    index: None,
+                    disp: None,
+                    rip_relative: false,
+                };
+                let src = if opcode < 0xaa {
+                    Operand::Mem {
+                        base: Some("rsi"),
+                        index: None,
+                        disp: None,
+                        rip_relative: false,
+                    }
+                } else {
+                    Operand::Reg(self.reg(RegKind::Gpr(op_size), 0))
+                };
+                self.suffix_size = Some(op_size);
+                Some((format!("{}{}", prefix, op), vec![dest, src]))
+            }
+            0xa8 => Some(("test".to_string(), vec![Operand::Reg("al"), self.imm_operand(1, 1)?])),
+            0xa9 => Some((
+                "test".to_string(),
+                vec![
+                    Operand::Reg(self.reg(RegKind::Gpr(size), 0)),
+                    self.imm_operand(size.min(4), size)?,
+                ],
+            )),
+            0xb0..=0xb7 => {
+                let reg = self.reg(RegKind::Gpr(1), (opcode & 7) | self.rex_b());
+                Some(("mov".to_string(), vec![Operand::Reg(reg), self.imm_operand(1, 1)?]))
+            }
+            0xb8..=0xbf => {
+                let reg = self.reg(RegKind::Gpr(size), (opcode & 7) | self.rex_b());
+                if size == 8 {
+                    let imm = self.imm(8)? as u64;
+                    Some(("movabs".to_string(), vec![Operand::Reg(reg), Operand::Imm(imm)]))
+                } else {
+                    Some(("mov".to_string(), vec![Operand::Reg(reg), self.imm_operand(size, size)?]))
+                }
+            }
+            0xc0 | 0xc1 | 0xd0 | 0xd1 | 0xd2 | 0xd3 => {
+                let op_size = if opcode & 1 == 0 { 1 } else { size };
+                let (reg, rm) = self.modrm(RegKind::Gpr(op_size))?;
+                self.suffix_size = Some(op_size);
+                let op = SHIFT_OPS[(reg & 7) as usize].to_string();
+                match opcode {
+                    0xc0 | 0xc1 => {
+                        let imm = self.imm_operand(1, 1)?;
+                        Some((op, vec![rm, imm]))
+                    }
+                    0xd0 | 0xd1 => Some((op, vec![rm])),
+                    _ => Some((op, vec![rm, Operand::Reg("cl")])),
+                }
+            }
+            0xc2 => {
+                let imm = self.imm(2)? as u16 as u64;
+                Some(("ret".to_string(), vec![Operand::Imm(imm)]))
+            }
+            0xc3 => Some((
+                if self.rep_prefix.is_some() { "repz ret" } else { "ret" }.to_string(),
+                vec![],
+            )),
+            0xc6 | 0xc7 => {
+                let op_size = if opcode == 0xc6 { 1 } else { size };
+                let (reg, rm) = self.modrm(RegKind::Gpr(op_size))?;
+                if reg & 7 != 0 {
+                    return None;
+                }
+                let imm = self.imm_operand(op_size.min(4), op_size)?;
+                self.suffix_size = Some(op_size);
+                Some(("mov".to_string(), vec![rm, imm]))
+            }
+            0xc9 => Some(("leave".to_string(), vec![])),
+            0xcc => Some(("int3".to_string(), vec![])),
+            0xcd => Some(("int".to_string(), vec![self.imm_operand(1, 1)?])),
+            0xe8 => {
+                let rel = self.imm(4)?;
+                Some(("call".to_string(), vec![Operand::Target(rel)]))
+            }
+            0xe9 => {
+                let rel = self.imm(4)?;
+                Some(("jmp".to_string(), vec![Operand::Target(rel)]))
+            }
+            0xeb => {
+                let rel = self.imm(1)?;
+                Some(("jmp".to_string(), vec![Operand::Target(rel)]))
+            }
+            0xd8..=0xdf => self.decode_x87(opcode),
+            0xf4 => Some(("hlt".to_string(), vec![])),
+            0xf6 | 0xf7 => {
+                let op_size = if opcode == 0xf6 { 1 } else { size };
+                let (reg, rm) = self.modrm(RegKind::Gpr(op_size))?;
+                self.suffix_size = Some(op_size);
+                match reg & 7 {
+                    0 | 1 => {
+                        let imm = self.imm_operand(op_size.min(4), op_size)?;
+                        Some(("test".to_string(), vec![rm, imm]))
+                    }
+                    n => {
+                        let op = ["", "", "not", "neg", "mul", "imul", "div", "idiv"][n as usize];
+                        Some((op.to_string(), vec![rm]))
+                    }
+                }
+            }
+            0xf8 => Some(("clc".to_string(), vec![])),
+            0xf9 => Some(("stc".to_string(), vec![])),
+            0xfc => Some(("cld".to_string(), vec![])),
+            0xfd => Some(("std".to_string(), vec![])),
+            0xfe => {
+                let (reg, rm) = self.modrm(RegKind::Gpr(1))?;
+                self.suffix_size = Some(1);
+                match reg & 7 {
+                    0 => Some(("inc".to_string(), vec![rm])),
+                    1 => Some(("dec".to_string(), vec![rm])),
+                    _ => None,
+                }
+            }
+            0xff => {
+                let reg = (*self.bytes.get(self.pos)? >> 3) & 7;
+                match reg {
+                    0 | 1 => {
+                        let (_, rm) = self.modrm(RegKind::Gpr(size))?;
+                        self.suffix_size = Some(size);
+                        let op = if reg == 0 { "inc" } else { "dec" };
+                        Some((op.to_string(), vec![rm]))
+                    }
+                    2 | 4 => {
+                        let (_, rm) = self.modrm(RegKind::Gpr(8))?;
+                        self.indirect = true;
+                        let op = if reg == 2 { "call" } else { "jmp" };
+                        Some((op.to_string(), vec![rm]))
+                    }
+                    6 => {
+                        let (_, rm) = self.modrm(RegKind::Gpr(8))?;
+                        Some(("push".to_string(), vec![rm]))
+                    }
+                    _ => None,
+                }
+            }
+            0x0f => self.decode_two_byte(size),
+            _ => None,
+        }
+    }
+
+    fn decode_two_byte(&mut self, size: usize) -> Option<(String, Vec<Operand>)> {
+        let opcode = self.byte()?;
+        // The mandatory prefix selects between the packed/scalar single/double variants
+        let sse_suffix = match (self.rep_prefix, self.opsize_prefix) {
+            (Some(0xf3), _) => "ss",
+            (Some(0xf2), _) => "sd",
+            (_, true) => "pd",
+            _ => "ps",
+        };
+        match opcode {
+            0x05 => Some(("syscall".to_string(), vec![])),
+            0x0b => Some(("ud2".to_string(), vec![])),
+            0x1e if self.rep_prefix == Some(0xf3) && self.bytes.get(self.pos) == Some(&0xfa) => {
+                self.pos += 1;
+                Some(("endbr64".to_string(), vec![]))
+            }
+            0x1f => {
+                let (_, rm) = self.modrm(RegKind::Gpr(size))?;
+                self.suffix_size = Some(size);
+                Some(("nop".to_string(), vec![rm]))
+            }
+            0x10 | 0x11 | 0x28 | 0x29 => {
+                let (reg, rm) = self.modrm(RegKind::Xmm)?;
+                let reg = Operand::Reg(REGS_XMM[reg as usize]);
+                let op = if opcode < 0x28 {
+                    match sse_suffix {
+                        "ps" => "movups",
+                        "pd" => "movupd",
+                        "ss" => "movss",
+                        _ => "movsd",
+                    }
+                } else if self.opsize_prefix {
+                    "movapd"
+                } else {
+                    "movaps"
+                };
+                let operands = if opcode & 1 == 0 {
+                    vec![reg, rm]
+                } else {
+                    vec![rm, reg]
+                };
+                Some((op.to_string(), operands))
+            }
+            0x2a => {
+                let src_size = if self.rex_w() { 8 } else { 4 };
+                let (reg, rm) = self.modrm(RegKind::Gpr(src_size))?;
+                if let Operand::Mem { .. } = rm {
+                    self.suffix_size = Some(src_size);
+                }
+                Some((
+                    format!("cvtsi2{}", &sse_suffix[1..]),
+                    vec![Operand::Reg(REGS_XMM[reg as usize]), rm],
+                ))
+            }
+            0x2c | 0x2d => {
+                let (reg, rm) = self.modrm(RegKind::Xmm)?;
+                let dest_size = if self.rex_w() { 8 } else { 4 };
+                let truncate = if opcode == 0x2c { "t" } else { "" };
+                Some((
+                    format!("cvt{}{}2si", truncate, sse_suffix),
+                    vec![Operand::Reg(self.reg(RegKind::Gpr(dest_size), reg)), rm],
+                ))
+            }
+            0x2e | 0x2f => {
+                let (reg, rm) = self.modrm(RegKind::Xmm)?;
+                let op = if opcode == 0x2e { "ucomis" } else { "comis" };
+                let precision = if self.opsize_prefix { "d" } else { "s" };
+                Some((
+                    format!("{}{}", op, precision),
+                    vec![Operand::Reg(REGS_XMM[reg as usize]), rm],
+                ))
+            }
+            0x40..=0x4f => {
+                let (reg, rm) = self.gpr_modrm(size)?;
+                Some((
+                    format!("cmov{}", CONDITIONS[(opcode & 0xf) as usize]),
+                    vec![reg, rm],
+                ))
+            }
+            0x51 | 0x54 | 0x55 | 0x56 | 0x57 | 0x58 | 0x59 | 0x5c | 0x5d | 0x5e | 0x5f => {
+                let (reg, rm) = self.modrm(RegKind::Xmm)?;
+                let op = match opcode {
+                    0x51 => "sqrt",
+                    0x54 => "and",
+                    0x55 => "andn",
+                    0x56 => "or",
+                    0x57 => "xor",
+                    0x58 => "add",
+                    0x59 => "mul",
+                    0x5c => "sub",
+                    0x5d => "min",
+                    0x5e => "div",
+                    _ => "max",
+                };
+                Some((
+                    format!("{}{}", op, sse_suffix),
+                    vec![Operand::Reg(REGS_XMM[reg as usize]), rm],
+                ))
+            }
+            0x5a => {
+                let (reg, rm) = self.modrm(RegKind::Xmm)?;
+                let op = match sse_suffix {
+                    "ss" => "cvtss2sd",
+                    "sd" => "cvtsd2ss",
+                    "pd" => "cvtpd2ps",
+                    _ => "cvtps2pd",
+                };
+                Some((op.to_string(), vec![Operand::Reg(REGS_XMM[reg as usize]), rm]))
+            }
+            0x6e | 0x7e if self.opsize_prefix => {
+                let gpr_size = if self.rex_w() { 8 } else { 4 };
+                let (reg, rm) = self.modrm(RegKind::Gpr(gpr_size))?;
+                let op = if self.rex_w() { "movq" } else { "movd" };
+                let xmm = Operand::Reg(REGS_XMM[reg as usize]);
+                let operands = if opcode == 0x6e {
+                    vec![xmm, rm]
+                } else {
+                    vec![rm, xmm]
+                };
+                Some((op.to_string(), operands))
+            }
+            0x7e if self.rep_prefix == Some(0xf3) => {
+                let (reg, rm) = self.modrm(RegKind::Xmm)?;
+                Some(("movq".to_string(), vec![Operand::Reg(REGS_XMM[reg as usize]), rm]))
+            }
+            0xd6 if self.opsize_prefix => {
+                let (reg, rm) = self.modrm(RegKind::Xmm)?;
+                Some(("movq".to_string(), vec![rm, Operand::Reg(REGS_XMM[reg as usize])]))
+            }
+            0x6f | 0x7f if self.opsize_prefix || self.rep_prefix == Some(0xf3) => {
+                let (reg, rm) = self.modrm(RegKind::Xmm)?;
+                let op = if self.opsize_prefix { "movdqa" } else { "movdqu" };
+                let xmm = Operand::Reg(REGS_XMM[reg as usize]);
+                let operands = if opcode == 0x6f {
+                    vec![xmm, rm]
+                } else {
+                    vec![rm, xmm]
+                };
+                Some((op.to_string(), operands))
+            }
+            0x12 | 0x13 | 0x16 | 0x17 => {
+                let (reg, rm) = self.modrm(RegKind::Xmm)?;
+                let xmm = Operand::Reg(REGS_XMM[reg as usize]);
+                let high = opcode >= 0x16;
+                let op = match (&rm, high) {
+                    (Operand::Reg(_), false) => "movhlps",
+                    (Operand::Reg(_), true) => "movlhps",
+                    (_, false) if self.opsize_prefix => "movlpd",
+                    (_, false) => "movlps",
+                    (_, true) if self.opsize_prefix => "movhpd",
+                    (_, true) => "movhps",
+                };
+                let operands = if opcode & 1 == 0 {
+                    vec![xmm, rm]
+                } else {
+                    vec![rm, xmm]
+                };
+                Some((op.to_string(), operands))
+            }
+            0x14 | 0x15 => {
+                let (reg, rm) = self.modrm(RegKind::Xmm)?;
+                let op = if opcode == 0x14 { "unpckl" } else { "unpckh" };
+                let precision = if self.opsize_prefix { "pd" } else { "ps" };
+                Some((
+                    format!("{}{}", op, precision),
+                    vec![Operand::Reg(REGS_XMM[reg as usize]), rm],
+                ))
+            }
+            0x5b | 0xe6 => {
+                let (reg, rm) = self.modrm(RegKind::Xmm)?;
+                let op = match (opcode, self.rep_prefix, self.opsize_prefix) {
+                    (0x5b, Some(0xf3), _) => "cvttps2dq",
+                    (0x5b, _, true) => "cvtps2dq",
+                    (0x5b, _, _) => "cvtdq2ps",
+                    (_, Some(0xf3), _) => "cvtdq2pd",
+                    (_, Some(0xf2), _) => "cvtpd2dq",
+                    (_, _, true) => "cvttpd2dq",
+                    _ => return None,
+                };
+                Some((op.to_string(), vec![Operand::Reg(REGS_XMM[reg as usize]), rm]))
+            }
+            0x70 if self.opsize_prefix || self.rep_prefix.is_some() => {
+                let (reg, rm) = self.modrm(RegKind::Xmm)?;
+                let imm = self.imm_operand(1, 1)?;
+                let op = match self.rep_prefix {
+                    Some(0xf2) => "pshuflw",
+                    Some(0xf3) => "pshufhw",
+                    _ => "pshufd",
+                };
+                Some((op.to_string(), vec![Operand::Reg(REGS_XMM[reg as usize]), rm, imm]))
+            }
+            0x71..=0x73 if self.opsize_prefix => {
+                let (reg, rm) = self.modrm(RegKind::Xmm)?;
+                let imm = self.imm_operand(1, 1)?;
+                let granularity = ["w", "d", "q"][(opcode - 0x71) as usize];
+                let op = match (reg & 7, opcode) {
+                    (2, _) => format!("psrl{}", granularity),
+                    (4, 0x71) | (4, 0x72) => format!("psra{}", granularity),
+                    (6, _) => format!("psll{}", granularity),
+                    (3, 0x73) => "psrldq".to_string(),
+                    (7, 0x73) => "pslldq".to_string(),
+                    _ => return None,
+                };
+                Some((op, vec![rm, imm]))
+            }
+            0xc4 if self.opsize_prefix => {
+                let (reg, rm) = self.modrm(RegKind::Gpr(4))?;
+                let imm = self.imm_operand(1, 1)?;
+                Some(("pinsrw".to_string(), vec![Operand::Reg(REGS_XMM[reg as usize]), rm, imm]))
+            }
+            0xc5 | 0xd7 if self.opsize_prefix => {
+                let (reg, rm) = self.modrm(RegKind::Xmm)?;
+                let dest = Operand::Reg(self.reg(RegKind::Gpr(4), reg));
+                if opcode == 0xc5 {
+                    let imm = self.imm_operand(1, 1)?;
+                    Some(("pextrw".to_string(), vec![dest, rm, imm]))
+                } else {
+                    Some(("pmovmskb".to_string(), vec![dest, rm]))
+                }
+            }
+            0x60..=0x6d | 0x74..=0x76 | 0xd1..=0xfe if self.opsize_prefix => {
+                let op = sse2_integer_op(opcode)?;
+                let (reg, rm) = self.modrm(RegKind::Xmm)?;
+                Some((op.to_string(), vec![Operand::Reg(REGS_XMM[reg as usize]), rm]))
+            }
+            0x01 if self.bytes.get(self.pos) == Some(&0xd0) => {
+                self.pos += 1;
+                Some(("xgetbv".to_string(), vec![]))
+            }
+            0x31 => Some(("rdtsc".to_string(), vec![])),
+            0xae if self.bytes.get(self.pos).is_some_and(|&b| b >= 0xe8) => {
+                let op = match self.byte()? & 0xf8 {
+                    0xe8 => "lfence",
+                    0xf0 => "mfence",
+                    _ => "sfence",
+                };
+                Some((op.to_string(), vec![]))
+            }
+            0xa3 | 0xab | 0xb3 | 0xbb => {
+                let (reg, rm) = self.gpr_modrm(size)?;
+                let op = ["bt", "bts", "btr", "btc"][((opcode >> 3) & 3) as usize];
+                Some((op.to_string(), vec![rm, reg]))
+            }
+            0xba => {
+                let (reg, rm) = self.modrm(RegKind::Gpr(size))?;
+                let imm = self.imm_operand(1, 1)?;
+                if reg & 7 < 4 {
+                    return None;
+                }
+                self.suffix_size = Some(size);
+                let op = ["bt", "bts", "btr", "btc"][(reg & 3) as usize];
+                Some((op.to_string(), vec![rm, imm]))
+            }
+            0xa4 | 0xa5 | 0xac | 0xad => {
+                let (reg, rm) = self.gpr_modrm(size)?;
+                let op = if opcode < 0xac { "shld" } else { "shrd" };
+                let count = if opcode & 1 == 0 {
+                    self.imm_operand(1, 1)?
+                } else {
+                    Operand::Reg("cl")
+                };
+                Some((op.to_string(), vec![rm, reg, count]))
+            }
+            0xb8 if self.rep_prefix == Some(0xf3) => {
+                let (reg, rm) = self.gpr_modrm(size)?;
+                Some(("popcnt".to_string(), vec![reg, rm]))
+            }
+            0xbc | 0xbd => {
+                let (reg, rm) = self.gpr_modrm(size)?;
+                let op = match (opcode, self.rep_prefix) {
+                    (0xbc, Some(0xf3)) => "tzcnt",
+                    (0xbc, _) => "bsf",
+                    (_, Some(0xf3)) => "lzcnt",
+                    _ => "bsr",
+                };
+                Some((op.to_string(), vec![reg, rm]))
+            }
+            0x80..=0x8f => {
+                let rel = self.imm(4)?;
+                Some((
+                    format!("j{}", CONDITIONS[(opcode & 0xf) as usize]),
+                    vec![Operand::Target(rel)],
+                ))
+            }
+            0x90..=0x9f => {
+                let (_, rm) = self.modrm(RegKind::Gpr(1))?;
+                Some((format!("set{}", CONDITIONS[(opcode & 0xf) as usize]), vec![rm]))
+            }
+            0xa2 => Some(("cpuid".to_string(), vec![])),
+            0xaf => {
+                let (reg, rm) = self.gpr_modrm(size)?;
+                Some(("imul".to_string(), vec![reg, rm]))
+            }
+            0xb0 | 0xb1 => {
+                let op_size = if opcode == 0xb0 { 1 } else { size };
+                let (reg, rm) = self.gpr_modrm(op_size)?;
+                let op = if self.lock { "lock cmpxchg" } else { "cmpxchg" };
+                Some((op.to_string(), vec![rm, reg]))
+            }
+            0xc0 | 0xc1 => {
+                let op_size = if opcode == 0xc0 { 1 } else { size };
+                let (reg, rm) = self.gpr_modrm(op_size)?;
+                let op = if self.lock { "lock xadd" } else { "xadd" };
+                Some((op.to_string(), vec![rm, reg]))
+            }
+            0xb6 | 0xb7 | 0xbe | 0xbf => {
+                let src_size = if opcode & 1 == 0 { 1 } else { 2 };
+                let (reg, rm) = self.modrm(RegKind::Gpr(src_size))?;
+                let extend = if opcode < 0xbe { "movz" } else { "movs" };
+                Some((
+                    format!("{}{}{}", extend, size_suffix(src_size), size_suffix(size)),
+                    vec![Operand::Reg(self.reg(RegKind::Gpr(size), reg)), rm],
+                ))
+            }
+            0xc8..=0xcf => Some((
+                "bswap".to_string(),
+                vec![Operand::Reg(self.reg(RegKind::Gpr(size), (opcode & 7) | self.rex_b()))],
+            )),
+            _ => None,
+        }
+    }
+
+    /// Decodes the common x87 floating point instructions (used for `long double` arithmetic).
+    fn decode_x87(&mut self, opcode: u8) -> Option<(String, Vec<Operand>)> {
+        let modrm = *self.bytes.get(self.pos)?;
+        if modrm < 0xc0 {
+            let (reg, rm) = self.modrm(RegKind::Gpr(8))?;
+            let op = match (opcode, reg & 7) {
+                (0xd9, 0) => "flds",
+                (0xd9, 2) => "fsts",
+                (0xd9, 3) => "fstps",
+                (0xd9, 5) => "fldcw",
+                (0xd9, 7) => "fnstcw",
+                (0xdb, 0) => "fildl",
+                (0xdb, 2) => "fistl",
+                (0xdb, 3) => "fistpl",
+                (0xdb, 5) => "fldt",
+                (0xdb, 7) => "fstpt",
+                (0xdd, 0) => "fldl",
+                (0xdd, 2) => "fstl",
+                (0xdd, 3) => "fstpl",
+                (0xdf, 5) => "fildll",
+                (0xdf, 7) => "fistpll",
+                (0xd8, n) | (0xdc, n) => {
+                    let precision = if opcode == 0xd8 { "s" } else { "l" };
+                    let op = ["fadd", "fmul", "fcom", "fcomp", "fsub", "fsubr", "fdiv", "fdivr"];
+                    return Some((format!("{}{}", op[n as usize], precision), vec![rm]));
+                }
+                _ => return None,
+            };
+            return Some((op.to_string(), vec![rm]));
+        }
+
+        self.pos += 1;
+        let st = |i: u8| ["st", "st(1)", "st(2)", "st(3)", "st(4)", "st(5)", "st(6)", "st(7)"][i as usize & 7];
+        let i = modrm & 7;
+        let (op, operands) = match (opcode, modrm & 0xf8) {
+            (0xd9, 0xc0) => ("fld", vec![Operand::Reg(st(i))]),
+            (0xd9, 0xc8) => ("fxch", vec![Operand::Reg(st(i))]),
+            (0xd9, 0xe0) if modrm == 0xe0 => ("fchs", vec![]),
+            (0xd9, 0xe0) if modrm == 0xe1 => ("fabs", vec![]),
+            (0xd9, 0xe8) if modrm == 0xe8 => ("fld1", vec![]),
+            (0xd9, 0xe8) if modrm == 0xee => ("fldz", vec![]),
+            (0xdd, 0xd8) => ("fstp", vec![Operand::Reg(st(i))]),
+            (0xde, 0xc0) => ("faddp", vec![Operand::Reg(st(i)), Operand::Reg("st")]),
+            (0xde, 0xc8) => ("fmulp", vec![Operand::Reg(st(i)), Operand::Reg("st")]),
+            (0xde, 0xe0) => ("fsubrp", vec![Operand::Reg(st(i)), Operand::Reg("st")]),
+            (0xde, 0xe8) => ("fsubp", vec![Operand::Reg(st(i)), Operand::Reg("st")]),
+            (0xde, 0xf0) => ("fdivrp", vec![Operand::Reg(st(i)), Operand::Reg("st")]),
+            (0xde, 0xf8) => ("fdivp", vec![Operand::Reg(st(i)), Operand::Reg("st")]),
+            (0xdf, 0xe8) => ("fucomip", vec![Operand::Reg("st"), Operand::Reg(st(i))]),
+            (0xdb, 0xe8) => ("fucomi", vec![Operand::Reg("st"), Operand::Reg(st(i))]),
+            _ => return None,
+        };
+        Some((op.to_string(), operands))
+    }
+
+    /// Reads an immediate of `size` bytes that is sign-extended to an operand of `op_size` bytes.
+    fn imm_operand(&mut self, size: usize, op_size: usize) -> Option<Operand> {
+        let val = self.imm(size)? as u64;
+        let mask = if op_size >= 8 {
+            u64::MAX
+        } else {
+            (1u64 << (8 * op_size)) - 1
+        };
+        Some(Operand::Imm(val & mask))
+    }
+
+    fn finish(&self, addr: usize, mut mnemonic: String, operands: Vec<Operand>) -> Instruction {
+        let next_addr = addr + self.pos;
+        let mut target = None;
+        let mut rip_relative_addr = None;
+
+        // Only add a size suffix if no register operand already says how big the operation is
+        if let Some(size) = self.suffix_size {
+            if !operands.iter().any(|op| matches!(op, Operand::Reg(_))) {
+                mnemonic.push_str(size_suffix(size));
+            }
+        }
+
+        // AT&T syntax lists the source operands before the destination
+        let formatted: Vec<String> = operands
+            .iter()
+            .rev()
+            .map(|op| match op {
+                Operand::Reg(name) => format!("%{}", name),
+                Operand::Imm(val) => format!("$0x{:x}", val),
+                Operand::Target(rel) => {
+                    let dest = (next_addr as i64 + rel) as usize;
+                    target = Some(dest);
+                    format!("{:#x}", dest)
+                }
+                Operand::Mem {
+                    base,
+                    index,
+                    disp,
+                    rip_relative,
+                } => {
+                    let mut s = String::new();
+                    if let Some(segment) = self.segment {
+                        s.push_str(&format!("%{}:", segment));
+                    }
+                    let disp_val = disp.unwrap_or(0);
+                    if *rip_relative {
+                        rip_relative_addr = Some((next_addr as i64 + disp_val) as usize);
+                        s.push_str(&format!("{}(%rip)", format_disp(disp_val)));
+                    } else if base.is_none() && index.is_none() {
+                        s.push_str(&format!("{:#x}", disp_val as u32));
+                    } else {
+                        if disp.is_some() {
+                            s.push_str(&format_disp(disp_val));
+                        }
+                        s.push('(');
+                        if let Some(base) = base {
+                            s.push_str(&format!("%{}", base));
+                        }
+                        if let Some((index, scale)) = index {
+                            s.push_str(&format!(",%{},{}", index, scale));
+                        }
+                        s.push(')');
+                    }
+                    s
+                }
+            })
+            .collect();
+        let mut operands = formatted.join(",");
+        if self.indirect {
+            operands.insert(0, '*');
+        }
+
+        Instruction {
+            address: addr,
+            length: self.pos,
+            mnemonic,
+            operands,
+            target,
+            rip_relative_addr,
+        }
+    }
+}
+
+/// Names the SSE2 integer instructions of the form `66 0f <opcode> /r` (xmm, xmm/m128).
+fn sse2_integer_op(opcode: u8) -> Option<&'static str> {
+    let op = match opcode {
+        0x60 => "punpcklbw",
+        0x61 => "punpcklwd",
+        0x62 => "punpckldq",
+        0x63 => "packsswb",
+        0x64 => "pcmpgtb",
+        0x65 => "pcmpgtw",
+        0x66 => "pcmpgtd",
+        0x67 => "packuswb",
+        0x68 => "punpckhbw",
+        0x69 => "punpckhwd",
+        0x6a => "punpckhdq",
+        0x6b => "packssdw",
+        0x6c => "punpcklqdq",
+        0x6d => "punpckhqdq",
+        0x74 => "pcmpeqb",
+        0x75 => "pcmpeqw",
+        0x76 => "pcmpeqd",
+        0xd1 => "psrlw",
+        0xd2 => "psrld",
+        0xd3 => "psrlq",
+        0xd4 => "paddq",
+        0xd5 => "pmullw",
+        0xd8 => "psubusb",
+        0xd9 => "psubusw",
+        0xda => "pminub",
+        0xdb => "pand",
+        0xdc => "paddusb",
+        0xdd => "paddusw",
+        0xde => "pmaxub",
+        0xdf => "pandn",
+        0xe0 => "pavgb",
+        0xe1 => "psraw",
+        0xe2 => "psrad",
+        0xe3 => "pavgw",
+        0xe4 => "pmulhuw",
+        0xe5 => "pmulhw",
+        0xe8 => "psubsb",
+        0xe9 => "psubsw",
+        0xea => "pminsw",
+        0xeb => "por",
+        0xec => "paddsb",
+        0xed => "paddsw",
+        0xee => "pmaxsw",
+        0xef => "pxor",
+        0xf1 => "psllw",
+        0xf2 => "pslld",
+        0xf3 => "psllq",
+        0xf4 => "pmuludq",
+        0xf5 => "pmaddwd",
+        0xf6 => "psadbw",
+        0xf8 => "psubb",
+        0xf9 => "psubw",
+        0xfa => "psubd",
+        0xfb => "psubq",
+        0xfc => "paddb",
+        0xfd => "paddw",
+        0xfe => "paddd",
+        _ => return None,
+    };
+    Some(op)
+}
+
+fn size_suffix(size: usize) -> &'static str {
+    match size {
+        1 => "b",
+        2 => "w",
+        4 => "l",
+        _ => "q",
+    }
+}
+
+fn format_disp(disp: i64) -> String {
+    if disp < 0 {
+        format!("-{:#x}", -disp)
+    } else {
+        format!("{:#x}", disp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn disassemble(bytes: &[u8]) -> (String, usize) {
+        let instruction = decode(bytes, 0x401000);
+        (instruction.to_string(), instruction.length)
+    }
+
+    #[test]
+    fn test_prologue_and_epilogue() {
+        assert_eq!(disassemble(&[0x55]), ("push   %rbp".to_string(), 1));
+        assert_eq!(disassemble(&[0x48, 0x89, 0xe5]), ("mov    %rsp,%rbp".to_string(), 3));
+        assert_eq!(disassemble(&[0x48, 0x83, 0xec, 0x10]), ("sub    $0x10,%rsp".to_string(), 4));
+        assert_eq!(disassemble(&[0xc9]), ("leave".to_string(), 1));
+        assert_eq!(disassemble(&[0xc3]), ("ret".to_string(), 1));
+        assert_eq!(disassemble(&[0xf3, 0x0f, 0x1e, 0xfa]), ("endbr64".to_string(), 4));
+    }
+
+    #[test]
+    fn test_memory_operands() {
+        assert_eq!(disassemble(&[0x89, 0x7d, 0xfc]), ("mov    %edi,-0x4(%rbp)".to_string(), 3));
+        assert_eq!(
+            disassemble(&[0xc7, 0x45, 0xfc, 0x00, 0x00, 0x00, 0x00]),
+            ("movl   $0x0,-0x4(%rbp)".to_string(), 7)
+        );
+        assert_eq!(
+            disassemble(&[0x8b, 0x04, 0x85, 0x20, 0x40, 0x40, 0x00]),
+            ("mov    0x404020(,%rax,4),%eax".to_string(), 7)
+        );
+        assert_eq!(
+            disassemble(&[0x64, 0x48, 0x8b, 0x04, 0x25, 0x28, 0x00, 0x00, 0x00]),
+            ("mov    %fs:0x28,%rax".to_string(), 9)
+        );
+        assert_eq!(
+            disassemble(&[0x0f, 0xb6, 0x45, 0xff]),
+            ("movzbl -0x1(%rbp),%eax".to_string(), 4)
+        );
+    }
+
+    #[test]
+    fn test_rip_relative() {
+        let instruction = decode(&[0x48, 0x8d, 0x05, 0xb7, 0x0e, 0x00, 0x00], 0x401146);
+        assert_eq!(instruction.to_string(), "lea    0xeb7(%rip),%rax");
+        assert_eq!(instruction.rip_relative_addr, Some(0x402004));
+    }
+
+    #[test]
+    fn test_calls_and_jumps() {
+        let call = decode(&[0xe8, 0xe6, 0xfe, 0xff, 0xff], 0x401155);
+        assert_eq!(call.to_string(), "call   0x401040");
+        assert_eq!(call.target, Some(0x401040));
+        assert_eq!(disassemble(&[0xff, 0xd0]), ("call   *%rax".to_string(), 2));
+        let jump = decode(&[0x7e, 0xf0], 0x401000);
+        assert_eq!(jump.to_string(), "jle    0x400ff2");
+    }
+
+    #[test]
+    fn test_sse() {
+        assert_eq!(
+            disassemble(&[0xf2, 0x0f, 0x10, 0x45, 0xf8]),
+            ("movsd  -0x8(%rbp),%xmm0".to_string(), 5)
+        );
+        assert_eq!(disassemble(&[0x66, 0x0f, 0xef, 0xc0]), ("pxor   %xmm0,%xmm0".to_string(), 4));
+        assert_eq!(disassemble(&[0x66, 0x48, 0x0f, 0x6e, 0xc0]), ("movq   %rax,%xmm0".to_string(), 5));
+    }
+
+    #[test]
+    fn test_unknown_instruction() {
+        assert_eq!(disassemble(&[0x0f, 0xff]), ("(bad)".to_string(), 1));
+    }
+}
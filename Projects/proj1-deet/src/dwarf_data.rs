@@ -1,6 +1,8 @@
+use crate::examine;
 use crate::gimli_wrapper;
 use addr2line::Context;
 use object::Object;
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::{fmt, fs};
 
@@ -12,7 +14,14 @@ pub enum Error {
 
 pub struct DwarfData {
     files: Vec<File>,
+    /// Every type described in the program, by the offset of its DIE
+    types: HashMap<TypeId, Type>,
     addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    /// Link-time address of the program's entry point, from the ELF header
+    entry_point: usize,
+    /// Difference between where the program is loaded and its link-time addresses. It is 0
+    /// unless the program is position-independent.
+    load_bias: usize,
 }
 
 impl fmt::Debug for DwarfData {
@@ -38,32 +47,93 @@ impl DwarfData {
         } else {
             gimli::RunTimeEndian::Big
         };
+        let (files, types) = gimli_wrapper::load_file(&object, endian)?;
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
+            files,
+            types,
             addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            entry_point: object.entry() as usize,
+            load_bias: 0,
         })
     }
 
-    #[allow(dead_code)]
+    pub fn entry_point(&self) -> usize {
+        self.entry_point
+    }
+
+    pub fn load_bias(&self) -> usize {
+        self.load_bias
+    }
+
+    /// Relocates all the addresses we hand out to where the program is loaded in memory, so
+    /// that the rest of deet only has to deal with run-time addresses.
+    pub fn set_load_bias(&mut self, load_bias: usize) {
+        let delta = load_bias.wrapping_sub(self.load_bias);
+        let relocate = |var: &mut Variable| var.location.relocate(delta);
+        for file in &mut self.files {
+            file.global_variables.iter_mut().for_each(relocate);
+            for func in &mut file.functions {
+                func.address = func.address.wrapping_add(delta);
+                // static locals live at fixed addresses, and location lists are keyed on pc
+                func.parameters.iter_mut().for_each(relocate);
+                func.variables.iter_mut().for_each(relocate);
+                if let Some(frame_base) = &mut func.frame_base {
+                    frame_base.relocate(delta);
+                }
+                func.scopes.iter_mut().for_each(|scope| scope.relocate(delta));
+            }
+            for line in &mut file.lines {
+                line.address = line.address.wrapping_add(delta);
+            }
+        }
+        self.load_bias = load_bias;
+    }
+
+    /// Whether the compilation unit `f` is the one the user means by `file`: either its full
+    /// name, or a suffix of it that starts at a path component (e.g. `foo.c` or `src/foo.c`).
+    fn file_matches(f: &File, file: &str) -> bool {
+        f.name == file || f.name.ends_with(&format!("/{}", file))
+    }
+
     fn get_target_file(&self, file: &str) -> Option<&File> {
-        self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
+        self.files.iter().find(|f| f.name == file).or_else(|| {
+            self.files.iter().find(|f| DwarfData::file_matches(f, file))
         })
     }
 
+    /// Returns the full names of all the source files that `file` could refer to, so that
+    /// ambiguous names can be reported.
+    pub fn get_matching_files(&self, file: &str) -> Vec<&str> {
+        if let Some(f) = self.files.iter().find(|f| f.name == file) {
+            return vec![&f.name];
+        }
+        self.files
+            .iter()
+            .filter(|f| DwarfData::file_matches(f, file))
+            .map(|f| f.name.as_str())
+            .collect()
+    }
+
+    /// Returns the address of the first instruction of `line_number`, or of the next line that
+    /// has code if that line has none.
     #[allow(dead_code)]
     pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
         let target_file = match file {
             Some(filename) => self.get_target_file(filename)?,
             None => self.files.get(0)?,
         };
-        Some(
-            target_file
-                .lines
-                .iter()
-                .find(|line| line.number >= line_number)?
-                .address,
-        )
+        let number = target_file
+            .lines
+            .iter()
+            .map(|line| line.number)
+            .filter(|&number| number >= line_number)
+            .min()?;
+        target_file
+            .lines
+            .iter()
+            .filter(|line| line.number == number)
+            .map(|line| line.address)
+            .min()
     }
 
     #[allow(dead_code)]
@@ -91,7 +161,7 @@ impl DwarfData {
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
         let location = self
             .addr2line
-            .find_location(curr_addr.try_into().unwrap())
+            .find_location(curr_addr.wrapping_sub(self.load_bias).try_into().unwrap())
             .ok()??;
         Some(Line {
             file: location.file?.to_string(),
@@ -104,13 +174,148 @@ impl DwarfData {
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
         let frame = self
             .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
+            .find_frames(curr_addr.wrapping_sub(self.load_bias).try_into().unwrap())
             .ok()?
             .next()
             .ok()??;
         Some(frame.function?.raw_name().ok()?.to_string())
     }
 
+    #[allow(dead_code)]
+    pub fn get_function_at_addr(&self, curr_addr: usize) -> Option<&Function> {
+        self.files.iter().flat_map(|file| file.functions.iter()).find(|func| {
+            func.address <= curr_addr && curr_addr < func.address + func.text_length
+        })
+    }
+
+    /// Describes the functions whose code is at `curr_addr`, innermost first: one for each
+    /// function inlined there, then the function they were inlined into. Each one's line is
+    /// where it was executing, which for callers of inlined functions is the line of the call.
+    pub fn get_inlined_frames(&self, curr_addr: usize) -> Vec<(Option<String>, Option<Line>)> {
+        let mut inlined_frames = Vec::new();
+        let mut frames = match self
+            .addr2line
+            .find_frames(curr_addr.wrapping_sub(self.load_bias).try_into().unwrap())
+        {
+            Ok(frames) => frames,
+            Err(_) => return inlined_frames,
+        };
+        while let Ok(Some(frame)) = frames.next() {
+            let func_name = frame
+                .function
+                .and_then(|func| func.raw_name().ok().map(|name| name.to_string()));
+            let line = frame.location.and_then(|location| {
+                Some(Line {
+                    file: location.file?.to_string(),
+                    number: location.line?.try_into().unwrap(),
+                    address: curr_addr,
+                })
+            });
+            inlined_frames.push((func_name, line));
+        }
+        inlined_frames
+    }
+
+    /// Collects the (parameters, local variables) in scope at `curr_addr`, innermost scope
+    /// first. Where functions were inlined, `inline_depth` picks the function as in
+    /// get_inlined_frames: each one only sees its own variables.
+    pub fn get_frame_variables(&self, curr_addr: usize, inline_depth: usize) -> Option<(Vec<&Variable>, Vec<&Variable>)> {
+        let func = self.get_function_at_addr(curr_addr)?;
+        let mut scopes: Vec<&Scope> = Vec::new();
+        let mut children = &func.scopes;
+        while let Some(scope) = children.iter().find(|scope| scope.contains(curr_addr)) {
+            scopes.push(scope);
+            children = &scope.scopes;
+        }
+        // The scopes belonging to the selected function run from its inlined call (or the
+        // function itself) to the next inlined call inside it
+        let calls: Vec<usize> = (0..scopes.len()).filter(|&i| scopes[i].inlined.is_some()).collect();
+        let level = calls.len().saturating_sub(inline_depth);
+        let start = if level == 0 { 0 } else { calls[level - 1] };
+        let end = calls.get(level).cloned().unwrap_or(scopes.len());
+
+        let mut parameters = Vec::new();
+        let mut variables = Vec::new();
+        for scope in scopes[start..end].iter().rev() {
+            parameters.extend(scope.parameters.iter());
+            variables.extend(scope.variables.iter());
+        }
+        if level == 0 {
+            parameters.extend(func.parameters.iter());
+            variables.extend(func.variables.iter());
+        }
+        Some((parameters, variables))
+    }
+
+    /// Looks up a variable visible from `curr_addr` in the function picked by `inline_depth`
+    /// (see get_frame_variables): its locals are searched innermost scope first, then its
+    /// parameters, then the global variables.
+    pub fn get_variable(&self, curr_addr: usize, inline_depth: usize, name: &str) -> Option<&Variable> {
+        if let Some((parameters, variables)) = self.get_frame_variables(curr_addr, inline_depth) {
+            if let Some(var) = variables.into_iter().chain(parameters).find(|var| var.name == name) {
+                return Some(var);
+            }
+        }
+        self.files
+            .iter()
+            .flat_map(|file| file.global_variables.iter())
+            .find(|var| var.name == name)
+    }
+
+    /// Returns the full description of a struct type that was only referred to by its `id`.
+    pub fn complete_type(&self, entity_type: &Type) -> Type {
+        if entity_type.encoding != TypeEncoding::Struct || !entity_type.members.is_empty() {
+            return entity_type.clone();
+        }
+        let declared = match entity_type.id.and_then(|id| self.types.get(&id)) {
+            Some(declared) => declared,
+            None => return entity_type.clone(),
+        };
+        // a file that only declares a struct refers to the definition in another file
+        let full = if declared.members.is_empty() {
+            self.types
+                .values()
+                .find(|full| full.name == declared.name && !full.members.is_empty())
+        } else {
+            Some(declared)
+        };
+        match full {
+            // keep the name it was referred to by, which may be a typedef
+            Some(full) => Type {
+                name: entity_type.name.clone(),
+                ..full.clone()
+            },
+            None => entity_type.clone(),
+        }
+    }
+
+    /// Looks up an enumerator (like `RED` in `enum color { RED, GREEN }`), returning its enum
+    /// type and value.
+    pub fn get_enumerator(&self, name: &str) -> Option<(&Type, i64)> {
+        self.types.values().find_map(|entity_type| {
+            entity_type
+                .enumerators
+                .iter()
+                .find(|(enumerator, _)| enumerator == name)
+                .map(|(_, val)| (entity_type, *val))
+        })
+    }
+
+    /// Looks up a type by the name the program gives it, e.g. `struct point`.
+    pub fn get_type_by_name(&self, name: &str) -> Option<&Type> {
+        self.types.values().find(|entity_type| entity_type.name == name)
+    }
+
+    /// Looks up a type written the way it would be in C, e.g. `unsigned int` or `struct point *`.
+    pub fn lookup_type(&self, name: &str) -> Option<Type> {
+        let base = name.trim_end_matches(|c: char| c == '*' || c.is_whitespace());
+        let mut entity_type = Type::from_c_name(base).or_else(|| self.get_type_by_name(base).cloned())?;
+        for _ in name[base.len()..].matches('*') {
+            entity_type = entity_type.pointer_to();
+        }
+        Some(entity_type)
+    }
+
     #[allow(dead_code)]
     pub fn print(&self) {
         for file in &self.files {
@@ -132,12 +337,21 @@ impl DwarfData {
                     "  * {} (declared on line {}, located at {:#x}, {} bytes long)",
                     func.name, func.line_number, func.address, func.text_length
                 );
+                for var in &func.parameters {
+                    println!(
+                        "    * Parameter: {} ({}, located at {}, declared at line {})",
+                        var.name, var.entity_type.name, var.location, var.line_number
+                    );
+                }
                 for var in &func.variables {
                     println!(
                         "    * Variable: {} ({}, located at {}, declared at line {})",
                         var.name, var.entity_type.name, var.location, var.line_number
                     );
                 }
+                for scope in &func.scopes {
+                    scope.print(2);
+                }
             }
 
             println!("Line numbers:");
@@ -148,32 +362,424 @@ impl DwarfData {
     }
 }
 
+/// How the bytes of a value are interpreted. Base types take this from DW_AT_encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TypeEncoding {
+    Signed,
+    Unsigned,
+    SignedChar,
+    UnsignedChar,
+    Boolean,
+    Float,
+    Pointer,
+    Array,
+    /// A struct or a union
+    Struct,
+    Enum,
+    Function,
+    Void,
+    #[default]
+    Unknown,
+}
+
+/// Identifies a type by the offset of its DIE in .debug_info
+pub type TypeId = usize;
+
 #[derive(Debug, Clone, Default)]
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub encoding: TypeEncoding,
+    /// The type a pointer points to, or the type of an array's elements
+    pub target: Option<Box<Type>>,
+    /// Fields of a struct. Structs can contain pointers to themselves, so wherever one type
+    /// refers to a struct, the struct is only described by its name, size and `id`, and
+    /// DwarfData::complete_type has to be used to get its members.
+    pub members: Vec<Member>,
+    pub id: Option<TypeId>,
+    /// Names and values of an enum's enumerators
+    pub enumerators: Vec<(String, i64)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    /// Offset of the field from the start of the struct
+    pub offset: usize,
+    pub entity_type: Type,
 }
 
 impl Type {
-    pub fn new(name: String, size: usize) -> Self {
+    pub fn new(name: String, size: usize, encoding: TypeEncoding) -> Self {
         Type {
-            name: name,
-            size: size,
+            name,
+            size,
+            encoding,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the type of a pointer to this type.
+    pub fn pointer_to(self) -> Type {
+        let name = if self.encoding == TypeEncoding::Function {
+            // int (*)(int) rather than int (int) *
+            self.name.replacen("(", "(*)(", 1)
+        } else if self.name.ends_with('*') {
+            // char ** rather than char * *
+            format!("{}*", self.name)
+        } else {
+            format!("{} *", self.name)
+        };
+        Type {
+            name,
+            size: 8,
+            encoding: TypeEncoding::Pointer,
+            target: Some(Box::new(self)),
+            ..Default::default()
+        }
+    }
+
+    /// Whether values of this type are numbers (including characters, booleans and pointers),
+    /// rather than arrays, structs or something we don't understand.
+    pub fn is_scalar(&self) -> bool {
+        !matches!(
+            self.encoding,
+            TypeEncoding::Array
+                | TypeEncoding::Struct
+                | TypeEncoding::Function
+                | TypeEncoding::Void
+                | TypeEncoding::Unknown
+        )
+    }
+
+    /// Whether this is a pointer to characters, which is shown as the string it points to.
+    pub fn is_string(&self) -> bool {
+        match (self.encoding, &self.target) {
+            (TypeEncoding::Pointer, Some(target)) => {
+                matches!(target.encoding, TypeEncoding::SignedChar | TypeEncoding::UnsignedChar)
+            }
+            _ => false,
+        }
+    }
+
+    /// Formats a value of this type, given the (little-endian) bytes read from the inferior.
+    pub fn format_value(&self, bytes: &[u8]) -> String {
+        self.format_value_with(bytes, &|_| None)
+    }
+
+    /// Formats a value of this type like format_value, showing the strings that `char *`s point
+    /// to using `read_string`, which returns the string at an address as a C string literal.
+    pub fn format_value_with(&self, bytes: &[u8], read_string: &dyn Fn(usize) -> Option<String>) -> String {
+        let bytes = &bytes[..self.size.min(bytes.len())];
+        match self.encoding {
+            TypeEncoding::Signed => format!("{}", read_signed(bytes)),
+            TypeEncoding::Unsigned => format!("{}", read_unsigned(bytes)),
+            TypeEncoding::SignedChar => {
+                let val = read_signed(bytes);
+                format!("{} {}", val, format_char(val as u8))
+            }
+            TypeEncoding::UnsignedChar => {
+                let val = read_unsigned(bytes);
+                format!("{} {}", val, format_char(val as u8))
+            }
+            TypeEncoding::Boolean => {
+                if read_unsigned(bytes) == 0 {
+                    "false".to_string()
+                } else {
+                    "true".to_string()
+                }
+            }
+            TypeEncoding::Float => match bytes.len() {
+                4 => format!("{}", f32::from_bits(read_unsigned(bytes) as u32)),
+                8 => format!("{}", f64::from_bits(read_unsigned(bytes))),
+                // x87 extended precision (long double), padded to 16 bytes
+                10 | 16 => format!("{}", x87_to_f64(&bytes[..10])),
+                _ => format!("<{}-byte float>", bytes.len()),
+            },
+            TypeEncoding::Pointer => {
+                let addr = read_unsigned(bytes);
+                let string = if addr != 0 && self.is_string() {
+                    read_string(addr as usize)
+                } else {
+                    None
+                };
+                match string {
+                    Some(string) => format!("{:#x} {}", addr, string),
+                    None => format!("{:#x}", addr),
+                }
+            }
+            TypeEncoding::Array => self.format_array(bytes, read_string),
+            TypeEncoding::Struct if self.members.is_empty() => "{...}".to_string(),
+            TypeEncoding::Struct => {
+                let fields: Vec<String> = self
+                    .members
+                    .iter()
+                    .map(|member| {
+                        let end = (member.offset + member.entity_type.size).min(bytes.len());
+                        let bytes = &bytes[member.offset.min(end)..end];
+                        format!("{} = {}", member.name, member.entity_type.format_value_with(bytes, read_string))
+                    })
+                    .collect();
+                format!("{{{}}}", fields.join(", "))
+            }
+            TypeEncoding::Enum => {
+                let val = self.integer_value(bytes).unwrap();
+                match self.enumerators.iter().find(|(_, enumerator)| *enumerator == val) {
+                    Some((name, _)) => name.clone(),
+                    None => format!("{}", val),
+                }
+            }
+            TypeEncoding::Function => format!("{{{}}}", self.name),
+            TypeEncoding::Void => "void".to_string(),
+            TypeEncoding::Unknown => {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("<{}: 0x{}>", self.name, hex.join(""))
+            }
+        }
+    }
+
+    /// Formats an array as `{1, 2, 3}`, or a string literal if it holds characters. Like gdb,
+    /// only the first 200 elements are shown, and long runs of the same value are abbreviated.
+    fn format_array(&self, bytes: &[u8], read_string: &dyn Fn(usize) -> Option<String>) -> String {
+        const MAX_ELEMENTS: usize = 200;
+        const REPEAT_THRESHOLD: usize = 10;
+        let element = self.target.as_ref().unwrap();
+        if matches!(element.encoding, TypeEncoding::SignedChar | TypeEncoding::UnsignedChar) {
+            // the unused space after a string is usually zeroed, so leave trailing NULs out
+            let len = bytes.iter().rposition(|&byte| byte != 0).map_or(0, |last| last + 1);
+            let ellipsis = if len > MAX_ELEMENTS { "..." } else { "" };
+            return format!("{}{}", examine::format_string(&bytes[..len.min(MAX_ELEMENTS)]), ellipsis);
+        }
+        let elements: Vec<String> = bytes
+            .chunks(element.size.max(1))
+            .take(MAX_ELEMENTS)
+            .map(|bytes| element.format_value_with(bytes, read_string))
+            .collect();
+        let mut parts = Vec::new();
+        let mut i = 0;
+        while i < elements.len() {
+            let run = elements[i..].iter().take_while(|val| **val == elements[i]).count();
+            if run > REPEAT_THRESHOLD {
+                parts.push(format!("{} <repeats {} times>", elements[i], run));
+            } else {
+                parts.extend_from_slice(&elements[i..i + run]);
+            }
+            i += run;
+        }
+        if bytes.len() > MAX_ELEMENTS * element.size {
+            parts.push("...".to_string());
+        }
+        format!("{{{}}}", parts.join(", "))
+    }
+
+    /// Converts an integer to the bytes of a value of this type, as C converts values on
+    /// assignment. Returns None if this isn't an arithmetic or pointer type.
+    pub fn encode_integer(&self, val: i64) -> Option<Vec<u8>> {
+        let val = match self.encoding {
+            TypeEncoding::Float => return self.encode_float(val as f64),
+            TypeEncoding::Boolean => (val != 0) as i64,
+            _ if !self.is_scalar() => return None,
+            _ => val,
+        };
+        let mut bytes = val.to_le_bytes().to_vec();
+        bytes.resize(self.size, if val < 0 { 0xff } else { 0 });
+        Some(bytes)
+    }
+
+    /// Converts a floating point number to the bytes of a value of this type, as C converts
+    /// values on assignment. Returns None if this isn't an arithmetic or pointer type.
+    pub fn encode_float(&self, val: f64) -> Option<Vec<u8>> {
+        let mut bytes = match (self.encoding, self.size) {
+            (TypeEncoding::Float, 4) => (val as f32).to_bits().to_le_bytes().to_vec(),
+            (TypeEncoding::Float, 8) => val.to_bits().to_le_bytes().to_vec(),
+            (TypeEncoding::Float, 10) | (TypeEncoding::Float, 16) => f64_to_x87(val).to_vec(),
+            (TypeEncoding::Float, _) => return None,
+            (TypeEncoding::Boolean, _) => return self.encode_integer((val != 0.0) as i64),
+            _ if !self.is_scalar() => return None,
+            _ => return self.encode_integer(val as i64),
+        };
+        bytes.resize(self.size, 0);
+        Some(bytes)
+    }
+
+    /// Looks up a C base type by name, e.g. `unsigned int`.
+    pub fn from_c_name(name: &str) -> Option<Type> {
+        let name = name.split_whitespace().collect::<Vec<&str>>().join(" ");
+        let (size, encoding) = match name.as_str() {
+            // like gcc, treat void as having a size of 1 for pointer arithmetic
+            "void" => (1, TypeEncoding::Void),
+            "char" | "signed char" => (1, TypeEncoding::SignedChar),
+            "unsigned char" => (1, TypeEncoding::UnsignedChar),
+            "short" | "short int" | "signed short" => (2, TypeEncoding::Signed),
+            "unsigned short" | "short unsigned int" => (2, TypeEncoding::Unsigned),
+            "int" | "signed" | "signed int" => (4, TypeEncoding::Signed),
+            "unsigned" | "unsigned int" => (4, TypeEncoding::Unsigned),
+            "long" | "long int" | "long long" | "long long int" => (8, TypeEncoding::Signed),
+            "unsigned long" | "long unsigned int" | "unsigned long long" => (8, TypeEncoding::Unsigned),
+            "bool" | "_Bool" => (1, TypeEncoding::Boolean),
+            "float" => (4, TypeEncoding::Float),
+            "double" => (8, TypeEncoding::Float),
+            "long double" => (16, TypeEncoding::Float),
+            _ => return None,
+        };
+        Some(Type::new(name, size, encoding))
+    }
+
+    /// Interprets a value of this type as an integer. Returns None unless it is an integer, a
+    /// character, a boolean or a pointer.
+    pub fn integer_value(&self, bytes: &[u8]) -> Option<i64> {
+        let bytes = &bytes[..self.size.min(bytes.len())];
+        match self.encoding {
+            TypeEncoding::Signed | TypeEncoding::SignedChar => Some(read_signed(bytes)),
+            TypeEncoding::Enum => match &self.target {
+                Some(target) if target.encoding == TypeEncoding::Signed => Some(read_signed(bytes)),
+                _ => Some(read_unsigned(bytes) as i64),
+            },
+            TypeEncoding::Unsigned
+            | TypeEncoding::UnsignedChar
+            | TypeEncoding::Boolean
+            | TypeEncoding::Pointer => Some(read_unsigned(bytes) as i64),
+            _ => None,
+        }
+    }
+
+    /// Interprets a value of this type as a floating point number, if it is one.
+    pub fn float_value(&self, bytes: &[u8]) -> Option<f64> {
+        let bytes = &bytes[..self.size.min(bytes.len())];
+        match (self.encoding, bytes.len()) {
+            (TypeEncoding::Float, 4) => Some(f32::from_bits(read_unsigned(bytes) as u32) as f64),
+            (TypeEncoding::Float, 8) => Some(f64::from_bits(read_unsigned(bytes))),
+            (TypeEncoding::Float, 10) | (TypeEncoding::Float, 16) => Some(x87_to_f64(&bytes[..10])),
+            _ => None,
         }
     }
 }
 
+fn read_unsigned(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .take(8)
+        .rev()
+        .fold(0, |acc, &byte| (acc << 8) | byte as u64)
+}
+
+fn read_signed(bytes: &[u8]) -> i64 {
+    let len = bytes.len().min(8);
+    if len == 0 {
+        return 0;
+    }
+    // sign-extend from the top bit of the value
+    let shift = 64 - 8 * len as u32;
+    ((read_unsigned(bytes) << shift) as i64) >> shift
+}
+
+fn format_char(c: u8) -> String {
+    match c {
+        0 => "'\\0'".to_string(),
+        b'\n' => "'\\n'".to_string(),
+        b'\t' => "'\\t'".to_string(),
+        b'\r' => "'\\r'".to_string(),
+        b'\'' => "'\\''".to_string(),
+        b'\\' => "'\\\\'".to_string(),
+        0x20..=0x7e => format!("'{}'", c as char),
+        _ => format!("'\\{:03o}'", c),
+    }
+}
+
+/// Converts an 80-bit x87 extended precision float to the nearest f64.
+fn x87_to_f64(bytes: &[u8]) -> f64 {
+    let mantissa = read_unsigned(&bytes[..8]);
+    let sign_exp = read_unsigned(&bytes[8..10]) as u16;
+    let sign = if sign_exp & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (sign_exp & 0x7fff) as i32;
+    if exponent == 0 && mantissa == 0 {
+        return sign * 0.0;
+    }
+    if exponent == 0x7fff {
+        return if mantissa << 1 == 0 {
+            sign * f64::INFINITY
+        } else {
+            f64::NAN
+        };
+    }
+    // The x87 format has an explicit integer bit, so the mantissa is read as 1.63 fixed point
+    sign * (mantissa as f64 / (1u64 << 63) as f64) * 2f64.powi(exponent - 16383)
+}
+
+/// Converts an f64 to 80-bit x87 extended precision, which represents every f64 exactly.
+fn f64_to_x87(val: f64) -> [u8; 10] {
+    let bits = val.to_bits();
+    let sign = ((bits >> 63) as u16) << 15;
+    let exponent = ((bits >> 52) & 0x7ff) as i32;
+    let fraction = bits & ((1 << 52) - 1);
+    let (exponent, mantissa) = match exponent {
+        0 if fraction == 0 => (0, 0),
+        // subnormal f64s are normal x87 numbers, once the leading 1 is shifted up to the integer bit
+        0 => {
+            let shift = fraction.leading_zeros() as i32;
+            ((63 - shift - 1074 + 16383) as u16, fraction << shift)
+        }
+        0x7ff => (0x7fff, (1 << 63) | (fraction << 11)),
+        _ => ((exponent - 1023 + 16383) as u16, (1 << 63) | (fraction << 11)),
+    };
+    let mut bytes = [0; 10];
+    bytes[..8].copy_from_slice(&mantissa.to_le_bytes());
+    bytes[8..].copy_from_slice(&(sign | exponent).to_le_bytes());
+    bytes
+}
+
 #[derive(Clone)]
 pub enum Location {
     Address(usize),
     FramePointerOffset(isize),
+    /// Any other DWARF location expression, evaluated against the stopped program when the
+    /// variable is needed (see the location module)
+    Expression(Vec<u8>, gimli::Encoding),
+    /// A location list: the variable's whereabouts depend on the pc, so each entry is only
+    /// valid for pcs in [start, end)
+    List(Vec<(usize, usize, Location)>),
+    /// The compiler folded the variable into a constant (DW_AT_const_value)
+    Value(Vec<u8>),
+    /// The variable no longer exists in the compiled program
+    OptimizedOut,
+}
+
+impl Location {
+    /// Moves every address in this location by delta. Addresses inside expressions are left
+    /// alone; those get relocated when the expression is evaluated.
+    fn relocate(&mut self, delta: usize) {
+        match self {
+            Location::Address(addr) => *addr = addr.wrapping_add(delta),
+            Location::List(entries) => {
+                for (start, end, location) in entries {
+                    *start = start.wrapping_add(delta);
+                    *end = end.wrapping_add(delta);
+                    location.relocate(delta);
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             Location::Address(addr) => write!(f, "Address({:#x})", addr),
             Location::FramePointerOffset(offset) => write!(f, "FramePointerOffset({})", offset),
+            Location::Expression(bytes, _) => write!(f, "Expression({} bytes)", bytes.len()),
+            Location::List(entries) => {
+                write!(f, "List[")?;
+                for (i, (start, end, location)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:#x}-{:#x}: {}", start, end, location)?;
+                }
+                write!(f, "]")
+            }
+            Location::Value(bytes) => write!(f, "Value({:02x?})", bytes),
+            Location::OptimizedOut => write!(f, "OptimizedOut"),
         }
     }
 }
@@ -199,7 +805,73 @@ pub struct Function {
     pub address: usize,
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
+    pub return_type: Option<Type>,
+    /// Where DW_OP_fbreg offsets are measured from (DW_AT_frame_base)
+    pub frame_base: Option<Location>,
+    pub parameters: Vec<Variable>,
     pub variables: Vec<Variable>,
+    /// Lexical blocks and inlined calls, whose variables aren't in scope everywhere
+    pub scopes: Vec<Scope>,
+}
+
+/// A lexical block, or a call to an inlined function, within a function. Its variables are
+/// only in scope while the pc is in one of its ranges.
+#[derive(Debug, Default, Clone)]
+pub struct Scope {
+    /// For a call to an inlined function, the function's name
+    pub inlined: Option<String>,
+    /// The address ranges [start, end) of the scope's code
+    pub ranges: Vec<(usize, usize)>,
+    /// The parameters of an inlined function
+    pub parameters: Vec<Variable>,
+    pub variables: Vec<Variable>,
+    pub scopes: Vec<Scope>,
+}
+
+impl Scope {
+    pub fn contains(&self, addr: usize) -> bool {
+        self.ranges.iter().any(|&(start, end)| start <= addr && addr < end)
+    }
+
+    fn relocate(&mut self, delta: usize) {
+        for (start, end) in &mut self.ranges {
+            *start = start.wrapping_add(delta);
+            *end = end.wrapping_add(delta);
+        }
+        for var in self.parameters.iter_mut().chain(self.variables.iter_mut()) {
+            var.location.relocate(delta);
+        }
+        self.scopes.iter_mut().for_each(|scope| scope.relocate(delta));
+    }
+
+    fn print(&self, indent: usize) {
+        let ranges: Vec<String> = self
+            .ranges
+            .iter()
+            .map(|(start, end)| format!("{:#x}-{:#x}", start, end))
+            .collect();
+        match &self.inlined {
+            Some(name) => println!("{:indent$}* Inlined {} (at {})", "", name, ranges.join(", "), indent = indent * 2),
+            None => println!("{:indent$}* Block (at {})", "", ranges.join(", "), indent = indent * 2),
+        }
+        for (kind, vars) in &[("Parameter", &self.parameters), ("Variable", &self.variables)] {
+            for var in vars.iter() {
+                println!(
+                    "{:indent$}* {}: {} ({}, located at {}, declared at line {})",
+                    "",
+                    kind,
+                    var.name,
+                    var.entity_type.name,
+                    var.location,
+                    var.line_number,
+                    indent = indent * 2 + 2
+                );
+            }
+        }
+        for scope in &self.scopes {
+            scope.print(indent + 1);
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -224,3 +896,46 @@ impl fmt::Display for Line {
 }
 
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_value() {
+        let int = Type::from_c_name("int").unwrap();
+        let array = |element: Type, count: usize| Type {
+            name: format!("{} [{}]", element.name, count),
+            size: element.size * count,
+            encoding: TypeEncoding::Array,
+            target: Some(Box::new(element)),
+            ..Default::default()
+        };
+        let color = Type {
+            name: "enum color".to_string(),
+            size: 4,
+            encoding: TypeEncoding::Enum,
+            enumerators: vec![("RED".to_string(), 0), ("GREEN".to_string(), 5)],
+            ..Default::default()
+        };
+        let point = Type {
+            name: "struct point".to_string(),
+            size: 12,
+            encoding: TypeEncoding::Struct,
+            members: vec![
+                Member { name: "x".to_string(), offset: 0, entity_type: int.clone() },
+                Member { name: "c".to_string(), offset: 4, entity_type: color },
+                Member { name: "tag".to_string(), offset: 8, entity_type: array(Type::from_c_name("char").unwrap(), 4) },
+            ],
+            ..Default::default()
+        };
+        let bytes = [3, 0, 0, 0, 5, 0, 0, 0, b'h', b'i', 0, 0];
+        assert_eq!(point.format_value(&bytes), "{x = 3, c = GREEN, tag = \"hi\"}");
+        assert_eq!(array(int.clone(), 12).format_value(&[0; 48]), "{0 <repeats 12 times>}");
+        let mut bytes = vec![0; 12];
+        bytes[4] = 7;
+        assert_eq!(array(int.clone(), 3).format_value(&bytes), "{0, 7, 0}");
+        assert_eq!(int.pointer_to().pointer_to().name, "int **");
+        assert_eq!(Type::new("int (int)".to_string(), 1, TypeEncoding::Function).pointer_to().name, "int (*)(int)");
+    }
+}
@@ -0,0 +1,140 @@
+//! Formatting for the `x` (examine memory) command, which shows memory as gdb's `x/NFU` does: N
+//! units of U bytes each, in format F.
+
+use crate::dwarf_data::{Type, TypeEncoding};
+
+/// Like gdb, don't show more than 200 characters of a string
+pub const MAX_STRING_LEN: usize = 200;
+
+/// How `x` displays each unit of memory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Hex,
+    Decimal,
+    Unsigned,
+    Octal,
+    Binary,
+    Char,
+    /// A NUL-terminated string; the unit size is ignored
+    String,
+    /// Machine instructions; the unit size is ignored
+    Instruction,
+}
+
+impl Format {
+    fn from_letter(letter: char) -> Option<Format> {
+        match letter {
+            'x' => Some(Format::Hex),
+            'd' => Some(Format::Decimal),
+            'u' => Some(Format::Unsigned),
+            'o' => Some(Format::Octal),
+            't' => Some(Format::Binary),
+            'c' => Some(Format::Char),
+            's' => Some(Format::String),
+            'i' => Some(Format::Instruction),
+            _ => None,
+        }
+    }
+}
+
+/// The `/NFU` part of an `x` command. Whatever is left out is taken from the previous `x`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ExamineSpec {
+    pub count: Option<usize>,
+    pub format: Option<Format>,
+    pub unit: Option<usize>,
+}
+
+impl ExamineSpec {
+    /// Parses the text following the slash in `x/NFU`: an optional count followed by format and
+    /// unit letters in either order.
+    pub fn parse(spec: &str) -> Option<ExamineSpec> {
+        let digits = spec.chars().take_while(|c| c.is_ascii_digit()).count();
+        let mut parsed = ExamineSpec::default();
+        if digits > 0 {
+            parsed.count = Some(spec[..digits].parse().ok()?);
+        }
+        for letter in spec[digits..].chars() {
+            match letter {
+                'b' => parsed.unit = Some(1),
+                'h' => parsed.unit = Some(2),
+                'w' => parsed.unit = Some(4),
+                'g' => parsed.unit = Some(8),
+                _ => parsed.format = Some(Format::from_letter(letter)?),
+            }
+        }
+        Some(parsed)
+    }
+}
+
+/// How many units `x` shows on each line
+pub fn units_per_line(format: Format, unit: usize) -> usize {
+    match (format, unit) {
+        (Format::Char, _) | (_, 1) | (_, 2) => 8,
+        (Format::Binary, 8) => 1,
+        (Format::Binary, _) | (_, 8) => 2,
+        _ => 4,
+    }
+}
+
+/// Formats one unit of memory (given as little-endian bytes).
+pub fn format_unit(bytes: &[u8], format: Format) -> String {
+    let val = bytes.iter().rev().fold(0u64, |acc, &byte| (acc << 8) | byte as u64);
+    match format {
+        Format::Hex => format!("{:#0width$x}", val, width = 2 + 2 * bytes.len()),
+        Format::Decimal => Type::new(String::new(), bytes.len(), TypeEncoding::Signed).format_value(bytes),
+        Format::Octal if val == 0 => "0".to_string(),
+        Format::Octal => format!("0{:o}", val),
+        Format::Binary => format!("{:0width$b}", val, width = 8 * bytes.len()),
+        Format::Char => Type::new(String::new(), 1, TypeEncoding::SignedChar).format_value(bytes),
+        Format::Unsigned | Format::String | Format::Instruction => format!("{}", val),
+    }
+}
+
+/// Formats the bytes of a C string (without its terminating NUL) as a string literal.
+pub fn format_string(bytes: &[u8]) -> String {
+    let mut literal = String::from("\"");
+    for &byte in bytes {
+        match byte {
+            b'"' => literal.push_str("\\\""),
+            b'\\' => literal.push_str("\\\\"),
+            b'\n' => literal.push_str("\\n"),
+            b'\t' => literal.push_str("\\t"),
+            b'\r' => literal.push_str("\\r"),
+            0x20..=0x7e => literal.push(byte as char),
+            _ => literal.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        assert_eq!(
+            ExamineSpec::parse("16xb"),
+            Some(ExamineSpec { count: Some(16), format: Some(Format::Hex), unit: Some(1) })
+        );
+        assert_eq!(
+            ExamineSpec::parse("gx"),
+            Some(ExamineSpec { count: None, format: Some(Format::Hex), unit: Some(8) })
+        );
+        assert_eq!(ExamineSpec::parse("s").unwrap().format, Some(Format::String));
+        assert_eq!(ExamineSpec::parse("4q"), None);
+    }
+
+    #[test]
+    fn test_format_unit() {
+        assert_eq!(format_unit(&[1, 0, 0, 0], Format::Hex), "0x00000001");
+        assert_eq!(format_unit(&[0xff, 0xff], Format::Decimal), "-1");
+        assert_eq!(format_unit(&[0xff, 0xff], Format::Unsigned), "65535");
+        assert_eq!(format_unit(&[8], Format::Octal), "010");
+        assert_eq!(format_unit(&[5], Format::Binary), "00000101");
+        assert_eq!(format_unit(b"A", Format::Char), "65 'A'");
+        assert_eq!(format_string(b"hi\n\"x\""), "\"hi\\n\\\"x\\\"\"");
+    }
+}
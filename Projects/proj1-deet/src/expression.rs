@@ -0,0 +1,1068 @@
+use crate::dwarf_data::{DwarfData, Type, TypeEncoding};
+use crate::inferior::Inferior;
+use crate::location::Place;
+use crate::registers;
+use crate::unwind::Frame;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// An expression typed by the user, in C syntax: the argument of `print`, the condition of a
+/// breakpoint (`i == 42 && j < 3`), the address given to `x` (`&arr[2]`) and so on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    Integer(i64),
+    Float(f64),
+    Char(u8),
+    Variable(String),
+    /// A register of the selected frame, e.g. `$rsp`
+    Register(String),
+    /// A value printed earlier
+    History(HistoryRef),
+    Unary(UnaryOp, Box<Expression>),
+    Binary(BinaryOp, Box<Expression>, Box<Expression>),
+    Cast(String, Box<Expression>),
+    SizeofType(String),
+    Index(Box<Expression>, Box<Expression>),
+    Member(Box<Expression>, String),
+    PointerMember(Box<Expression>, String),
+    /// `{type} addr`: the memory at an address, viewed as a value of the given type
+    At(String, Box<Expression>),
+}
+
+/// Which entry of the value history an expression refers to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HistoryRef {
+    /// `$n`, numbered from 1
+    Absolute(usize),
+    /// `$` is the last value, `$$` the one before it and `$$n` the one n before the last
+    Relative(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOp {
+    Not,
+    Negate,
+    BitNot,
+    Deref,
+    AddressOf,
+    Sizeof,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+/// Precedence of unary operators and casts, which bind tighter than any binary operator
+const UNARY_PRECEDENCE: u8 = 11;
+/// Precedence of postfix operators, variables and literals
+const POSTFIX_PRECEDENCE: u8 = 12;
+
+/// Words that can only start a type name, so `(` followed by one of them has to be a cast
+const TYPE_KEYWORDS: [&str; 16] = [
+    "char", "short", "int", "long", "unsigned", "signed", "float", "double", "void", "_Bool",
+    "bool", "struct", "union", "enum", "const", "volatile",
+];
+
+impl UnaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Not => "!",
+            UnaryOp::Negate => "-",
+            UnaryOp::BitNot => "~",
+            UnaryOp::Deref => "*",
+            UnaryOp::AddressOf => "&",
+            UnaryOp::Sizeof => "sizeof ",
+        }
+    }
+}
+
+impl BinaryOp {
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::BitOr => 3,
+            BinaryOp::BitXor => 4,
+            BinaryOp::BitAnd => 5,
+            BinaryOp::Eq | BinaryOp::Ne => 6,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 7,
+            BinaryOp::Shl | BinaryOp::Shr => 8,
+            BinaryOp::Add | BinaryOp::Sub => 9,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 10,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitXor => "^",
+            BinaryOp::BitOr => "|",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
+
+    fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge | BinaryOp::Eq | BinaryOp::Ne
+        )
+    }
+}
+
+/// The result of evaluating an expression: a value of some type, which may be a copy of memory
+/// in the inferior.
+#[derive(Clone, Debug)]
+pub struct Value {
+    pub entity_type: Type,
+    /// The value, in the inferior's (little-endian) representation
+    pub bytes: Vec<u8>,
+    /// Where the value was read from, if it is an lvalue like a variable or `*p`
+    pub address: Option<usize>,
+}
+
+impl Value {
+    fn new(entity_type: Type, bytes: Vec<u8>) -> Value {
+        Value {
+            entity_type,
+            bytes,
+            address: None,
+        }
+    }
+
+    fn from_integer(val: i64, entity_type: Type) -> Value {
+        let bytes = entity_type.encode_integer(val).unwrap_or_default();
+        Value::new(entity_type, bytes)
+    }
+
+    fn from_float(val: f64, entity_type: Type) -> Value {
+        let bytes = entity_type.encode_float(val).unwrap_or_default();
+        Value::new(entity_type, bytes)
+    }
+
+    fn from_bool(val: bool) -> Value {
+        Value::from_integer(val as i64, integer_type(4, true))
+    }
+
+    pub fn is_true(&self) -> Result<bool, String> {
+        match self.entity_type.float_value(&self.bytes) {
+            Some(val) => Ok(val != 0.0),
+            None => Ok(self.integer()? != 0),
+        }
+    }
+
+    fn integer(&self) -> Result<i64, String> {
+        self.entity_type
+            .integer_value(&self.bytes)
+            .ok_or_else(|| format!("Value of type `{}' is not a number.", self.entity_type.name))
+    }
+
+    fn float(&self) -> Result<f64, String> {
+        match self.entity_type.float_value(&self.bytes) {
+            Some(val) => Ok(val),
+            None => Ok(self.integer()? as f64),
+        }
+    }
+
+    /// Arrays used as values turn into pointers to their first element, as they do in C.
+    fn decay(self) -> Value {
+        match (self.entity_type.encoding, self.address) {
+            (TypeEncoding::Array, Some(addr)) => {
+                let element = *self.entity_type.target.unwrap();
+                Value::from_integer(addr as i64, element.pointer_to())
+            }
+            _ => self,
+        }
+    }
+
+    /// Interprets the value as an address. Arrays and structs stand for their own address.
+    pub fn as_address(&self) -> Result<usize, String> {
+        match (self.entity_type.encoding, self.address) {
+            (TypeEncoding::Array, Some(addr)) | (TypeEncoding::Struct, Some(addr)) => Ok(addr),
+            (TypeEncoding::Float, _) => Err("Value can't be converted to an address.".to_string()),
+            _ => Ok(self.integer()? as usize),
+        }
+    }
+
+    /// Converts the value to the bytes of a value of type `ty`, for assigning it to a variable of
+    /// that type.
+    pub fn encode(&self, ty: &Type) -> Option<Vec<u8>> {
+        if self.entity_type.name == ty.name && self.entity_type.size == ty.size {
+            return Some(self.bytes.clone());
+        }
+        match self.entity_type.float_value(&self.bytes) {
+            Some(val) => ty.encode_float(val),
+            None => ty.encode_integer(self.clone().decay().integer().ok()?),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.entity_type.format_value(&self.bytes))
+    }
+}
+
+/// What an expression is evaluated against
+pub struct Context<'a> {
+    pub inferior: &'a Inferior,
+    pub debug_data: &'a DwarfData,
+    /// The stack frame whose variables and registers are visible
+    pub frame: &'a Frame,
+    /// Values printed so far, which `$n` refers to
+    pub history: &'a [Value],
+}
+
+impl Expression {
+    fn precedence(&self) -> u8 {
+        match self {
+            Expression::Binary(op, _, _) => op.precedence(),
+            Expression::Unary(_, _)
+            | Expression::Cast(_, _)
+            | Expression::SizeofType(_)
+            | Expression::At(_, _) => UNARY_PRECEDENCE,
+            _ => POSTFIX_PRECEDENCE,
+        }
+    }
+
+    /// Writes the expression, parenthesized if it binds less tightly than `min_precedence`.
+    fn fmt_with_precedence(&self, f: &mut fmt::Formatter<'_>, min_precedence: u8) -> fmt::Result {
+        let parenthesize = self.precedence() < min_precedence;
+        if parenthesize {
+            write!(f, "(")?;
+        }
+        match self {
+            Expression::Binary(op, lhs, rhs) => {
+                lhs.fmt_with_precedence(f, op.precedence())?;
+                write!(f, " {} ", op.symbol())?;
+                rhs.fmt_with_precedence(f, op.precedence() + 1)?;
+            }
+            Expression::Unary(op, expr) => {
+                write!(f, "{}", op.symbol())?;
+                expr.fmt_with_precedence(f, UNARY_PRECEDENCE)?;
+            }
+            Expression::Cast(type_name, expr) => {
+                write!(f, "({}) ", type_name)?;
+                expr.fmt_with_precedence(f, UNARY_PRECEDENCE)?;
+            }
+            Expression::At(type_name, expr) => {
+                write!(f, "{{{}}} ", type_name)?;
+                expr.fmt_with_precedence(f, UNARY_PRECEDENCE)?;
+            }
+            Expression::SizeofType(type_name) => write!(f, "sizeof ({})", type_name)?,
+            Expression::Index(expr, index) => {
+                expr.fmt_with_precedence(f, POSTFIX_PRECEDENCE)?;
+                write!(f, "[{}]", index)?;
+            }
+            Expression::Member(expr, name) => {
+                expr.fmt_with_precedence(f, POSTFIX_PRECEDENCE)?;
+                write!(f, ".{}", name)?;
+            }
+            Expression::PointerMember(expr, name) => {
+                expr.fmt_with_precedence(f, POSTFIX_PRECEDENCE)?;
+                write!(f, "->{}", name)?;
+            }
+            Expression::Integer(val) => write!(f, "{}", val)?,
+            Expression::Float(val) => write!(f, "{:?}", val)?,
+            Expression::Char(c) => match c {
+                b'\'' | b'\\' => write!(f, "'\\{}'", *c as char)?,
+                0x20..=0x7e => write!(f, "'{}'", *c as char)?,
+                _ => write!(f, "'\\{:o}'", c)?,
+            },
+            Expression::Variable(name) => write!(f, "{}", name)?,
+            Expression::Register(name) => write!(f, "${}", name)?,
+            Expression::History(HistoryRef::Absolute(n)) => write!(f, "${}", n)?,
+            Expression::History(HistoryRef::Relative(0)) => write!(f, "$")?,
+            Expression::History(HistoryRef::Relative(1)) => write!(f, "$$")?,
+            Expression::History(HistoryRef::Relative(n)) => write!(f, "$${}", n)?,
+        }
+        if parenthesize {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with_precedence(f, 0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Integer(i64),
+    Float(f64),
+    Char(u8),
+    Ident(String),
+    /// `$` and whatever name follows it
+    Dollar(String),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 28] = [
+    // two-character operators come first, so that `<=` isn't read as `<` `=`
+    "==", "!=", "<=", ">=", "&&", "||", "<<", ">>", "->", "<", ">", "!", "~", "+", "-", "*", "/",
+    "%", "&", "|", "^", "(", ")", "[", "]", "{", "}", ".",
+];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit())) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            tokens.push(parse_number(&text)?);
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c == '$' {
+            let start = i + 1;
+            i += 1;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$') {
+                i += 1;
+            }
+            tokens.push(Token::Dollar(chars[start..i].iter().collect()));
+        } else if c == '\'' {
+            let (byte, len) = parse_char(&chars[i + 1..]).ok_or("Unmatched single quote.")?;
+            i += 1 + len;
+            tokens.push(Token::Char(byte));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    i += op.len();
+                    tokens.push(Token::Op(op));
+                }
+                None => return Err(format!("Invalid character '{}' in expression.", c)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_number(text: &str) -> Result<Token, String> {
+    let lower = text.to_lowercase();
+    let result = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex.trim_end_matches(['u', 'l']), 16)
+            .map(Token::Integer)
+            .ok()
+    } else if lower.contains('.') || lower.contains('e') {
+        lower.parse().map(Token::Float).ok()
+    } else {
+        lower.trim_end_matches(['u', 'l']).parse().map(Token::Integer).ok()
+    };
+    result.ok_or_else(|| format!("Invalid number \"{}\".", text))
+}
+
+/// Parses the inside of a character literal, up to and including the closing quote. Returns the
+/// character and the number of chars used.
+fn parse_char(chars: &[char]) -> Option<(u8, usize)> {
+    let (byte, len) = match chars {
+        ['\\', 'n', ..] => (b'\n', 2),
+        ['\\', 't', ..] => (b'\t', 2),
+        ['\\', 'r', ..] => (b'\r', 2),
+        ['\\', '0', ..] => (0, 2),
+        ['\\', c, ..] => (*c as u8, 2),
+        [c, ..] if *c != '\'' && c.is_ascii() => (*c as u8, 1),
+        _ => return None,
+    };
+    match chars.get(len) {
+        Some('\'') => Some((byte, len + 1)),
+        _ => None,
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, op: &'static str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Op(next)) if next == op => Ok(()),
+            _ => Err(format!("Missing '{}' in expression.", op)),
+        }
+    }
+
+    fn peek_binary_op(&self) -> Option<BinaryOp> {
+        let op = match self.peek()? {
+            Token::Op("*") => BinaryOp::Mul,
+            Token::Op("/") => BinaryOp::Div,
+            Token::Op("%") => BinaryOp::Rem,
+            Token::Op("+") => BinaryOp::Add,
+            Token::Op("-") => BinaryOp::Sub,
+            Token::Op("<<") => BinaryOp::Shl,
+            Token::Op(">>") => BinaryOp::Shr,
+            Token::Op("<") => BinaryOp::Lt,
+            Token::Op("<=") => BinaryOp::Le,
+            Token::Op(">") => BinaryOp::Gt,
+            Token::Op(">=") => BinaryOp::Ge,
+            Token::Op("==") => BinaryOp::Eq,
+            Token::Op("!=") => BinaryOp::Ne,
+            Token::Op("&") => BinaryOp::BitAnd,
+            Token::Op("^") => BinaryOp::BitXor,
+            Token::Op("|") => BinaryOp::BitOr,
+            Token::Op("&&") => BinaryOp::And,
+            Token::Op("||") => BinaryOp::Or,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// Parses binary operators by precedence climbing; all of them are left-associative.
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expression, String> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(op) = self.peek_binary_op() {
+            if op.precedence() < min_precedence {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.parse_binary(op.precedence() + 1)?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    /// Tries to read a type name (like `unsigned int` or `struct node *`) followed by `close`.
+    /// Returns the name, and whether it has to be a type rather than a parenthesized variable.
+    fn parse_type_name(&mut self, close: &'static str) -> Option<(String, bool)> {
+        let start = self.pos;
+        let mut words = Vec::new();
+        while let Some(Token::Ident(word)) = self.peek() {
+            words.push(word.clone());
+            self.pos += 1;
+        }
+        let mut pointers = String::new();
+        while let Some(Token::Op("*")) = self.peek() {
+            pointers.push('*');
+            self.pos += 1;
+        }
+        if words.is_empty() || self.next() != Some(Token::Op(close)) {
+            self.pos = start;
+            return None;
+        }
+        let is_type = TYPE_KEYWORDS.contains(&words[0].as_str()) || words.len() > 1 || !pointers.is_empty();
+        if pointers.is_empty() {
+            Some((words.join(" "), is_type))
+        } else {
+            Some((format!("{} {}", words.join(" "), pointers), is_type))
+        }
+    }
+
+    /// Whether the next token can start an operand, which tells `(ulong_t) x`, a cast to a
+    /// typedef, apart from `(x) + 1`.
+    fn at_operand(&self) -> bool {
+        match self.peek() {
+            Some(Token::Op(op)) => ["(", "!", "~", "{"].contains(op),
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, String> {
+        let start = self.pos;
+        let op = match self.peek() {
+            Some(Token::Op("!")) => UnaryOp::Not,
+            Some(Token::Op("-")) => UnaryOp::Negate,
+            Some(Token::Op("~")) => UnaryOp::BitNot,
+            Some(Token::Op("*")) => UnaryOp::Deref,
+            Some(Token::Op("&")) => UnaryOp::AddressOf,
+            Some(Token::Op("+")) => {
+                self.pos += 1;
+                return self.parse_unary();
+            }
+            Some(Token::Ident(word)) if word == "sizeof" => {
+                self.pos += 1;
+                if let Some(Token::Op("(")) = self.peek() {
+                    self.pos += 1;
+                    match self.parse_type_name(")") {
+                        Some((type_name, true)) => return Ok(Expression::SizeofType(type_name)),
+                        _ => self.pos = start + 1,
+                    }
+                }
+                UnaryOp::Sizeof
+            }
+            Some(Token::Op("(")) => {
+                self.pos += 1;
+                match self.parse_type_name(")") {
+                    Some((type_name, is_type)) if is_type || self.at_operand() => {
+                        return Ok(Expression::Cast(type_name, Box::new(self.parse_unary()?)));
+                    }
+                    _ => {
+                        self.pos = start;
+                        return self.parse_postfix();
+                    }
+                }
+            }
+            Some(Token::Op("{")) => {
+                self.pos += 1;
+                let (type_name, _) = self.parse_type_name("}").ok_or("Expected a type name in '{}'.")?;
+                return Ok(Expression::At(type_name, Box::new(self.parse_unary()?)));
+            }
+            _ => return self.parse_postfix(),
+        };
+        if op != UnaryOp::Sizeof {
+            self.pos += 1;
+        }
+        Ok(Expression::Unary(op, Box::new(self.parse_unary()?)))
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expression, String> {
+        let mut expr = self.parse_primary()?;
+        loop {
+            match self.peek() {
+                Some(Token::Op("[")) => {
+                    self.pos += 1;
+                    let index = self.parse_binary(0)?;
+                    self.expect("]")?;
+                    expr = Expression::Index(Box::new(expr), Box::new(index));
+                }
+                Some(Token::Op(op)) if *op == "." || *op == "->" => {
+                    let op = *op;
+                    self.pos += 1;
+                    let name = match self.next() {
+                        Some(Token::Ident(name)) => name,
+                        _ => return Err(format!("Expected a member name after \"{}\".", op)),
+                    };
+                    expr = if op == "." {
+                        Expression::Member(Box::new(expr), name)
+                    } else {
+                        Expression::PointerMember(Box::new(expr), name)
+                    };
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, String> {
+        match self.next() {
+            Some(Token::Integer(val)) => Ok(Expression::Integer(val)),
+            Some(Token::Float(val)) => Ok(Expression::Float(val)),
+            Some(Token::Char(c)) => Ok(Expression::Char(c)),
+            Some(Token::Ident(name)) => Ok(Expression::Variable(name)),
+            Some(Token::Dollar(name)) => Ok(parse_dollar(name)),
+            Some(Token::Op("(")) => {
+                let expr = self.parse_binary(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Op(op)) => Err(format!("Unexpected \"{}\" in expression.", op)),
+            None => Err("Incomplete expression.".to_string()),
+        }
+    }
+}
+
+/// Works out what `$name` refers to: a register, or an entry of the value history.
+fn parse_dollar(name: String) -> Expression {
+    let mut chars = name.chars();
+    match chars.next() {
+        None => Expression::History(HistoryRef::Relative(0)),
+        Some('$') => match chars.as_str() {
+            "" => Expression::History(HistoryRef::Relative(1)),
+            back => match back.parse() {
+                Ok(back) => Expression::History(HistoryRef::Relative(back)),
+                Err(_) => Expression::Register(name),
+            },
+        },
+        Some(_) => match name.parse() {
+            Ok(n) => Expression::History(HistoryRef::Absolute(n)),
+            Err(_) => Expression::Register(name),
+        },
+    }
+}
+
+impl Expression {
+    pub fn parse(input: &str) -> Result<Expression, String> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            pos: 0,
+        };
+        let expr = parser.parse_binary(0)?;
+        if parser.pos < parser.tokens.len() {
+            return Err(format!("Junk at end of expression: {:?}", parser.tokens[parser.pos]));
+        }
+        Ok(expr)
+    }
+
+    /// Evaluates the expression in the context of a stack frame of the inferior.
+    pub fn evaluate(&self, ctx: &Context) -> Result<Value, String> {
+        match self {
+            Expression::Integer(val) => {
+                let size = if *val as i32 as i64 == *val { 4 } else { 8 };
+                Ok(Value::from_integer(*val, integer_type(size, true)))
+            }
+            Expression::Float(val) => Ok(Value::from_float(*val, Type::from_c_name("double").unwrap())),
+            Expression::Char(c) => Ok(Value::from_integer(*c as i64, Type::from_c_name("char").unwrap())),
+            Expression::Variable(name) => read_variable(name, ctx),
+            Expression::Register(name) => read_register(name, ctx),
+            Expression::History(history_ref) => {
+                let index = match *history_ref {
+                    HistoryRef::Absolute(n) => n.checked_sub(1),
+                    HistoryRef::Relative(back) => ctx.history.len().checked_sub(back + 1),
+                };
+                match index.and_then(|index| ctx.history.get(index)) {
+                    Some(value) => Ok(value.clone()),
+                    None if ctx.history.is_empty() => Err("History is empty.".to_string()),
+                    None => Err(format!("History has not yet reached {}.", self)),
+                }
+            }
+            Expression::Unary(UnaryOp::Sizeof, expr) => match (&**expr, expr.evaluate(ctx)) {
+                // sizeof (node_t) looks just like the size of a parenthesized variable
+                (Expression::Variable(name), Err(err)) => {
+                    let size = ctx.debug_data.lookup_type(name).ok_or(err)?.size;
+                    Ok(Value::from_integer(size as i64, integer_type(8, false)))
+                }
+                (_, value) => evaluate_unary(UnaryOp::Sizeof, value?, ctx),
+            },
+            Expression::Unary(op, expr) => evaluate_unary(*op, expr.evaluate(ctx)?, ctx),
+            Expression::Binary(BinaryOp::And, lhs, rhs) => {
+                // && and || short-circuit like they do in C
+                let val = lhs.evaluate(ctx)?.is_true()? && rhs.evaluate(ctx)?.is_true()?;
+                Ok(Value::from_bool(val))
+            }
+            Expression::Binary(BinaryOp::Or, lhs, rhs) => {
+                let val = lhs.evaluate(ctx)?.is_true()? || rhs.evaluate(ctx)?.is_true()?;
+                Ok(Value::from_bool(val))
+            }
+            Expression::Binary(op, lhs, rhs) => evaluate_binary(*op, lhs.evaluate(ctx)?, rhs.evaluate(ctx)?),
+            Expression::Cast(type_name, expr) => {
+                let entity_type = lookup_type(type_name, ctx)?;
+                let value = expr.evaluate(ctx)?.decay();
+                if !entity_type.is_scalar() || !value.entity_type.is_scalar() {
+                    return Err("Invalid cast.".to_string());
+                }
+                match value.entity_type.float_value(&value.bytes) {
+                    Some(val) if entity_type.encoding == TypeEncoding::Float => Ok(Value::from_float(val, entity_type)),
+                    Some(val) => Ok(Value::from_integer(val as i64, entity_type)),
+                    None => Ok(Value::from_integer(value.integer()?, entity_type)),
+                }
+            }
+            Expression::SizeofType(type_name) => {
+                let size = lookup_type(type_name, ctx)?.size;
+                Ok(Value::from_integer(size as i64, integer_type(8, false)))
+            }
+            Expression::At(type_name, expr) => {
+                let entity_type = lookup_type(type_name, ctx)?;
+                read_memory(expr.evaluate(ctx)?.as_address()?, entity_type, ctx)
+            }
+            Expression::Index(expr, index) => {
+                let value = expr.evaluate(ctx)?;
+                let index = index.evaluate(ctx)?.integer()?;
+                let element = match (value.entity_type.encoding, &value.entity_type.target) {
+                    (TypeEncoding::Array, Some(element)) | (TypeEncoding::Pointer, Some(element)) => {
+                        ctx.debug_data.complete_type(element)
+                    }
+                    _ => return Err(format!("cannot subscript something of type `{}'", value.entity_type.name)),
+                };
+                let offset = index * element.size as i64;
+                match (value.entity_type.encoding, value.address) {
+                    // arrays from the value history are no longer in memory
+                    (TypeEncoding::Array, None) => {
+                        let start = offset as usize;
+                        match value.bytes.get(start..start + element.size) {
+                            Some(bytes) => Ok(Value::new(element, bytes.to_vec())),
+                            None => Err(format!("no such vector element: {}", index)),
+                        }
+                    }
+                    _ => read_memory((value.as_address()? as i64 + offset) as usize, element, ctx),
+                }
+            }
+            Expression::Member(expr, name) => member(expr.evaluate(ctx)?, name, ctx),
+            Expression::PointerMember(expr, name) => {
+                let value = expr.evaluate(ctx)?;
+                if value.entity_type.encoding != TypeEncoding::Pointer {
+                    return Err(
+                        "Attempt to extract a component of a value that is not a structure pointer.".to_string()
+                    );
+                }
+                member(evaluate_unary(UnaryOp::Deref, value, ctx)?, name, ctx)
+            }
+        }
+    }
+}
+
+/// Returns the signed or unsigned integer type of the given size that C promotes to.
+fn integer_type(size: usize, signed: bool) -> Type {
+    let name = match (size, signed) {
+        (8, true) => "long",
+        (8, false) => "unsigned long",
+        (_, true) => "int",
+        (_, false) => "unsigned int",
+    };
+    Type::from_c_name(name).unwrap()
+}
+
+/// Works out the type both operands of an arithmetic operator are converted to, following C's
+/// usual arithmetic conversions.
+fn common_type(lhs: &Type, rhs: &Type) -> Result<Type, String> {
+    let operands = [lhs, rhs];
+    if let Some(ty) = operands.iter().find(|ty| !ty.is_scalar()) {
+        return Err(format!("Argument to arithmetic operation not a number: `{}'", ty.name));
+    }
+    let float = operands.iter().filter(|ty| ty.encoding == TypeEncoding::Float).max_by_key(|ty| ty.size);
+    if let Some(float) = float {
+        return Ok((*float).clone());
+    }
+    // anything smaller than an int is promoted to int; pointers compare like unsigned longs
+    let promote = |ty: &Type| match ty.encoding {
+        _ if ty.size < 4 => (4, true),
+        TypeEncoding::Signed => (ty.size, true),
+        _ => (ty.size, false),
+    };
+    let (lhs, rhs) = (promote(lhs), promote(rhs));
+    let size = lhs.0.max(rhs.0);
+    // if the operands are the same size and either is unsigned, so is the result
+    let signed = [lhs, rhs].iter().all(|(ty_size, signed)| *signed || *ty_size < size);
+    Ok(integer_type(size, signed))
+}
+
+/// Converts an integer to an integer type of the given size, truncating or extending it.
+fn convert_integer(val: i64, entity_type: &Type) -> i64 {
+    let shift = 64 - 8 * entity_type.size.min(8) as u32;
+    if entity_type.encoding == TypeEncoding::Signed {
+        (val << shift) >> shift
+    } else {
+        ((val << shift) as u64 >> shift) as i64
+    }
+}
+
+fn evaluate_unary(op: UnaryOp, value: Value, ctx: &Context) -> Result<Value, String> {
+    match op {
+        UnaryOp::Not => Ok(Value::from_bool(!value.is_true()?)),
+        UnaryOp::Negate => evaluate_binary(BinaryOp::Sub, Value::from_integer(0, integer_type(4, true)), value),
+        UnaryOp::BitNot => evaluate_binary(BinaryOp::BitXor, value, Value::from_integer(-1, integer_type(4, true))),
+        UnaryOp::AddressOf => match value.address {
+            Some(addr) => Ok(Value::from_integer(addr as i64, value.entity_type.pointer_to())),
+            None => Err("Attempt to take address of value not located in memory.".to_string()),
+        },
+        UnaryOp::Sizeof => Ok(Value::from_integer(value.entity_type.size as i64, integer_type(8, false))),
+        UnaryOp::Deref => {
+            let value = value.decay();
+            let target = match (value.entity_type.encoding, &value.entity_type.target) {
+                (TypeEncoding::Pointer, Some(target)) if target.encoding != TypeEncoding::Void => {
+                    ctx.debug_data.complete_type(target)
+                }
+                _ => return Err("Attempt to take contents of a non-pointer value.".to_string()),
+            };
+            read_memory(value.as_address()?, target, ctx)
+        }
+    }
+}
+
+fn evaluate_binary(op: BinaryOp, lhs: Value, rhs: Value) -> Result<Value, String> {
+    let (lhs, rhs) = (lhs.decay(), rhs.decay());
+    let lhs_pointer = lhs.entity_type.encoding == TypeEncoding::Pointer;
+    let rhs_pointer = rhs.entity_type.encoding == TypeEncoding::Pointer;
+    // pointer arithmetic counts in elements rather than bytes
+    let element_size = |pointer: &Value| pointer.entity_type.target.as_ref().map_or(1, |target| target.size.max(1)) as i64;
+    match (op, lhs_pointer, rhs_pointer) {
+        (BinaryOp::Add, true, false) | (BinaryOp::Sub, true, false) => {
+            let offset = rhs.integer()?.wrapping_mul(element_size(&lhs));
+            let offset = if op == BinaryOp::Sub { offset.wrapping_neg() } else { offset };
+            return Ok(Value::from_integer(lhs.integer()?.wrapping_add(offset), lhs.entity_type));
+        }
+        (BinaryOp::Add, false, true) => return evaluate_binary(op, rhs, lhs),
+        (BinaryOp::Sub, true, true) => {
+            let diff = lhs.integer()?.wrapping_sub(rhs.integer()?) / element_size(&lhs);
+            return Ok(Value::from_integer(diff, integer_type(8, true)));
+        }
+        _ => {}
+    }
+
+    let result_type = common_type(&lhs.entity_type, &rhs.entity_type)?;
+    if result_type.encoding == TypeEncoding::Float {
+        let (lhs, rhs) = (lhs.float()?, rhs.float()?);
+        let val = match op {
+            BinaryOp::Mul => lhs * rhs,
+            BinaryOp::Div => lhs / rhs,
+            BinaryOp::Add => lhs + rhs,
+            BinaryOp::Sub => lhs - rhs,
+            BinaryOp::Lt => return Ok(Value::from_bool(lhs < rhs)),
+            BinaryOp::Le => return Ok(Value::from_bool(lhs <= rhs)),
+            BinaryOp::Gt => return Ok(Value::from_bool(lhs > rhs)),
+            BinaryOp::Ge => return Ok(Value::from_bool(lhs >= rhs)),
+            BinaryOp::Eq => return Ok(Value::from_bool(lhs == rhs)),
+            BinaryOp::Ne => return Ok(Value::from_bool(lhs != rhs)),
+            _ => return Err("Integer only operation.".to_string()),
+        };
+        return Ok(Value::from_float(val, result_type));
+    }
+
+    let (lhs, rhs) = (
+        convert_integer(lhs.integer()?, &result_type),
+        convert_integer(rhs.integer()?, &result_type),
+    );
+    let signed = result_type.encoding == TypeEncoding::Signed;
+    if op.is_comparison() {
+        let ordering = if signed {
+            lhs.cmp(&rhs)
+        } else {
+            (lhs as u64).cmp(&(rhs as u64))
+        };
+        let val = match op {
+            BinaryOp::Lt => ordering == Ordering::Less,
+            BinaryOp::Le => ordering != Ordering::Greater,
+            BinaryOp::Gt => ordering == Ordering::Greater,
+            BinaryOp::Ge => ordering != Ordering::Less,
+            BinaryOp::Eq => ordering == Ordering::Equal,
+            _ => ordering != Ordering::Equal,
+        };
+        return Ok(Value::from_bool(val));
+    }
+    if (op == BinaryOp::Div || op == BinaryOp::Rem) && rhs == 0 {
+        return Err("Division by zero".to_string());
+    }
+    let val = match op {
+        BinaryOp::Mul => lhs.wrapping_mul(rhs),
+        BinaryOp::Div if signed => lhs.wrapping_div(rhs),
+        BinaryOp::Div => ((lhs as u64) / (rhs as u64)) as i64,
+        BinaryOp::Rem if signed => lhs.wrapping_rem(rhs),
+        BinaryOp::Rem => ((lhs as u64) % (rhs as u64)) as i64,
+        BinaryOp::Add => lhs.wrapping_add(rhs),
+        BinaryOp::Sub => lhs.wrapping_sub(rhs),
+        BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+        BinaryOp::Shr if signed => lhs.wrapping_shr(rhs as u32),
+        BinaryOp::Shr => (lhs as u64).wrapping_shr(rhs as u32) as i64,
+        BinaryOp::BitAnd => lhs & rhs,
+        BinaryOp::BitXor => lhs ^ rhs,
+        _ => lhs | rhs,
+    };
+    Ok(Value::from_integer(val, result_type))
+}
+
+fn member(value: Value, name: &str, ctx: &Context) -> Result<Value, String> {
+    if value.entity_type.encoding != TypeEncoding::Struct {
+        return Err("Attempt to extract a component of a value that is not a structure.".to_string());
+    }
+    let struct_type = ctx.debug_data.complete_type(&value.entity_type);
+    let member = struct_type
+        .members
+        .iter()
+        .find(|member| member.name == name)
+        .ok_or_else(|| format!("There is no member named {}.", name))?;
+    let end = member.offset + member.entity_type.size;
+    Ok(Value {
+        entity_type: member.entity_type.clone(),
+        bytes: value.bytes.get(member.offset..end).unwrap_or_default().to_vec(),
+        address: value.address.map(|addr| addr + member.offset),
+    })
+}
+
+fn lookup_type(name: &str, ctx: &Context) -> Result<Type, String> {
+    ctx.debug_data
+        .lookup_type(name)
+        .ok_or_else(|| format!("No symbol \"{}\" in current context.", name))
+}
+
+fn read_memory(addr: usize, entity_type: Type, ctx: &Context) -> Result<Value, String> {
+    let bytes = ctx
+        .inferior
+        .read_memory(addr, entity_type.size)
+        .map_err(|_| format!("Cannot access memory at address {:#x}", addr))?;
+    Ok(Value {
+        entity_type,
+        bytes,
+        address: Some(addr),
+    })
+}
+
+fn read_variable(name: &str, ctx: &Context) -> Result<Value, String> {
+    let var = match ctx.debug_data.get_variable(ctx.frame.lookup_pc, ctx.frame.inline_depth, name) {
+        Some(var) => var,
+        None => {
+            return match ctx.debug_data.get_enumerator(name) {
+                Some((entity_type, val)) => Ok(Value::from_integer(val, entity_type.clone())),
+                None => Err(format!("No symbol \"{}\" in current context.", name)),
+            }
+        }
+    };
+    match ctx.inferior.locate_variable(var, ctx.frame, ctx.debug_data)? {
+        Place::Memory(addr) => read_memory(addr, var.entity_type.clone(), ctx),
+        // kept in registers or computed, so there's nothing to take the address of
+        place => Ok(Value {
+            entity_type: var.entity_type.clone(),
+            bytes: ctx.inferior.read_place(&place, var.entity_type.size)?,
+            address: None,
+        }),
+    }
+}
+
+/// Reads a register. Only %rip, %rbp and %rsp are known for frames other than the innermost
+/// one, so the others always come from the innermost frame.
+fn read_register(name: &str, ctx: &Context) -> Result<Value, String> {
+    let val = match name {
+        "pc" | "rip" => ctx.frame.pc as u64,
+        "fp" | "rbp" => ctx.frame.rbp as u64,
+        "sp" | "rsp" => ctx.frame.rsp as u64,
+        _ => {
+            let regs = ctx.inferior.get_registers().map_err(|err| err.to_string())?;
+            registers::get_register(&regs, name).ok_or_else(|| format!("Invalid register `{}'", name))?
+        }
+    };
+    let entity_type = match name {
+        "pc" | "rip" => Type::new("void ()".to_string(), 1, TypeEncoding::Function).pointer_to(),
+        "sp" | "rsp" | "fp" | "rbp" => Type::from_c_name("void").unwrap().pointer_to(),
+        _ => integer_type(8, true),
+    };
+    Ok(Value::from_integer(val as i64, entity_type))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn var(name: &str) -> Box<Expression> {
+        Box::new(Expression::Variable(name.to_string()))
+    }
+
+    fn int(val: i64) -> Value {
+        Value::from_integer(val, integer_type(4, true))
+    }
+
+    #[test]
+    fn test_parse_comparison() {
+        assert_eq!(
+            Expression::parse("i == 42"),
+            Ok(Expression::Binary(BinaryOp::Eq, var("i"), Box::new(Expression::Integer(42))))
+        );
+        assert_eq!(
+            Expression::parse("x>=0x10"),
+            Ok(Expression::Binary(BinaryOp::Ge, var("x"), Box::new(Expression::Integer(16))))
+        );
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let expr = Expression::parse("a < 1 || b == 2 && !c").unwrap();
+        assert_eq!(expr.to_string(), "a < 1 || b == 2 && !c");
+        match expr {
+            Expression::Binary(BinaryOp::Or, _, rhs) => match *rhs {
+                Expression::Binary(BinaryOp::And, _, _) => {}
+                other => panic!("unexpected rhs {:?}", other),
+            },
+            other => panic!("unexpected expression {:?}", other),
+        }
+        let expr = Expression::parse("(a || b) && !(c == 1.5)").unwrap();
+        assert_eq!(expr.to_string(), "(a || b) && !(c == 1.5)");
+        assert_eq!(Expression::parse("-x < -1").unwrap().to_string(), "-x < -1");
+        assert_eq!(Expression::parse("a + b*c - (d - e)").unwrap().to_string(), "a + b * c - (d - e)");
+        assert_eq!(Expression::parse("1 << 2 + 3 & 4").unwrap().to_string(), "1 << 2 + 3 & 4");
+    }
+
+    #[test]
+    fn test_parse_postfix_and_casts() {
+        let expr = Expression::parse("*p->next[2].x").unwrap();
+        assert_eq!(expr.to_string(), "*p->next[2].x");
+        assert!(matches!(expr, Expression::Unary(UnaryOp::Deref, _)));
+        assert_eq!(Expression::parse("(unsigned long)&arr").unwrap().to_string(), "(unsigned long) &arr");
+        assert_eq!(Expression::parse("(struct node*)p").unwrap().to_string(), "(struct node *) p");
+        assert_eq!(Expression::parse("(ulong_t) x").unwrap().to_string(), "(ulong_t) x");
+        assert_eq!(Expression::parse("(x) - 1").unwrap().to_string(), "x - 1");
+        assert_eq!(Expression::parse("{int} $rsp + 8").unwrap().to_string(), "{int} $rsp + 8");
+        assert_eq!(Expression::parse("sizeof(int) + sizeof x").unwrap().to_string(), "sizeof (int) + sizeof x");
+        assert_eq!(
+            Expression::parse("c == '\\n'"),
+            Ok(Expression::Binary(BinaryOp::Eq, var("c"), Box::new(Expression::Char(b'\n'))))
+        );
+    }
+
+    #[test]
+    fn test_parse_dollar() {
+        assert_eq!(Expression::parse("$rsp"), Ok(Expression::Register("rsp".to_string())));
+        assert_eq!(Expression::parse("$"), Ok(Expression::History(HistoryRef::Relative(0))));
+        assert_eq!(Expression::parse("$$"), Ok(Expression::History(HistoryRef::Relative(1))));
+        assert_eq!(Expression::parse("$$3"), Ok(Expression::History(HistoryRef::Relative(3))));
+        assert_eq!(Expression::parse("$2"), Ok(Expression::History(HistoryRef::Absolute(2))));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Expression::parse("").is_err());
+        assert!(Expression::parse("i ==").is_err());
+        assert!(Expression::parse("(i == 1").is_err());
+        assert!(Expression::parse("i == 1 2").is_err());
+        assert!(Expression::parse("i # 2").is_err());
+        assert!(Expression::parse("a[1").is_err());
+        assert!(Expression::parse("'ab'").is_err());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let eval = |op, lhs, rhs| evaluate_binary(op, lhs, rhs).map(|val: Value| val.to_string());
+        assert_eq!(eval(BinaryOp::Add, int(2), int(3)), Ok("5".to_string()));
+        assert_eq!(eval(BinaryOp::Div, int(-7), int(2)), Ok("-3".to_string()));
+        assert_eq!(eval(BinaryOp::Rem, int(1), int(0)), Err("Division by zero".to_string()));
+        let unsigned = Value::from_integer(1, integer_type(4, false));
+        // -1 converts to the largest unsigned int, as in C
+        assert_eq!(eval(BinaryOp::Lt, int(-1), unsigned.clone()), Ok("0".to_string()));
+        assert_eq!(eval(BinaryOp::Sub, unsigned, int(2)), Ok("4294967295".to_string()));
+        let half = Value::from_float(0.5, Type::from_c_name("double").unwrap());
+        assert_eq!(eval(BinaryOp::Mul, int(3), half.clone()), Ok("1.5".to_string()));
+        assert_eq!(eval(BinaryOp::Eq, int(2), Value::from_float(2.0, half.entity_type.clone())), Ok("1".to_string()));
+        let inf = Value::from_float(f64::INFINITY, half.entity_type.clone());
+        assert_eq!(eval(BinaryOp::Eq, inf.clone(), inf), Ok("1".to_string()));
+        assert!(eval(BinaryOp::Shl, half, int(1)).is_err());
+        let pointer = Value::from_integer(0x1000, integer_type(4, true).pointer_to());
+        assert_eq!(eval(BinaryOp::Add, int(2), pointer.clone()), Ok("0x1008".to_string()));
+        // wraps around rather than overflowing, as the pointer arithmetic in the inferior would
+        let huge = Value::from_integer(i64::MAX, integer_type(8, true));
+        assert_eq!(eval(BinaryOp::Add, pointer.clone(), huge), Ok("0xffc".to_string()));
+        let end = Value::from_integer(0x1010, pointer.entity_type.clone());
+        assert_eq!(eval(BinaryOp::Sub, end, pointer), Ok("4".to_string()));
+    }
+}
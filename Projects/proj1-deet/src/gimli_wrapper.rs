@@ -7,20 +7,24 @@
 
 use gimli;
 use gimli::{UnitOffset, UnitSectionOffset};
-use object::Object;
+use object::{Object, ObjectSection};
 use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{File, Function, Line, Location, Member, Scope, Type, TypeEncoding, Variable};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
 use std::{io, path};
 
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
+pub fn load_file(
+    object: &object::File,
+    endian: gimli::RunTimeEndian,
+) -> Result<(Vec<File>, HashMap<usize, Type>), Error> {
     // Load a section and return as `Cow<[u8]>`.
     let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
         Ok(object
-            .section_data_by_name(id.name())
+            .section_by_name(id.name())
+            .and_then(|section| section.uncompressed_data().ok())
             .unwrap_or(borrow::Cow::Borrowed(&[][..])))
     };
     // Load a supplementary section. We don't have a supplementary object file,
@@ -49,11 +53,24 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     while let Some(header) = iter.next()? {
         let unit = dwarf.unit(header)?;
 
+        // Collect the types first, since variables may refer to types that are declared later on
+        load_types(&unit, &dwarf, &mut offset_to_type)?;
+
         // Iterate over the Debugging Information Entries (DIEs) in the unit.
         let mut depth = 0;
+        // Tags of the DIEs enclosing the current one, outermost first
+        let mut parents: Vec<gimli::DwTag> = Vec::new();
+        // Depth of the function whose variables are being collected, unless it has no code
+        let mut function_depth: Option<isize> = None;
+        // Lexical blocks and inlined calls enclosing the current DIE, innermost last
+        let mut scopes: Vec<(isize, Scope)> = Vec::new();
         let mut entries = unit.entries();
         while let Some((delta_depth, entry)) = entries.next_dfs()? {
             depth += delta_depth;
+            parents.truncate(depth as usize);
+            let parent = parents.last().cloned();
+            parents.push(entry.tag());
+            close_scopes(&mut scopes, depth, &mut compilation_units);
             // Update the offset_to_type mapping for types
             // Update the variable list for formal params/variables
             match entry.tag() {
@@ -67,6 +84,15 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                     } else {
                         "<unknown>".to_string()
                     };
+                    // a relative name is relative to where the compiler ran, and the line table
+                    // names files with that directory included
+                    let name = match &unit.comp_dir {
+                        Some(dir) if !name.starts_with('/') && name != "<unknown>" => {
+                            let dir = dir.to_string_lossy();
+                            path::Path::new(dir.as_ref()).join(&name).to_string_lossy().into_owned()
+                        }
+                        _ => name,
+                    };
                     compilation_units.push(File {
                         name,
                         global_variables: Vec::new(),
@@ -74,35 +100,9 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                         lines: Vec::new(),
                     });
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
-                        }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
                 gimli::DW_TAG_subprogram => {
                     let mut func: Function = Default::default();
+                    let mut origin = Origin::default();
                     let mut attrs = entry.attrs();
                     while let Some(attr) = attrs.next()? {
                         let val = get_attr_value(&attr, &unit, &dwarf);
@@ -129,16 +129,68 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                     func.line_number = line_number.try_into().unwrap();
                                 }
                             }
+                            gimli::DW_AT_type => {
+                                if let Ok(DebugValue::Size(offset)) = val {
+                                    func.return_type = offset_to_type.get(&offset).cloned();
+                                }
+                            }
+                            gimli::DW_AT_frame_base => {
+                                func.frame_base = get_location(&attr, &unit, &dwarf)?;
+                            }
+                            gimli::DW_AT_abstract_origin | gimli::DW_AT_specification => {
+                                origin = get_origin(&attr, &unit, &dwarf)?;
+                            }
                             _ => {}
                         }
                     }
-                    compilation_units.last_mut().unwrap().functions.push(func);
+                    // out-of-line copies of inline functions describe only their code, and the
+                    // rest comes from the abstract instance
+                    if func.name.is_empty() {
+                        func.name = origin.name;
+                        func.line_number = origin.line_number.try_into().unwrap();
+                    }
+                    if func.return_type.is_none() {
+                        func.return_type = origin.type_offset.and_then(|offset| offset_to_type.get(&offset).cloned());
+                    }
+                    if func.text_length == 0 {
+                        // functions split into pieces (like a cold path) give their ranges
+                        // instead, and the first one is where the function starts
+                        if let Some(range) = dwarf.die_ranges(&unit, entry)?.next()? {
+                            func.address = range.begin.try_into().unwrap();
+                            func.text_length = (range.end - range.begin).try_into().unwrap();
+                        }
+                    }
+                    // declarations and abstract instances of inline functions have no code
+                    if func.text_length > 0 {
+                        compilation_units.last_mut().unwrap().functions.push(func);
+                        function_depth = Some(depth);
+                    } else {
+                        function_depth = None;
+                    }
+                }
+                gimli::DW_TAG_lexical_block | gimli::DW_TAG_inlined_subroutine
+                    if function_depth.is_some_and(|function_depth| depth > function_depth) =>
+                {
+                    let mut scope = Scope::default();
+                    if entry.tag() == gimli::DW_TAG_inlined_subroutine {
+                        scope.inlined = Some(match entry.attr(gimli::DW_AT_abstract_origin)? {
+                            Some(attr) => get_origin(&attr, &unit, &dwarf)?.name,
+                            None => String::new(),
+                        });
+                    }
+                    let mut ranges = dwarf.die_ranges(&unit, entry)?;
+                    while let Some(range) = ranges.next()? {
+                        scope.ranges.push((range.begin.try_into().unwrap(), range.end.try_into().unwrap()));
+                    }
+                    scopes.push((depth, scope));
                 }
                 gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
                     let mut name = String::new();
                     let mut entity_type: Option<Type> = None;
                     let mut location: Option<Location> = None;
                     let mut line_number = 0;
+                    let mut declaration = false;
+                    let mut origin = Origin::default();
                     let mut attrs = entry.attrs();
                     while let Some(attr) = attrs.next()? {
                         let val = get_attr_value(&attr, &unit, &dwarf);
@@ -157,40 +209,66 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                 }
                             }
                             gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
-                                    location = Some(loc);
-                                }
+                                location = get_location(&attr, &unit, &dwarf)?;
+                            }
+                            gimli::DW_AT_const_value => {
+                                location = get_const_value(&attr).map(Location::Value);
+                            }
+                            gimli::DW_AT_declaration => {
+                                // e.g. `extern FILE *stdin;`, which is defined somewhere else
+                                declaration = true;
                             }
                             gimli::DW_AT_decl_line => {
                                 if let Ok(DebugValue::Uint(num)) = val {
                                     line_number = num;
                                 }
                             }
+                            gimli::DW_AT_abstract_origin | gimli::DW_AT_specification => {
+                                origin = get_origin(&attr, &unit, &dwarf)?;
+                            }
                             _ => {}
                         }
                     }
-                    if entity_type.is_some() && location.is_some() {
+                    // the parameters of an inlined call only say where they ended up
+                    if name.is_empty() {
+                        name = origin.name;
+                        line_number = origin.line_number;
+                    }
+                    if entity_type.is_none() {
+                        entity_type = origin.type_offset.and_then(|offset| offset_to_type.get(&offset).cloned());
+                    }
+                    if entity_type.is_some() && !declaration {
                         let var = Variable {
                             name,
                             entity_type: entity_type.unwrap(),
-                            location: location.unwrap(),
+                            // a variable without a location has been optimized away
+                            location: location.unwrap_or(Location::OptimizedOut),
                             line_number: line_number.try_into().unwrap(),
                         };
-                        if depth == 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .global_variables
-                                .push(var);
-                        } else if depth > 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .functions
-                                .last_mut()
-                                .unwrap()
-                                .variables
-                                .push(var);
+                        let is_param = entry.tag() == gimli::DW_TAG_formal_parameter;
+                        let file = compilation_units.last_mut().unwrap();
+                        match parent {
+                            Some(gimli::DW_TAG_compile_unit) => file.global_variables.push(var),
+                            Some(gimli::DW_TAG_subprogram) if function_depth == Some(depth - 1) => {
+                                let func = file.functions.last_mut().unwrap();
+                                if is_param {
+                                    func.parameters.push(var);
+                                } else {
+                                    func.variables.push(var);
+                                }
+                            }
+                            Some(gimli::DW_TAG_lexical_block) | Some(gimli::DW_TAG_inlined_subroutine) => {
+                                if let Some((scope_depth, scope)) = scopes.last_mut() {
+                                    if *scope_depth == depth - 1 && is_param {
+                                        scope.parameters.push(var);
+                                    } else if *scope_depth == depth - 1 {
+                                        scope.variables.push(var);
+                                    }
+                                }
+                            }
+                            // e.g. the parameters of a function pointer type, or of a function
+                            // without code
+                            _ => {}
                         }
                     }
                 }
@@ -200,6 +278,8 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
             }
         }
 
+        close_scopes(&mut scopes, 0, &mut compilation_units);
+
         // Get line numbers
         if let Some(program) = unit.line_program.clone() {
             // Iterate over the line program rows.
@@ -219,6 +299,9 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                 .as_ref(),
                         );
                     }
+                    if let (true, Some(dir)) = (path.is_relative(), &unit.comp_dir) {
+                        path = path::Path::new(dir.to_string_lossy().as_ref()).join(path);
+                    }
 
                     // Get the File
                     let file = compilation_units
@@ -240,7 +323,284 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
             }
         }
     }
-    Ok(compilation_units)
+    Ok((compilation_units, offset_to_type))
+}
+
+/// A type as its DIE describes it, before the types it refers to (by offset) are looked up
+enum RawType {
+    Base(Type),
+    Pointer {
+        size: usize,
+        target: Option<usize>,
+    },
+    Struct {
+        name: String,
+        size: usize,
+        /// (name, offset, type) of each field
+        members: Vec<(String, usize, Option<usize>)>,
+    },
+    Array {
+        target: Option<usize>,
+        /// Number of elements in each dimension
+        counts: Vec<usize>,
+    },
+    Enum {
+        name: String,
+        size: usize,
+        /// The integer type the enum is stored as
+        target: Option<usize>,
+        enumerators: Vec<(String, i64)>,
+    },
+    Function {
+        return_type: Option<usize>,
+        parameters: Vec<Option<usize>>,
+        /// Whether the function was declared with a prototype, as opposed to `int f()`
+        prototyped: bool,
+    },
+    /// Another name for a type, from a typedef or a const or volatile qualifier
+    Alias {
+        name: Option<String>,
+        /// `const` or `volatile`, for qualifiers
+        qualifier: Option<&'static str>,
+        target: Option<usize>,
+    },
+}
+
+fn load_types<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    offset_to_type: &mut HashMap<usize, Type>,
+) -> Result<(), Error> {
+    let mut raw_types: HashMap<usize, RawType> = HashMap::new();
+    // Struct members and array dimensions are children of their type's DIE, so keep track of
+    // which type (if any) the entries we're visiting belong to
+    let mut parent: Option<(isize, usize)> = None;
+
+    let mut depth = 0;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        let offset = section_offset(entry.offset(), unit);
+        let name = match entry.attr(gimli::DW_AT_name) {
+            Ok(Some(attr)) => match get_attr_value(&attr, unit, dwarf) {
+                Ok(DebugValue::Str(name)) => Some(name),
+                _ => None,
+            },
+            _ => None,
+        };
+        let byte_size = match entry.attr(gimli::DW_AT_byte_size) {
+            Ok(Some(attr)) => attr.udata_value().map(|size| size.try_into().unwrap()),
+            _ => None,
+        };
+        let target = match entry.attr(gimli::DW_AT_type) {
+            Ok(Some(attr)) => match get_attr_value(&attr, unit, dwarf) {
+                Ok(DebugValue::Size(offset)) => Some(offset),
+                _ => None,
+            },
+            _ => None,
+        };
+        // formal parameters belong to functions too, so forget the type once we're past it
+        if parent.is_some_and(|(parent_depth, _)| depth <= parent_depth) {
+            parent = None;
+        }
+        let parent_type = match parent {
+            Some((parent_depth, parent_offset)) if parent_depth + 1 == depth => {
+                raw_types.get_mut(&parent_offset)
+            }
+            _ => None,
+        };
+        match (entry.tag(), parent_type) {
+            (gimli::DW_TAG_base_type, _) => {
+                let encoding = match entry.attr_value(gimli::DW_AT_encoding) {
+                    Ok(Some(gimli::AttributeValue::Encoding(encoding))) => match encoding {
+                        gimli::DW_ATE_signed => TypeEncoding::Signed,
+                        gimli::DW_ATE_unsigned => TypeEncoding::Unsigned,
+                        gimli::DW_ATE_signed_char => TypeEncoding::SignedChar,
+                        gimli::DW_ATE_unsigned_char => TypeEncoding::UnsignedChar,
+                        gimli::DW_ATE_boolean => TypeEncoding::Boolean,
+                        gimli::DW_ATE_float => TypeEncoding::Float,
+                        _ => TypeEncoding::Unknown,
+                    },
+                    _ => TypeEncoding::Unknown,
+                };
+                let name = name.unwrap_or_else(|| "<unknown>".to_string());
+                raw_types.insert(offset, RawType::Base(Type::new(name, byte_size.unwrap_or(0), encoding)));
+            }
+            (gimli::DW_TAG_pointer_type, _) => {
+                let size = byte_size.unwrap_or(8);
+                raw_types.insert(offset, RawType::Pointer { size, target });
+            }
+            (gimli::DW_TAG_structure_type, _) | (gimli::DW_TAG_union_type, _) => {
+                let keyword = if entry.tag() == gimli::DW_TAG_union_type { "union" } else { "struct" };
+                let name = format!("{} {}", keyword, name.unwrap_or_else(|| "{...}".to_string()));
+                let size = byte_size.unwrap_or(0);
+                let members = Vec::new();
+                raw_types.insert(offset, RawType::Struct { name, size, members });
+                parent = Some((depth, offset));
+            }
+            (gimli::DW_TAG_enumeration_type, _) => {
+                let name = format!("enum {}", name.unwrap_or_else(|| "{...}".to_string()));
+                let size = byte_size.unwrap_or(4);
+                let enumerators = Vec::new();
+                raw_types.insert(offset, RawType::Enum { name, size, target, enumerators });
+                parent = Some((depth, offset));
+            }
+            (gimli::DW_TAG_subroutine_type, _) => {
+                let prototyped = matches!(entry.attr_value(gimli::DW_AT_prototyped), Ok(Some(_)));
+                let parameters = Vec::new();
+                raw_types.insert(offset, RawType::Function { return_type: target, parameters, prototyped });
+                parent = Some((depth, offset));
+            }
+            (gimli::DW_TAG_formal_parameter, Some(RawType::Function { parameters, .. })) => {
+                parameters.push(target);
+            }
+            (gimli::DW_TAG_typedef, _) => {
+                raw_types.insert(offset, RawType::Alias { name, qualifier: None, target });
+            }
+            (gimli::DW_TAG_const_type, _) | (gimli::DW_TAG_volatile_type, _) => {
+                let qualifier = if entry.tag() == gimli::DW_TAG_const_type { "const" } else { "volatile" };
+                raw_types.insert(offset, RawType::Alias { name: None, qualifier: Some(qualifier), target });
+            }
+            (gimli::DW_TAG_enumerator, Some(RawType::Enum { enumerators, .. })) => {
+                // values are stored in the smallest form that fits, which doesn't say whether
+                // they are signed; only negative ones are stored as signed
+                let value = match entry.attr(gimli::DW_AT_const_value) {
+                    Ok(Some(attr)) => attr.udata_value().map(|val| val as i64).or_else(|| attr.sdata_value()),
+                    _ => None,
+                };
+                enumerators.push((name.unwrap_or_default(), value.unwrap_or(0)));
+            }
+            (gimli::DW_TAG_array_type, _) => {
+                raw_types.insert(offset, RawType::Array { target, counts: Vec::new() });
+                parent = Some((depth, offset));
+            }
+            (gimli::DW_TAG_member, Some(RawType::Struct { members, .. })) => {
+                let member_offset = match entry.attr(gimli::DW_AT_data_member_location) {
+                    Ok(Some(attr)) => attr.udata_value().unwrap_or(0).try_into().unwrap(),
+                    _ => 0,
+                };
+                members.push((name.unwrap_or_default(), member_offset, target));
+            }
+            (gimli::DW_TAG_subrange_type, Some(RawType::Array { counts, .. })) => {
+                // arrays without a size (like `extern int arr[];`) have no bounds
+                let count = match (entry.attr(gimli::DW_AT_count), entry.attr(gimli::DW_AT_upper_bound)) {
+                    (Ok(Some(count)), _) => count.udata_value().unwrap_or(0),
+                    (_, Ok(Some(upper_bound))) => upper_bound.udata_value().map_or(0, |bound| bound + 1),
+                    _ => 0,
+                };
+                counts.push(count.try_into().unwrap());
+            }
+            _ => {}
+        }
+    }
+
+    for offset in raw_types.keys() {
+        offset_to_type.insert(*offset, resolve_type(Some(*offset), &raw_types, false));
+    }
+    Ok(())
+}
+
+/// Builds the Type for the DIE at `offset`, following the references to the types it is made
+/// of. Structs behind pointers are left without members, since they can point back at themselves.
+fn resolve_type(offset: Option<usize>, raw_types: &HashMap<usize, RawType>, behind_pointer: bool) -> Type {
+    let offset = match offset {
+        Some(offset) => offset,
+        None => return Type::from_c_name("void").unwrap(),
+    };
+    match raw_types.get(&offset) {
+        Some(RawType::Base(base)) => base.clone(),
+        Some(RawType::Pointer { size, target }) => Type {
+            size: *size,
+            ..resolve_type(*target, raw_types, true).pointer_to()
+        },
+        Some(RawType::Struct { name, size, members }) => {
+            let members = if behind_pointer {
+                Vec::new()
+            } else {
+                members
+                    .iter()
+                    .map(|(name, offset, target)| Member {
+                        name: name.clone(),
+                        offset: *offset,
+                        entity_type: resolve_type(*target, raw_types, false),
+                    })
+                    .collect()
+            };
+            Type {
+                name: name.clone(),
+                size: *size,
+                encoding: TypeEncoding::Struct,
+                members,
+                id: Some(offset),
+                ..Default::default()
+            }
+        }
+        Some(RawType::Array { target, counts }) => {
+            let element = resolve_type(*target, raw_types, behind_pointer);
+            let mut array = element.clone();
+            // int [2][3] is an array of two int [3]s
+            for (dimension, count) in counts.iter().enumerate().rev() {
+                let dimensions: Vec<String> = counts[dimension..].iter().map(|count| format!("[{}]", count)).collect();
+                array = Type {
+                    name: format!("{} {}", element.name, dimensions.concat()),
+                    size: count * array.size,
+                    encoding: TypeEncoding::Array,
+                    target: Some(Box::new(array)),
+                    ..Default::default()
+                };
+            }
+            array
+        }
+        Some(RawType::Enum { name, size, target, enumerators }) => Type {
+            name: name.clone(),
+            size: *size,
+            encoding: TypeEncoding::Enum,
+            target: target.map(|target| Box::new(resolve_type(Some(target), raw_types, behind_pointer))),
+            enumerators: enumerators.clone(),
+            ..Default::default()
+        },
+        Some(RawType::Function { return_type, parameters, prototyped }) => {
+            let return_type = resolve_type(*return_type, raw_types, true);
+            let parameters: Vec<String> = parameters
+                .iter()
+                .map(|parameter| resolve_type(*parameter, raw_types, true).name)
+                .collect();
+            let parameters = if parameters.is_empty() && *prototyped {
+                "void".to_string()
+            } else {
+                parameters.join(", ")
+            };
+            Type {
+                name: format!("{} ({})", return_type.name, parameters),
+                size: 1,
+                encoding: TypeEncoding::Function,
+                ..Default::default()
+            }
+        }
+        Some(RawType::Alias { name, qualifier, target }) => {
+            let target = resolve_type(*target, raw_types, behind_pointer);
+            let name = match (name, qualifier) {
+                (Some(name), _) => name.clone(),
+                // the qualifier of a pointer comes after the *
+                (None, Some(qualifier)) if target.encoding == TypeEncoding::Pointer => {
+                    format!("{} {}", target.name, qualifier)
+                }
+                (None, Some(qualifier)) => format!("{} {}", qualifier, target.name),
+                (None, None) => target.name.clone(),
+            };
+            Type { name, ..target }
+        }
+        None => Type::new("<unknown>".to_string(), 0, TypeEncoding::Unknown),
+    }
+}
+
+/// Converts an offset within a unit to an offset within the .debug_info section, which is what
+/// DW_AT_type references are resolved to by get_attr_value.
+fn section_offset<R: Reader>(offset: UnitOffset, unit: &gimli::Unit<R>) -> usize {
+    match offset.to_unit_section_offset(unit) {
+        UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+        UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -255,7 +615,6 @@ pub enum DebugValue {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     GimliError(gimli::Error),
-    Addr2lineError(addr2line::gimli::Error),
     ObjectError(String),
     IoError,
 }
@@ -266,12 +625,6 @@ impl From<gimli::Error> for Error {
     }
 }
 
-impl From<addr2line::gimli::Error> for Error {
-    fn from(err: addr2line::gimli::Error) -> Self {
-        Error::Addr2lineError(err)
-    }
-}
-
 impl From<io::Error> for Error {
     fn from(_: io::Error) -> Self {
         Error::IoError
@@ -291,25 +644,112 @@ impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
 
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
-    if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
-        let encoding = unit.encoding();
-        let mut pc = data.0.clone();
-        if pc.len() > 0 {
-            if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
-                match op {
-                    gimli::Operation::FrameOffset { offset } => {
-                        return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
-                    }
-                    gimli::Operation::Address { address } => {
-                        return Some(Location::Address(address.try_into().unwrap()));
-                    }
-                    _ => {}
+/// What a DIE inherits from the one its DW_AT_abstract_origin (for inlined calls and
+/// out-of-line copies of inline functions) or DW_AT_specification refers to.
+#[derive(Default)]
+struct Origin {
+    name: String,
+    type_offset: Option<usize>,
+    line_number: u64,
+}
+
+fn get_origin<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Origin, Error> {
+    let mut origin = Origin::default();
+    let entry = match attr.value() {
+        gimli::AttributeValue::UnitRef(offset) => unit.entry(offset)?,
+        _ => return Ok(origin),
+    };
+    let mut attrs = entry.attrs();
+    while let Some(attr) = attrs.next()? {
+        match (attr.name(), get_attr_value(&attr, unit, dwarf)) {
+            (gimli::DW_AT_name, Ok(DebugValue::Str(name))) => origin.name = name,
+            (gimli::DW_AT_type, Ok(DebugValue::Size(offset))) => origin.type_offset = Some(offset),
+            (gimli::DW_AT_decl_line, Ok(DebugValue::Uint(line_number))) => origin.line_number = line_number,
+            _ => {}
+        }
+    }
+    Ok(origin)
+}
+
+/// Finishes the scopes that a DIE at `depth` is outside of, adding each one to its enclosing
+/// scope, or to the function being loaded.
+fn close_scopes(scopes: &mut Vec<(isize, Scope)>, depth: isize, compilation_units: &mut [File]) {
+    while scopes.last().is_some_and(|(scope_depth, _)| depth <= *scope_depth) {
+        let (_, scope) = scopes.pop().unwrap();
+        match scopes.last_mut() {
+            Some((_, parent)) => parent.scopes.push(scope),
+            None => {
+                if let Some(func) = compilation_units.last_mut().and_then(|file| file.functions.last_mut()) {
+                    func.scopes.push(scope);
+                }
+            }
+        }
+    }
+}
+
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Option<Location>, Error> {
+    if let gimli::AttributeValue::Exprloc(ref expression) = attr.value() {
+        return Ok(Some(expression_location(expression, unit.encoding())?));
+    }
+    // Otherwise this may be a location list, giving a different expression for each range of
+    // pcs (the ranges come back already adjusted for the unit's base address)
+    let mut entries = match dwarf.attr_locations(unit, attr.value())? {
+        Some(entries) => entries,
+        None => return Ok(None),
+    };
+    let mut list = Vec::new();
+    while let Some(entry) = entries.next()? {
+        list.push((
+            entry.range.begin.try_into().unwrap(),
+            entry.range.end.try_into().unwrap(),
+            expression_location(&entry.data, unit.encoding())?,
+        ));
+    }
+    Ok(Some(Location::List(list)))
+}
+
+/// Turns a location expression into a Location. The common single-operation forms used at -O0
+/// get their own variants; anything else is kept as bytes to evaluate once the program is
+/// running.
+fn expression_location<R: Reader>(
+    expression: &gimli::Expression<R>,
+    encoding: gimli::Encoding,
+) -> Result<Location, Error> {
+    if expression.0.is_empty() {
+        return Ok(Location::OptimizedOut);
+    }
+    let mut pc = expression.0.clone();
+    if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
+        if pc.is_empty() {
+            match op {
+                gimli::Operation::FrameOffset { offset } => {
+                    return Ok(Location::FramePointerOffset(offset.try_into().unwrap()));
+                }
+                gimli::Operation::Address { address } => {
+                    return Ok(Location::Address(address.try_into().unwrap()));
                 }
+                _ => {}
             }
         }
     }
-    None
+    Ok(Location::Expression(expression.0.to_slice()?.to_vec(), encoding))
+}
+
+/// Gets the bytes of a DW_AT_const_value, in target (little-endian) order.
+fn get_const_value<R: Reader>(attr: &gimli::Attribute<R>) -> Option<Vec<u8>> {
+    match attr.value() {
+        gimli::AttributeValue::Block(data) => data.to_slice().ok().map(|bytes| bytes.to_vec()),
+        gimli::AttributeValue::Sdata(value) => Some(value.to_le_bytes().to_vec()),
+        _ => attr.udata_value().map(|value| value.to_le_bytes().to_vec()),
+    }
 }
 
 // based on dwarf_dump.rs
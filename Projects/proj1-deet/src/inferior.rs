@@ -3,12 +3,26 @@ use nix::sys::signal;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
 use std::collections::HashMap;
+use std::convert::TryInto;
 use std::process::Child;
 use std::process::Command;
 use std::os::unix::process::CommandExt;
-use crate::dwarf_data::DwarfData;
+use crate::dwarf_data::{DwarfData, Line, Variable};
+use crate::location::{self, Place};
+use crate::registers;
 use std::mem::size_of;
-use crate::debugger::Breakpoint;
+use crate::debugger::{Breakpoint, WatchKind};
+use crate::expression::{Context, Value};
+use crate::signals::SignalTable;
+use crate::unwind::{self, Frame, Mapping, Module, SavedRegister};
+
+/// Offset of `u_debugreg` in `struct user` (see sys/user.h), for PTRACE_PEEKUSER/POKEUSER
+const DEBUG_REG_OFFSET: usize = 848;
+/// DR6 and DR7 are the debug status and control registers; DR0-DR3 hold watched addresses
+const DR_STATUS: usize = 6;
+const DR_CONTROL: usize = 7;
+/// Backtraces stop after this many frames, in case the stack is corrupted in a way that loops
+const MAX_FRAMES: usize = 1024;
 
 pub enum Status {
     /// Indicates inferior stopped. Contains the signal that stopped the process, as well as the
@@ -21,6 +35,37 @@ pub enum Status {
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+
+    /// Indicates the inferior stopped because a hardware watchpoint triggered. Contains the
+    /// debug register (0-3) that triggered, as well as the current instruction pointer.
+    Watchpoint(usize, usize),
+
+    /// Indicates the inferior forked. Contains the pid of the new process, which is traced and
+    /// stopped.
+    Forked(Pid),
+
+    /// Indicates the inferior replaced its program with a new one through exec.
+    Execed,
+}
+
+/// A stepping command that the inferior forked in the middle of. The debugger carries on with it
+/// (in whichever process it follows) once it has dealt with the fork.
+#[derive(Clone)]
+pub enum Step {
+    /// A single instruction, the system call that forked
+    Instruction,
+    /// Nothing; the step is over. This is where a child is when its parent forked during a
+    /// single instruction step.
+    Done,
+    /// Running until the given address is reached with the stack pointer above the given one
+    Until(usize, usize),
+    /// Stepping until the inferior leaves `start_line`, running to `until` first if it was
+    /// stepping over a call
+    Line {
+        start_line: Option<Line>,
+        step_into: bool,
+        until: Option<(usize, usize)>,
+    },
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
@@ -32,19 +77,158 @@ fn child_traceme() -> Result<(), std::io::Error> {
     )))
 }
 
+/// The events we want to hear about besides signals: new threads, new processes and new programs
+fn trace_options() -> ptrace::Options {
+    ptrace::Options::PTRACE_O_TRACECLONE
+        | ptrace::Options::PTRACE_O_TRACEFORK
+        | ptrace::Options::PTRACE_O_TRACEEXEC
+}
+
 fn align_addr_to_word(addr: usize) -> usize {
     addr & (-(size_of::<usize>() as isize) as usize)
 }
 
+/// Returns the address just past `len` bytes at `addr`, which is a bad address if it wraps around.
+fn end_addr(addr: usize, len: usize) -> Result<usize, nix::Error> {
+    addr.checked_add(len).ok_or(nix::Error::Sys(nix::errno::Errno::EFAULT))
+}
+
+fn read_debug_register(tid: Pid, index: usize) -> Result<u64, nix::Error> {
+    // nix doesn't wrap PTRACE_PEEKUSER, so call it directly. The register's value is
+    // returned, so errors can only be told apart from a value of -1 through errno.
+    unsafe { nix::errno::Errno::clear() };
+    let ret = unsafe {
+        libc::ptrace(
+            libc::PTRACE_PEEKUSER,
+            libc::pid_t::from(tid),
+            DEBUG_REG_OFFSET + index * size_of::<u64>(),
+            std::ptr::null_mut::<libc::c_void>(),
+        )
+    };
+    if ret == -1 && nix::errno::errno() != 0 {
+        return Err(nix::Error::last());
+    }
+    Ok(ret as u64)
+}
+
+fn write_debug_register(tid: Pid, index: usize, val: u64) -> Result<(), nix::Error> {
+    let ret = unsafe {
+        libc::ptrace(
+            libc::PTRACE_POKEUSER,
+            libc::pid_t::from(tid),
+            DEBUG_REG_OFFSET + index * size_of::<u64>(),
+            val as usize as *mut libc::c_void,
+        )
+    };
+    nix::errno::Errno::result(ret)?;
+    Ok(())
+}
+
+/// Returns whether `tid` is a thread of process `pid`, as opposed to a process of its own.
+fn is_thread_of(tid: Pid, pid: Pid) -> bool {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", tid)).unwrap_or_default();
+    status
+        .lines()
+        .find(|line| line.starts_with("Tgid:"))
+        .is_none_or(|line| line["Tgid:".len()..].trim() == pid.to_string())
+}
+
+/// Returns whether an enabled breakpoint (i.e. an int3 in the inferior's memory) sits at `addr`.
+fn is_enabled_at(breakpoints: &HashMap<usize, Breakpoint>, addr: usize) -> bool {
+    breakpoints.get(&addr).is_some_and(|bp| bp.enabled)
+}
+
+
+/// A thread of the inferior. All threads are stopped while the user is at the prompt; while the
+/// inferior runs, `running` tracks which threads we have resumed and not yet seen stop.
+struct Thread {
+    /// The number the user refers to the thread by, counting from 1 in order of creation
+    id: usize,
+    tid: Pid,
+    running: bool,
+    /// Set when a SIGSTOP is on its way to this thread (one we sent, or the one new threads start
+    /// with) that should be swallowed rather than reported
+    stop_pending: bool,
+    /// A signal the thread received while we were stopping it, to be delivered when it resumes
+    pending_signal: Option<signal::Signal>,
+    /// Whether the debug registers have been programmed in this thread yet
+    initialized: bool,
+}
 
 pub struct Inferior {
-    child: Child,
+    pid: Pid,
+    /// The process we spawned, or None if we didn't start it through Command
+    child: Option<Child>,
+    /// Whether the process was started by someone else, so that we leave it running when done
+    attached: bool,
+    load_bias: usize,
+    threads: Vec<Thread>,
+    next_thread_id: usize,
+    /// The thread registers and memory are accessed through, normally the one that stopped last
+    current: Pid,
+    /// The thread the user was last told about, to announce when a different thread stops
+    reported: Pid,
+    /// The thread whose stop was reported last
+    event_thread: Pid,
+    /// Whether only the current thread is running, single-stepping
+    stepping: bool,
+    /// Which signals to stop for and which to pass on to the program
+    signals: SignalTable,
+    /// Where a thread comes back to (thread, breakpoint address and stack pointer) once it has run
+    /// the handler of a signal we delivered while it sat on a breakpoint. Getting there doesn't
+    /// count as hitting the breakpoint again.
+    handler_return: Option<(Pid, usize, u64)>,
+    /// Children whose fork was seen while stopping the other threads, still to be reported along
+    /// with the thread that forked them
+    unreported_forks: Vec<(Pid, Pid)>,
+    /// Watchpoints (thread and debug register slot) that triggered in other threads while they
+    /// were being stopped, still to be reported
+    unreported_watchpoints: Vec<(Pid, usize)>,
+    /// New processes that stopped before their parent's fork event arrived
+    early_fork_stops: Vec<Pid>,
+    /// The step that was under way when the process last forked, if it hasn't been resumed
+    interrupted_step: Option<Step>,
+    /// A temporary breakpoint (address and original byte) that was planted when the process
+    /// forked, and so was copied into the child
+    fork_temp_breakpoint: Option<(usize, u8)>,
+    /// Object files mapped into the process, by path, for unwinding through them (None if the
+    /// file couldn't be read)
+    modules: HashMap<String, Option<Module>>,
+    /// Watched addresses (DR0-DR3) and DR7, which are per-thread and so have to be copied into
+    /// threads as they are created
+    debug_addrs: [u64; 4],
+    debug_control: u64,
 }
 
 impl Inferior {
+    fn with_pid(pid: Pid, child: Option<Child>) -> Inferior {
+        Inferior {
+            pid,
+            attached: child.is_none(),
+            child,
+            load_bias: 0,
+            threads: Vec::new(),
+            next_thread_id: 1,
+            current: pid,
+            reported: pid,
+            event_thread: pid,
+            stepping: false,
+            signals: SignalTable::default(),
+            handler_return: None,
+            unreported_forks: Vec::new(),
+            unreported_watchpoints: Vec::new(),
+            early_fork_stops: Vec::new(),
+            interrupted_step: None,
+            fork_temp_breakpoint: None,
+            modules: HashMap::new(),
+            debug_addrs: [0; 4],
+            debug_control: 0,
+        }
+    }
+
     /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
     /// an error is encountered.
-    pub fn new(target: &str, args: &Vec<String>, breakpoints: &mut HashMap<usize, Breakpoint>) -> Option<Inferior> {
+    pub fn new(target: &str, args: &Vec<String>, debug_data: &DwarfData) -> Option<Inferior> {
         // spawn a child process running our target program
         let mut cmd = Command::new(target);
         cmd.args(args);
@@ -55,117 +239,1389 @@ impl Inferior {
         
         // Milestone 1: Run the inferior
         let child = cmd.spawn().ok()?;
-        let mut inferior = Inferior { child };
-        match inferior.wait(None) {
-            Ok(_) => {
-                // after you wait for SIGTRAP (indicating that the inferior has fully loaded) but before returning
-                // , you should install these breakpoints in the child process.
-                for bp in breakpoints {
-                    let orig_byte = inferior.write_byte(bp.0.clone(), 0xcc).expect("write_byte failed");
-                    bp.1.orig_byte = orig_byte; // also, remember the orig_byte
+        let mut inferior = Inferior::with_pid(Pid::from_raw(child.id() as i32), Some(child));
+        inferior.add_thread(inferior.pid, false);
+        match inferior.wait() {
+            Ok(Status::Stopped(..)) => {
+                // report new threads to us, so that they can be stopped along with the others, and
+                // since the program has been loaded by now, work out where it ended up
+                let load_bias = ptrace::setoptions(inferior.pid, trace_options())
+                    .ok()
+                    .and_then(|_| inferior.find_load_bias(debug_data));
+                match load_bias {
+                    Some(load_bias) => {
+                        inferior.load_bias = load_bias;
+                        Some(inferior)
+                    }
+                    None => {
+                        // don't leave the child behind, stopped and traced
+                        let _ = inferior.kill();
+                        None
+                    }
                 }
-
-                Some(inferior)
             }
             _ => None,
         }
     }
 
+    /// Attaches to every thread of the running process `pid` and stops them.
+    pub fn attach(pid: Pid, debug_data: &DwarfData) -> Result<Inferior, nix::Error> {
+        let mut inferior = Inferior::with_pid(pid, None);
+        ptrace::attach(pid)?;
+        inferior.add_thread(pid, true);
+        if let Err(err) = inferior.attach_threads(debug_data) {
+            inferior.release_threads();
+            return Err(err);
+        }
+        Ok(inferior)
+    }
+
+    /// Finishes attaching: waits for the first thread to stop, then attaches to the others.
+    fn attach_threads(&mut self, debug_data: &DwarfData) -> Result<(), nix::Error> {
+        self.wait_for_attach(self.pid)?;
+        // threads can be created while we attach to the others, so keep looking until we have
+        // found them all
+        loop {
+            let tids = match std::fs::read_dir(format!("/proc/{}/task", self.pid)) {
+                Ok(entries) => entries
+                    .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                    .map(Pid::from_raw)
+                    .filter(|tid| self.thread(*tid).is_none())
+                    .collect::<Vec<_>>(),
+                Err(_) => Vec::new(),
+            };
+            if tids.is_empty() {
+                break;
+            }
+            for tid in tids {
+                // the thread may have exited in the meantime
+                if ptrace::attach(tid).is_ok() {
+                    self.add_thread(tid, true);
+                    self.wait_for_attach(tid)?;
+                }
+            }
+        }
+        self.load_bias = self
+            .find_load_bias(debug_data)
+            .ok_or(nix::Error::Sys(nix::errno::Errno::ENOENT))?;
+        Ok(())
+    }
+
+    /// Lets go of the threads attached to so far when attaching fails partway, so that the process
+    /// isn't left stopped.
+    fn release_threads(&mut self) {
+        let threads: Vec<(Pid, bool)> = self.threads.iter().map(|thread| (thread.tid, thread.running)).collect();
+        for (tid, running) in threads {
+            // a thread can only be detached once the SIGSTOP from attaching to it has arrived
+            if running {
+                self.wait_for_attach(tid).ok();
+            }
+            ptrace::detach(tid, None).ok();
+        }
+    }
+
+    /// Waits for the SIGSTOP that PTRACE_ATTACH sends to `tid`, before we touch its registers.
+    fn wait_for_attach(&mut self, tid: Pid) -> Result<(), nix::Error> {
+        loop {
+            match waitpid(tid, Some(WaitPidFlag::__WALL))? {
+                WaitStatus::Stopped(_, signal::Signal::SIGSTOP) => break,
+                WaitStatus::Stopped(_, sig) => ptrace::cont(tid, sig)?,
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                    return Err(nix::Error::Sys(nix::errno::Errno::ESRCH))
+                }
+                _ => ptrace::cont(tid, None)?,
+            }
+        }
+        if let Some(thread) = self.thread_mut(tid) {
+            thread.running = false;
+            thread.stop_pending = false;
+        }
+        ptrace::setoptions(tid, trace_options())?;
+        Ok(())
+    }
+
+    /// Takes control of `child`, a process this inferior just forked. The child starts out
+    /// stopped, as an exact copy of this process (breakpoints included) except for watchpoints.
+    pub fn take_fork_child(&mut self, child: Pid) -> Result<Inferior, nix::Error> {
+        if let Some(index) = self.early_fork_stops.iter().position(|&pid| pid == child) {
+            self.early_fork_stops.remove(index);
+        } else {
+            loop {
+                match waitpid(child, Some(WaitPidFlag::__WALL))? {
+                    WaitStatus::Stopped(_, signal::Signal::SIGSTOP) => break,
+                    WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                        return Err(nix::Error::Sys(nix::errno::Errno::ESRCH))
+                    }
+                    _ => ptrace::cont(child, None)?,
+                }
+            }
+        }
+        // the options are inherited, but set them anyway in case that changes
+        ptrace::setoptions(child, trace_options())?;
+        let mut inferior = Inferior::with_pid(child, None);
+        // a process forked by one we started is ours to kill, too
+        inferior.attached = self.attached;
+        inferior.load_bias = self.load_bias;
+        inferior.signals = self.signals.clone();
+        inferior.add_thread(child, false);
+        if let Some((addr, orig_byte)) = self.fork_temp_breakpoint.take() {
+            inferior.write_byte(addr, orig_byte)?;
+        }
+        // should the debugger follow the child, the step carries on there (with the system call
+        // already over)
+        inferior.interrupted_step = match self.interrupted_step {
+            Some(Step::Instruction) => Some(Step::Done),
+            ref step => step.clone(),
+        };
+        Ok(inferior)
+    }
+
+    /// Computes how far the program was moved from its link-time addresses when it was loaded,
+    /// by comparing the entry point the kernel reports in the auxiliary vector with the one in
+    /// the ELF header. This is 0 for executables that aren't position-independent.
+    fn find_load_bias(&self, debug_data: &DwarfData) -> Option<usize> {
+        let auxv = std::fs::read(format!("/proc/{}/auxv", self.pid())).ok()?;
+        // the auxiliary vector is a list of (type, value) pairs of native words
+        let entry = auxv.chunks_exact(2 * size_of::<u64>()).find_map(|pair| {
+            let key = u64::from_le_bytes(pair[..8].try_into().unwrap());
+            let val = u64::from_le_bytes(pair[8..].try_into().unwrap());
+            if key == libc::AT_ENTRY {
+                Some(val as usize)
+            } else {
+                None
+            }
+        })?;
+        Some(entry.wrapping_sub(debug_data.entry_point()))
+    }
+
+    pub fn load_bias(&self) -> usize {
+        self.load_bias
+    }
+
+    /// Works out the load bias again after the process has execed `debug_data`'s program.
+    pub fn update_load_bias(&mut self, debug_data: &DwarfData) -> Option<usize> {
+        self.load_bias = self.find_load_bias(debug_data)?;
+        Some(self.load_bias)
+    }
+
+    /// Writes the enabled breakpoints into the inferior, remembering the bytes they replace.
+    /// Breakpoints that can't be written are disabled, and returned (by id) with the reason.
+    pub fn install_breakpoints(&mut self, breakpoints: &mut HashMap<usize, Breakpoint>) -> Vec<(usize, nix::Error)> {
+        let mut failed = Vec::new();
+        for bp in breakpoints.values_mut().filter(|bp| bp.enabled) {
+            match self.write_byte(bp.addr, 0xcc) {
+                Ok(orig_byte) => bp.orig_byte = orig_byte, // also, remember the orig_byte
+                Err(err) => {
+                    // so that disabling or deleting it doesn't write back a byte it never replaced
+                    bp.enabled = false;
+                    failed.push((bp.id, err));
+                }
+            }
+        }
+        failed.sort_by_key(|(id, _)| *id);
+        failed
+    }
+
+    /// Restores the instructions our breakpoints replaced and turns off the watchpoints, leaving
+    /// the process as it would be without a debugger.
+    pub fn remove_breakpoints(&mut self, breakpoints: &HashMap<usize, Breakpoint>) -> Result<(), nix::Error> {
+        for bp in breakpoints.values().filter(|bp| bp.enabled) {
+            self.write_byte(bp.addr, bp.orig_byte)?;
+        }
+        self.debug_control = 0;
+        for thread in &self.threads {
+            write_debug_register(thread.tid, DR_CONTROL, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Removes our breakpoints and watchpoints from the process and lets it continue on its own.
+    pub fn detach(mut self, breakpoints: &HashMap<usize, Breakpoint>) -> Result<(), nix::Error> {
+        self.remove_breakpoints(breakpoints)?;
+        let mut stop_pending = false;
+        for thread in self.threads.iter_mut() {
+            ptrace::detach(thread.tid, thread.pending_signal.take())?;
+            stop_pending |= thread.stop_pending;
+        }
+        if stop_pending {
+            // a SIGSTOP we sent is still queued; sending SIGCONT discards it, so that the process
+            // doesn't stop as soon as we let go of it
+            signal::kill(self.pid, signal::Signal::SIGCONT)?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether we attached to this process rather than spawning it.
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+        self.pid
     }
 
-    /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
-    /// after the waitpid call.
-    pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
-        Ok(match waitpid(self.pid(), options)? {
-            WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
-            WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
-            WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
-                Status::Stopped(signal, regs.rip as usize)
+    /// Returns the id of the thread that registers and memory are currently accessed through.
+    fn tid(&self) -> Pid {
+        self.current
+    }
+
+    fn thread(&self, tid: Pid) -> Option<&Thread> {
+        self.threads.iter().find(|thread| thread.tid == tid)
+    }
+
+    fn thread_mut(&mut self, tid: Pid) -> Option<&mut Thread> {
+        self.threads.iter_mut().find(|thread| thread.tid == tid)
+    }
+
+    /// Starts tracking a thread. Threads we are told about by a clone event are still running
+    /// until they stop with the SIGSTOP every new thread starts with.
+    fn add_thread(&mut self, tid: Pid, running: bool) {
+        let id = self.next_thread_id;
+        self.next_thread_id += 1;
+        if id > 1 {
+            println!("[New thread {} (LWP {})]", id, tid);
+        }
+        self.threads.push(Thread {
+            id,
+            tid,
+            running,
+            stop_pending: running,
+            pending_signal: None,
+            // the first thread has no watchpoints to inherit
+            initialized: id == 1,
+        });
+    }
+
+    fn remove_thread(&mut self, tid: Pid) {
+        if let Some(index) = self.threads.iter().position(|thread| thread.tid == tid) {
+            let thread = self.threads.remove(index);
+            println!("[Thread {} (LWP {}) exited]", thread.id, thread.tid);
+        }
+    }
+
+    /// Sets which signals stop the inferior and which are passed on to it.
+    pub fn set_signal_handling(&mut self, signals: &SignalTable) {
+        self.signals = signals.clone();
+    }
+
+    /// Sets the signal the current thread receives when it resumes, or None to discard the one
+    /// that stopped it.
+    pub fn set_pending_signal(&mut self, signal: Option<signal::Signal>) {
+        let tid = self.current;
+        if let Some(thread) = self.thread_mut(tid) {
+            thread.pending_signal = signal;
+        }
+    }
+
+    /// Returns what the kernel recorded about the signal that stopped the current thread.
+    pub fn get_siginfo(&self) -> Result<libc::siginfo_t, nix::Error> {
+        ptrace::getsiginfo(self.tid())
+    }
+
+    /// Decides whether the current thread receiving `signal` should be reported to the user, and
+    /// arranges for the signal to be delivered when the thread resumes if it is to be passed on.
+    fn should_stop_for_signal(&mut self, signal: signal::Signal) -> bool {
+        let handling = self.signals.get(signal);
+        self.set_pending_signal(if handling.pass { Some(signal) } else { None });
+        handling.stop
+    }
+
+    /// Returns the ids and LWP ids of the inferior's threads, in order of creation.
+    pub fn threads(&self) -> Vec<(usize, Pid)> {
+        self.threads.iter().map(|thread| (thread.id, thread.tid)).collect()
+    }
+
+    /// Returns the id of the current thread.
+    pub fn current_thread(&self) -> usize {
+        self.thread(self.current).map_or(1, |thread| thread.id)
+    }
+
+    /// Makes thread `id` the current thread. Returns false if there is no such thread.
+    pub fn select_thread(&mut self, id: usize) -> bool {
+        match self.threads.iter().find(|thread| thread.id == id) {
+            Some(thread) => {
+                self.current = thread.tid;
+                self.reported = thread.tid;
+                true
             }
-            other => panic!("waitpid returned unexpected status: {:?}", other),
-        })
+            None => false,
+        }
+    }
+
+    /// Returns whether the inferior stopped in a different thread than the one the user last
+    /// heard about, and remembers the current one as reported.
+    pub fn take_thread_switch(&mut self) -> bool {
+        let switched = self.current != self.reported && self.threads.len() > 1;
+        self.reported = self.current;
+        switched
+    }
+
+    /// Resumes thread `tid` the way the inferior is currently being run: all threads continue,
+    /// or only the current one single-steps while the others stay stopped.
+    fn resume_thread(&mut self, tid: Pid) -> Result<(), nix::Error> {
+        let (stepping, current) = (self.stepping, self.current);
+        let thread = match self.thread_mut(tid) {
+            Some(thread) => thread,
+            None => return Ok(()),
+        };
+        if !stepping {
+            ptrace::cont(tid, thread.pending_signal.take())?;
+        } else if tid == current {
+            ptrace::step(tid, thread.pending_signal.take())?;
+        } else {
+            thread.running = false;
+            return Ok(());
+        }
+        thread.running = true;
+        Ok(())
+    }
+
+    /// Continues every thread of the inferior.
+    fn resume_all(&mut self) -> Result<(), nix::Error> {
+        self.stepping = false;
+        for thread in self.threads.iter_mut().filter(|thread| !thread.running) {
+            ptrace::cont(thread.tid, thread.pending_signal.take())?;
+            thread.running = true;
+        }
+        Ok(())
+    }
+
+    /// Executes a single instruction in the current thread, leaving the others stopped.
+    fn step_current(&mut self) -> Result<(), nix::Error> {
+        self.stepping = true;
+        let tid = self.current;
+        let mut signal = None;
+        if let Some(thread) = self.thread_mut(tid) {
+            thread.running = true;
+            signal = thread.pending_signal.take();
+        }
+        ptrace::step(tid, signal)
+    }
+
+    /// Copies the watchpoints into a thread that hasn't had them set up yet.
+    fn initialize_thread(&mut self, tid: Pid) -> Result<(), nix::Error> {
+        let (addrs, control) = (self.debug_addrs, self.debug_control);
+        if let Some(thread) = self.thread_mut(tid) {
+            if !thread.initialized {
+                thread.initialized = true;
+                if control != 0 {
+                    for (slot, addr) in addrs.iter().enumerate() {
+                        write_debug_register(tid, slot, *addr)?;
+                    }
+                    write_debug_register(tid, DR_CONTROL, control)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until some thread of the inferior stops or the whole process terminates, and returns
+    /// a Status to indicate which. Thread creation and exit are dealt with along the way. Once a
+    /// thread has stopped, it becomes the current thread and all other threads are stopped too.
+    pub fn wait(&mut self) -> Result<Status, nix::Error> {
+        loop {
+            match waitpid(Pid::from_raw(-1), Some(WaitPidFlag::__WALL))? {
+                WaitStatus::Exited(tid, exit_code) if tid == self.pid => return Ok(Status::Exited(exit_code)),
+                WaitStatus::Signaled(tid, signal, _core_dumped) if tid == self.pid => {
+                    return Ok(Status::Signaled(signal))
+                }
+                WaitStatus::Exited(tid, _) | WaitStatus::Signaled(tid, _, _) => self.remove_thread(tid),
+                WaitStatus::PtraceEvent(tid, _, event) if event == libc::PTRACE_EVENT_FORK => {
+                    let child = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                    if let Some(thread) = self.thread_mut(tid) {
+                        thread.running = false;
+                    }
+                    self.current = tid;
+                    self.event_thread = tid;
+                    self.stop_all_threads()?;
+                    return Ok(Status::Forked(child));
+                }
+                WaitStatus::PtraceEvent(_, _, event) if event == libc::PTRACE_EVENT_EXEC => {
+                    // Whichever thread called exec, it now carries on as the only thread, under
+                    // the process id. The kernel has cleared its debug registers.
+                    let pid = self.pid;
+                    self.threads.retain(|thread| thread.tid == pid);
+                    if self.threads.is_empty() {
+                        self.add_thread(pid, false);
+                    }
+                    let thread = &mut self.threads[0];
+                    thread.running = false;
+                    thread.stop_pending = false;
+                    thread.pending_signal = None;
+                    self.current = pid;
+                    self.event_thread = pid;
+                    self.debug_addrs = [0; 4];
+                    self.debug_control = 0;
+                    self.unreported_watchpoints.clear();
+                    self.modules.clear();
+                    return Ok(Status::Execed);
+                }
+                WaitStatus::PtraceEvent(tid, _, event) => {
+                    if event == libc::PTRACE_EVENT_CLONE {
+                        let new_tid = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                        // the new thread's SIGSTOP may have been reported before this event
+                        if self.thread(new_tid).is_none() {
+                            self.add_thread(new_tid, true);
+                        }
+                    }
+                    self.resume_thread(tid)?;
+                }
+                WaitStatus::Stopped(tid, signal::Signal::SIGSTOP)
+                    if self.thread(tid).is_none_or(|thread| thread.stop_pending) =>
+                {
+                    // a new thread starting, or a stop we asked for earlier arriving late
+                    let pid = self.pid;
+                    match self.thread_mut(tid) {
+                        Some(thread) => thread.stop_pending = false,
+                        None if is_thread_of(tid, pid) => self.add_thread(tid, false),
+                        None => {
+                            // a forked child, which waits for us to decide what to do with it
+                            self.early_fork_stops.push(tid);
+                            continue;
+                        }
+                    }
+                    self.initialize_thread(tid)?;
+                    self.resume_thread(tid)?;
+                }
+                WaitStatus::Stopped(tid, signal) => {
+                    if self.thread(tid).is_none() {
+                        self.add_thread(tid, false);
+                    }
+                    if let Some(thread) = self.thread_mut(tid) {
+                        thread.running = false;
+                    }
+                    self.current = tid;
+                    self.event_thread = tid;
+                    self.stop_all_threads()?;
+                    let rip = ptrace::getregs(tid)?.rip as usize;
+                    return Ok(match signal {
+                        signal::Signal::SIGTRAP => match self.take_triggered_watchpoint(tid)? {
+                            Some(slot) => Status::Watchpoint(slot, rip),
+                            None => Status::Stopped(signal, rip),
+                        },
+                        _ => Status::Stopped(signal, rip),
+                    });
+                }
+                // only reported for options we don't set
+                _ => {}
+            }
+        }
+    }
+
+    /// Stops every thread that is still running, so that the user sees a frozen process.
+    fn stop_all_threads(&mut self) -> Result<(), nix::Error> {
+        for thread in self.threads.iter_mut().filter(|thread| thread.running && !thread.stop_pending) {
+            // tgkill, so that the signal goes to this particular thread
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_tgkill,
+                    libc::pid_t::from(self.pid),
+                    libc::pid_t::from(thread.tid),
+                    libc::SIGSTOP,
+                )
+            };
+            if ret == 0 {
+                thread.stop_pending = true;
+            }
+        }
+        while let Some(tid) = self.threads.iter().find(|thread| thread.running).map(|thread| thread.tid) {
+            let status = match waitpid(tid, Some(WaitPidFlag::__WALL)) {
+                Ok(status) => status,
+                // the thread is gone
+                Err(_) => {
+                    self.remove_thread(tid);
+                    continue;
+                }
+            };
+            match status {
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                    self.remove_thread(tid);
+                    continue;
+                }
+                WaitStatus::Stopped(_, signal::Signal::SIGSTOP) => {
+                    if let Some(thread) = self.thread_mut(tid) {
+                        thread.stop_pending = false;
+                    }
+                }
+                WaitStatus::Stopped(_, signal::Signal::SIGTRAP) => {
+                    // A watchpoint has already done its work by the time it traps, so it's
+                    // reported next time instead. Its DR6 bit has to be cleared either way, or the
+                    // thread's next trap would look like the watchpoint triggering again.
+                    if let Some(slot) = self.take_triggered_watchpoint(tid)? {
+                        self.unreported_watchpoints.push((tid, slot));
+                    } else {
+                        // If the thread ran into a breakpoint, rewind it so that it hits it again
+                        // when it resumes, rather than reporting two stops at once
+                        let siginfo = ptrace::getsiginfo(tid)?;
+                        let mut regs = ptrace::getregs(tid)?;
+                        let prev_addr = align_addr_to_word(regs.rip as usize - 1);
+                        let prev_byte = ptrace::read(tid, prev_addr as ptrace::AddressType)? as u64
+                            >> (8 * (regs.rip as usize - 1 - prev_addr));
+                        if siginfo.si_code == libc::SI_KERNEL && prev_byte & 0xff == 0xcc {
+                            regs.rip -= 1;
+                            ptrace::setregs(tid, regs)?;
+                        }
+                    }
+                }
+                WaitStatus::Stopped(_, signal) => {
+                    // there's no telling the user about it now, but it shouldn't be lost either
+                    let pass = self.signals.get(signal).pass;
+                    if let Some(thread) = self.thread_mut(tid) {
+                        thread.pending_signal = if pass { Some(signal) } else { None };
+                    }
+                }
+                WaitStatus::PtraceEvent(_, _, event) if event == libc::PTRACE_EVENT_CLONE => {
+                    let new_tid = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                    if self.thread(new_tid).is_none() {
+                        self.add_thread(new_tid, true);
+                    }
+                }
+                WaitStatus::PtraceEvent(_, _, event) if event == libc::PTRACE_EVENT_FORK => {
+                    let child = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                    self.unreported_forks.push((tid, child));
+                }
+                _ => {}
+            }
+            if let Some(thread) = self.thread_mut(tid) {
+                thread.running = false;
+            }
+            self.initialize_thread(tid)?;
+        }
+        Ok(())
     }
 
     // Milestone 1: Run the inferior
-    /// Wakes up the inferior and waits until it stops or terminates
-    pub fn run(&mut self, breakpoints: &mut HashMap<usize, Breakpoint>)-> Result<Status, nix::Error> {
-        // Note: we should be able to use `break *addr` even after the inferio has started running
-        let clone_bps = breakpoints.clone();
-        for bp in clone_bps{
-            self.write_byte(bp.0.clone(), 0xcc).expect("write_byte failed");
-            // we only reinstall the breakpoints, so we don't need to remember the orig_byte again
-        }
-
-        let mut reg_vals = ptrace::getregs(self.pid())?;
-        // if inferior stopped at a breakpoint (i.e. (%rip - 1) matches a breakpoint address):
-        // use .get() to check if (%rip - 1) matches a breakpoint address
-        if let Some(bp) = breakpoints.get(&((reg_vals.rip - 1) as usize)) {
-            // restore the first byte of the instruction we replaced
-            self.write_byte(bp.addr, bp.orig_byte).unwrap();
-            // set %rip = %rip - 1 to rewind the instruction pointer
-            reg_vals.rip -= 1;
-            ptrace::setregs(self.pid(), reg_vals).unwrap();
+    /// Wakes up the inferior and waits until it stops or terminates.
+    /// Breakpoints whose condition is false or that are being ignored are resumed from silently.
+    /// Conditions can refer to the debugger's value `history` (`$1`).
+    pub fn run(
+        &mut self,
+        debug_data: &DwarfData,
+        history: &[Value],
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+    ) -> Result<Status, nix::Error> {
+        self.interrupted_step = None;
+        // a fork we came across while stopping the threads last time is reported before anything
+        // else can happen
+        if let Some((tid, child)) = self.unreported_forks.pop() {
+            self.current = tid;
+            self.event_thread = tid;
+            return Ok(Status::Forked(child));
+        }
+        while let Some((tid, slot)) = self.unreported_watchpoints.pop() {
+            // unless the thread has exited since
+            if let Ok(regs) = ptrace::getregs(tid) {
+                self.current = tid;
+                self.event_thread = tid;
+                return Ok(Status::Watchpoint(slot, regs.rip as usize));
+            }
+        }
+        loop {
+            // if inferior stopped at a breakpoint, we need to execute the original instruction first.
+            // This goes for the thread that hit it even if the user has switched to another one.
+            let status = match self.step_over_breakpoints(breakpoints)? {
+                Some(status) => status,
+                None => {
+                    self.resume_all()?;
+                    self.wait_for_breakpoint(breakpoints)?
+                }
+            };
+            match status {
+                Status::Stopped(signal::Signal::SIGTRAP, rip)
+                    if self.is_handler_return(rip)? || !self.should_stop_at(rip, debug_data, history, breakpoints) =>
+                {
+                    continue;
+                }
+                Status::Stopped(signal, _) if !self.should_stop_for_signal(signal) => continue,
+                _ => {}
+            }
+            return Ok(status);
+        }
+    }
+
+    /// Steps the current thread, and the thread that stopped last if that is another one, past
+    /// the breakpoints they are sitting on. Returns the status if a thread terminated or received
+    /// a signal while doing so.
+    fn step_over_breakpoints(&mut self, breakpoints: &HashMap<usize, Breakpoint>) -> Result<Option<Status>, nix::Error> {
+        let current = self.current;
+        let mut tids = vec![current];
+        if self.event_thread != current && self.thread(self.event_thread).is_some() {
+            tids.push(self.event_thread);
+        }
+        for tid in tids {
+            self.current = tid;
+            if let Some(status) = self.step_over_breakpoint(breakpoints)? {
+                return Ok(Some(status));
+            }
+        }
+        self.current = current;
+        Ok(None)
+    }
+
+    /// Checks whether the current thread stopping at the breakpoint at `addr` is it coming back
+    /// from a signal handler, rather than a new hit.
+    fn is_handler_return(&mut self, addr: usize) -> Result<bool, nix::Error> {
+        if let Some((tid, bp_addr, rsp)) = self.handler_return {
+            if tid == self.tid() && bp_addr == addr && ptrace::getregs(tid)?.rsp == rsp {
+                self.handler_return = None;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Decides whether hitting the breakpoint at `addr` should stop the inferior, based on its
+    /// condition and ignore count, and counts the hit if the condition holds.
+    fn should_stop_at(
+        &mut self,
+        addr: usize,
+        debug_data: &DwarfData,
+        history: &[Value],
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+    ) -> bool {
+        let bp = match breakpoints.get_mut(&addr) {
+            Some(bp) if bp.enabled => bp,
+            _ => return true,
+        };
+        if let Some(exit) = bp.frame_exit {
+            // a deeper call returning to the same address, or another thread passing it
+            match ptrace::getregs(self.tid()) {
+                Ok(regs) if !exit.has_returned(self.current_thread(), regs.rsp as usize) => return false,
+                _ => {}
+            }
+        }
+        if let Some(condition) = &bp.condition {
+            let frame = match self.innermost_frame() {
+                Ok(frame) => frame,
+                Err(_) => return true,
+            };
+            let ctx = Context {
+                inferior: self,
+                debug_data,
+                frame: &frame,
+                history,
+            };
+            match condition.evaluate(&ctx).and_then(|val| val.is_true()) {
+                Ok(false) => return false,
+                Ok(_) => {}
+                Err(err) => {
+                    // stop so the user can fix the condition
+                    println!("Error in testing condition for breakpoint {}: {}", bp.id, err);
+                }
+            }
+        }
+        bp.hit_count += 1;
+        if bp.ignore_count > 0 {
+            bp.ignore_count -= 1;
+            return false;
+        }
+        true
+    }
+
+    /// Executes a single instruction, stepping over the breakpoint at %rip if there is one.
+    pub fn step_instruction(&mut self, breakpoints: &mut HashMap<usize, Breakpoint>) -> Result<Status, nix::Error> {
+        self.interrupted_step = None;
+        loop {
+            let rip = ptrace::getregs(self.tid())?.rip as usize;
+            let status = if is_enabled_at(breakpoints, rip) {
+                // stepping over the breakpoint executes exactly one instruction
+                match self.step_over_breakpoint(breakpoints)? {
+                    Some(status) => status,
+                    None => Status::Stopped(
+                        signal::Signal::SIGTRAP,
+                        ptrace::getregs(self.tid())?.rip as usize,
+                    ),
+                }
+            } else {
+                self.step_current()?;
+                self.wait()?
+            };
+            // a signal that doesn't stop the inferior interrupted the step, so try again
+            if let Status::Stopped(signal, _) = status {
+                if signal != signal::Signal::SIGTRAP && !self.should_stop_for_signal(signal) {
+                    continue;
+                }
+            }
+            // the system call isn't done until the thread is stepped again
+            if let Status::Forked(_) = status {
+                self.interrupted_step = Some(Step::Instruction);
+            }
+            return Ok(status);
+        }
+    }
+
+    /// Executes a single instruction. If it is a call, runs until the called function returns.
+    pub fn next_instruction(
+        &mut self,
+        debug_data: &DwarfData,
+        history: &[Value],
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+    ) -> Result<Status, nix::Error> {
+        let prev_regs = ptrace::getregs(self.tid())?;
+        let status = self.step_instruction(breakpoints)?;
+        if let Status::Stopped(signal::Signal::SIGTRAP, _) = status {
+            if let Some(ret_addr) = self.entered_call(&prev_regs)? {
+                let sp = ptrace::getregs(self.tid())?.rsp as usize;
+                return self.run_until(ret_addr, sp, debug_data, history, breakpoints);
+            }
+        }
+        Ok(status)
+    }
 
-            // Note: the breakpoint is no longer in the code, so we need to reset the breakpoint
+    /// If the inferior is sitting on a breakpoint, temporarily restores the original instruction,
+    /// executes it and puts the breakpoint back. Returns the status if the inferior terminated or
+    /// received a signal while doing so.
+    fn step_over_breakpoint(&mut self, breakpoints: &HashMap<usize, Breakpoint>) -> Result<Option<Status>, nix::Error> {
+        let regs = ptrace::getregs(self.tid())?;
+        let rip = regs.rip as usize;
+        if let Some(bp) = breakpoints.get(&rip).filter(|bp| bp.enabled) {
+            let delivering_signal = self.thread(self.tid()).is_some_and(|thread| thread.pending_signal.is_some());
+            // restore the first byte of the instruction we replaced
+            self.write_byte(bp.addr, bp.orig_byte)?;
             // ptrace::step to go to next instruction
-            ptrace::step(self.pid(), None).unwrap();
+            self.step_current()?;
             // wait for inferior to stop due to SIGTRAP, return if the inferior terminates here
-            match self.wait(None) {
-                Ok(Status::Exited(exit_code)) => return Ok(Status::Exited(exit_code)),
-                Ok(Status::Signaled(signal)) => return Ok(Status::Signaled(signal)),
-                Ok(Status::Stopped(_, _)) => {
+            match self.wait()? {
+                Status::Stopped(signal::Signal::SIGTRAP, new_rip) => {
                     // restore the breakpoint
-                    // self.write_byte(bp.addr, 0xcc).unwrap();
+                    self.write_byte(bp.addr, 0xcc)?;
+                    // If the signal has a handler, the step stops at its start instead, without
+                    // having run the instruction
+                    if delivering_signal && (new_rip <= bp.addr || new_rip > bp.addr + 15) {
+                        self.handler_return = Some((self.tid(), bp.addr, regs.rsp));
+                    }
+                }
+                status @ Status::Stopped(..) | status @ Status::Watchpoint(..) | status @ Status::Forked(_) => {
+                    self.write_byte(bp.addr, 0xcc)?;
+                    return Ok(Some(status));
+                }
+                other => return Ok(Some(other)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Waits for the inferior to stop. If it stopped because it hit a breakpoint (i.e. (%rip - 1)
+    /// matches a breakpoint address), %rip is rewound to point at the breakpoint again.
+    fn wait_for_breakpoint(&mut self, breakpoints: &HashMap<usize, Breakpoint>) -> Result<Status, nix::Error> {
+        match self.wait()? {
+            Status::Stopped(signal::Signal::SIGTRAP, rip) if is_enabled_at(breakpoints, rip.wrapping_sub(1)) => {
+                let mut reg_vals = ptrace::getregs(self.tid())?;
+                reg_vals.rip -= 1;
+                ptrace::setregs(self.tid(), reg_vals)?;
+                Ok(Status::Stopped(signal::Signal::SIGTRAP, rip - 1))
+            }
+            other => Ok(other),
+        }
+    }
+
+    /// Continues until the inferior reaches `addr` with its stack pointer above `sp` (so that
+    /// recursive calls of the same function don't count), or until it stops for any other reason.
+    /// A temporary breakpoint is planted at `addr` for the duration of the call.
+    pub fn run_until(
+        &mut self,
+        addr: usize,
+        sp: usize,
+        debug_data: &DwarfData,
+        history: &[Value],
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+    ) -> Result<Status, nix::Error> {
+        let is_user_breakpoint = breakpoints.get(&addr).is_some_and(|bp| bp.enabled && bp.id > 0);
+        // a disabled breakpoint at `addr`, or an internal one waiting for some other frame to
+        // return there, is set aside while the temporary one is in place
+        let mut set_aside = None;
+        if !is_user_breakpoint {
+            let orig_byte = match breakpoints.get(&addr) {
+                Some(bp) if bp.enabled => bp.orig_byte,
+                _ => self.write_byte(addr, 0xcc)?,
+            };
+            set_aside = breakpoints.insert(addr, Breakpoint::temporary(addr, orig_byte));
+        }
+        let result = loop {
+            match self.run(debug_data, history, breakpoints) {
+                Ok(Status::Stopped(signal::Signal::SIGTRAP, rip)) if rip == addr && !is_user_breakpoint => {
+                    if ptrace::getregs(self.tid())?.rsp as usize > sp {
+                        break Ok(Status::Stopped(signal::Signal::SIGTRAP, rip));
+                    }
+                }
+                other => break other,
+            }
+        };
+        if !is_user_breakpoint {
+            let temp_bp = breakpoints.remove(&addr).unwrap();
+            let keeps_int3 = set_aside.as_ref().is_some_and(|bp| bp.enabled);
+            if let Some(bp) = set_aside {
+                breakpoints.insert(addr, bp);
+            }
+            if !keeps_int3 {
+                // after an exec, the breakpoint went away with the rest of the old program
+                if let Ok(Status::Stopped(..)) | Ok(Status::Watchpoint(..)) | Ok(Status::Forked(_)) = result {
+                    self.write_byte(addr, temp_bp.orig_byte)?;
+                }
+                if let Ok(Status::Forked(_)) = result {
+                    self.fork_temp_breakpoint = Some((addr, temp_bp.orig_byte));
+                }
+            }
+        }
+        if let Ok(Status::Forked(_)) = result {
+            self.interrupted_step = Some(Step::Until(addr, sp));
+        }
+        result
+    }
+
+    /// Carries on with the step that the inferior forked in the middle of, or just continues if
+    /// there isn't one.
+    pub fn resume_step(
+        &mut self,
+        debug_data: &DwarfData,
+        history: &[Value],
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+    ) -> Result<Status, nix::Error> {
+        match self.interrupted_step.take() {
+            None => self.run(debug_data, history, breakpoints),
+            Some(Step::Instruction) => self.step_instruction(breakpoints),
+            Some(Step::Done) => Ok(Status::Stopped(
+                signal::Signal::SIGTRAP,
+                ptrace::getregs(self.tid())?.rip as usize,
+            )),
+            Some(Step::Until(addr, sp)) => self.run_until(addr, sp, debug_data, history, breakpoints),
+            Some(Step::Line { start_line, step_into, until }) => {
+                self.continue_step_line(debug_data, history, breakpoints, start_line, step_into, until)
+            }
+        }
+    }
+
+    /// Single-steps until the inferior reaches a different source line. Calls are stepped over
+    /// (by running to their return address) unless `step_into` is set and the called function
+    /// has line number information.
+    pub fn step_line(
+        &mut self,
+        debug_data: &DwarfData,
+        history: &[Value],
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+        step_into: bool,
+    ) -> Result<Status, nix::Error> {
+        let rip = ptrace::getregs(self.tid())?.rip as usize;
+        let start_line = debug_data.get_line_from_addr(rip);
+        self.continue_step_line(debug_data, history, breakpoints, start_line, step_into, None)
+    }
+
+    /// Carries on stepping until the inferior leaves `start_line`, first running to `until` (a
+    /// return address and stack pointer) if it was in the middle of stepping over a call.
+    fn continue_step_line(
+        &mut self,
+        debug_data: &DwarfData,
+        history: &[Value],
+        breakpoints: &mut HashMap<usize, Breakpoint>,
+        mut start_line: Option<Line>,
+        step_into: bool,
+        mut until: Option<(usize, usize)>,
+    ) -> Result<Status, nix::Error> {
+        loop {
+            if until.is_none() {
+                let prev_regs = ptrace::getregs(self.tid())?;
+                let status = self.step_instruction(breakpoints)?;
+                let rip = match status {
+                    Status::Stopped(signal::Signal::SIGTRAP, rip) => rip,
+                    Status::Forked(child) => {
+                        self.interrupted_step = Some(Step::Line { start_line, step_into, until: None });
+                        return Ok(Status::Forked(child));
+                    }
+                    other => return Ok(other),
+                };
+
+                if let Some(ret_addr) = self.entered_call(&prev_regs)? {
+                    if step_into && debug_data.get_line_from_addr(rip).is_some() {
+                        // Keep stepping through the prologue, which is attributed to the line the
+                        // function is declared on
+                        start_line = debug_data.get_line_from_addr(rip);
+                        continue;
+                    }
+                    until = Some((ret_addr, ptrace::getregs(self.tid())?.rsp as usize));
                 }
-                Err(e) => return Err(e),
             }
+
+            if let Some((ret_addr, sp)) = until.take() {
+                match self.run_until(ret_addr, sp, debug_data, history, breakpoints)? {
+                    Status::Stopped(signal::Signal::SIGTRAP, rip) if rip == ret_addr => {}
+                    Status::Forked(child) => {
+                        let until = Some((ret_addr, sp));
+                        self.interrupted_step = Some(Step::Line { start_line, step_into, until });
+                        return Ok(Status::Forked(child));
+                    }
+                    other => return Ok(other),
+                }
+            }
+
+            let rip = ptrace::getregs(self.tid())?.rip as usize;
+            match (debug_data.get_line_from_addr(rip), &start_line) {
+                (Some(line), Some(start)) if line.file == start.file && line.number == start.number => {}
+                // If we started somewhere without line number information, keep going until we
+                // get back to code that has it
+                (None, None) => {}
+                _ => return Ok(Status::Stopped(signal::Signal::SIGTRAP, rip)),
+            }
+        }
+    }
+
+    /// Checks whether the instruction that was just executed (with registers `prev_regs` before
+    /// it ran) was a call, i.e. whether it pushed the address of the following instruction. If
+    /// so, returns that return address.
+    fn entered_call(&self, prev_regs: &libc::user_regs_struct) -> Result<Option<usize>, nix::Error> {
+        let regs = ptrace::getregs(self.tid())?;
+        if regs.rsp != prev_regs.rsp.wrapping_sub(8) {
+            return Ok(None);
+        }
+        let ret_addr = ptrace::read(self.tid(), regs.rsp as ptrace::AddressType)? as u64;
+        // x86-64 instructions are at most 15 bytes long
+        if ret_addr > prev_regs.rip && ret_addr <= prev_regs.rip + 15 {
+            Ok(Some(ret_addr as usize))
+        } else {
+            Ok(None)
         }
+    }
 
-        ptrace::cont(self.pid(), None)?;
-        self.wait(None)
+    /// Finds the return address of the function the inferior is stopped in. Returns the return
+    /// address along with the location on the stack it is stored at, or None in the outermost
+    /// frame.
+    pub fn get_return_address(&mut self) -> Result<Option<(usize, usize)>, nix::Error> {
+        let frames = self.unwind()?;
+        Ok(match (frames.get(1), frames[0].cfa) {
+            // the call instruction pushed the return address right below the CFA
+            (Some(caller), Some(cfa)) => Some((caller.pc, cfa - size_of::<usize>())),
+            _ => None,
+        })
     }
 
     // Milestone 2. Stopping, resuming, and restarting the inferior
-    /// Kill the inferior && reap the killed process
-    pub fn kill(&mut self) {
-       self.child.kill().expect("Child is not running");  // kill existing inferior
-       self.wait(None).unwrap(); // reap the killed process
+    /// Kill the inferior && reap the killed process. A process that is gone already counts as
+    /// killed.
+    pub fn kill(&mut self) -> Result<(), nix::Error> {
+       let sent = match self.child.as_mut() {
+           Some(child) => child  // kill existing inferior
+               .kill()
+               .map_err(|err| nix::Error::Sys(nix::errno::Errno::from_i32(err.raw_os_error().unwrap_or(0)))),
+           None => signal::kill(self.pid(), signal::Signal::SIGKILL),
+       };
+       match sent {
+           // it died and has been reaped, so there is nothing to wait for
+           Err(nix::Error::Sys(nix::errno::Errno::ESRCH)) => return Ok(()),
+           other => other?,
+       }
+       // reap the killed process, whose threads each report their exit first
+       while let Ok(status) = waitpid(Pid::from_raw(-1), Some(WaitPidFlag::__WALL)) {
+           match status {
+               WaitStatus::Exited(tid, _) | WaitStatus::Signaled(tid, _, _) if tid == self.pid => break,
+               _ => {}
+           }
+       }
        println!("Killing running inferior (pid {})", self.pid());
+       Ok(())
     }
 
     // Milestone 3: Printing a backtrace
-    pub fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        let reg_vals = ptrace::getregs(self.pid())?;
-        let (mut instruction_ptr, mut base_ptr) = (reg_vals.rip as usize, reg_vals.rbp as usize);
-        loop {
-            let lineno = DwarfData::get_line_from_addr(debug_data, instruction_ptr).unwrap();
-            let func_name = DwarfData::get_function_from_addr(debug_data, instruction_ptr).unwrap();
-            println!("{} ({})", func_name, lineno);
-            if func_name == "main" {
-                break;
+    /// Unwinds the current thread's stack, innermost frame first, with a frame for each
+    /// function inlined into the code a frame is executing as well.
+    pub fn backtrace(&mut self, debug_data: &DwarfData) -> Result<Vec<Frame>, nix::Error> {
+        let mut frames = Vec::new();
+        for frame in self.unwind()? {
+            let inlined_calls = debug_data.get_inlined_frames(frame.lookup_pc).len().saturating_sub(1);
+            for inline_depth in 0..=inlined_calls {
+                frames.push(Frame {
+                    inline_depth,
+                    ..frame.clone()
+                });
             }
-            instruction_ptr = ptrace::read(self.pid(), (base_ptr + 8) as ptrace::AddressType)? as usize;
-            base_ptr = ptrace::read(self.pid(), base_ptr as ptrace::AddressType)? as usize;
+        }
+        Ok(frames)
+    }
+
+    /// Unwinds the current thread's stack, innermost frame first, using the call frame
+    /// information of the program and its libraries. The walk ends at the outermost frame
+    /// (`_start`, or where a thread started), or at the first frame that can't be unwound.
+    fn unwind(&mut self) -> Result<Vec<Frame>, nix::Error> {
+        self.unwind_frames(MAX_FRAMES)
+    }
+
+    /// Unwinds at most `limit` frames of the current thread's stack.
+    fn unwind_frames(&mut self, limit: usize) -> Result<Vec<Frame>, nix::Error> {
+        let regs = ptrace::getregs(self.tid())?;
+        let maps = std::fs::read_to_string(format!("/proc/{}/maps", self.pid)).unwrap_or_default();
+        let mappings = unwind::parse_maps(&maps);
+        for mapping in mappings.iter().filter(|mapping| mapping.executable) {
+            if !self.modules.contains_key(&mapping.path) {
+                self.modules.insert(mapping.path.clone(), Module::from_file(&mapping.path));
+            }
+        }
+
+        let (mut pc, mut rsp, mut rbp) = (regs.rip as usize, regs.rsp as usize, regs.rbp as usize);
+        let mut frames: Vec<Frame> = Vec::new();
+        while frames.len() < limit {
+            let lookup_pc = if frames.is_empty() { pc } else { pc - 1 };
+            let mapping = mappings
+                .iter()
+                .find(|mapping| mapping.executable && mapping.start <= lookup_pc && lookup_pc < mapping.end);
+            let mut frame = Frame {
+                pc,
+                lookup_pc,
+                rbp,
+                rsp,
+                innermost: frames.is_empty(),
+                inline_depth: 0,
+                cfa: None,
+                symbol: None,
+                module: mapping.map(|mapping| mapping.path.clone()),
+            };
+            let rules = match mapping.and_then(|mapping| self.module_at(mapping, &mappings)) {
+                Some((module, bias)) => {
+                    let addr = (lookup_pc as u64).wrapping_sub(bias);
+                    frame.symbol = module.symbol_at(addr).map(String::from);
+                    module.find_rules(addr)
+                }
+                None => None,
+            };
+            frames.push(frame);
+            let rules = match rules {
+                Some(rules) => rules,
+                None => break,
+            };
+
+            let cfa = match rules.cfa_register {
+                unwind::RSP => rsp,
+                unwind::RBP => rbp,
+                _ => break,
+            }
+            .wrapping_add(rules.cfa_offset as usize);
+            frames.last_mut().unwrap().cfa = Some(cfa);
+            let ret_addr = match rules.return_address {
+                SavedRegister::AtCfaOffset(offset) => self.read_word(cfa.wrapping_add(offset as usize)),
+                // the outermost frame has no return address
+                _ => None,
+            };
+            rbp = match rules.rbp {
+                SavedRegister::AtCfaOffset(offset) => match self.read_word(cfa.wrapping_add(offset as usize)) {
+                    Some(val) => val,
+                    None => break,
+                },
+                SavedRegister::CfaOffset(offset) => cfa.wrapping_add(offset as usize),
+                _ => rbp,
+            };
+            // the stack only grows one way, so anything else means we have lost track
+            match ret_addr {
+                Some(ret_addr) if ret_addr != 0 && cfa > rsp => {
+                    pc = ret_addr;
+                    rsp = cfa;
+                }
+                _ => break,
+            }
+        }
+        Ok(frames)
+    }
+
+    /// Returns the frame the current thread is executing in, with its CFA worked out from the
+    /// call frame information as a backtrace would, but without unwinding the rest of the stack.
+    fn innermost_frame(&mut self) -> Result<Frame, nix::Error> {
+        Ok(self.unwind_frames(1)?.remove(0))
+    }
+
+    /// Finds the module `mapping` belongs to, along with how far it was moved from its link-time
+    /// addresses when it was loaded.
+    fn module_at(&self, mapping: &Mapping, mappings: &[Mapping]) -> Option<(&Module, u64)> {
+        let module = self.modules.get(&mapping.path)?.as_ref()?;
+        let start = mappings
+            .iter()
+            .find(|other| other.path == mapping.path && other.offset == 0)?
+            .start;
+        Some((module, (start as u64).wrapping_sub(module.base_addr())))
+    }
+
+    fn read_word(&self, addr: usize) -> Option<usize> {
+        ptrace::read(self.tid(), addr as ptrace::AddressType).ok().map(|word| word as usize)
+    }
+
+    /// Returns the current register values of the inferior.
+    pub fn get_registers(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        ptrace::getregs(self.tid())
+    }
+
+    /// Changes the register values of the inferior's current thread.
+    pub fn set_registers(&self, regs: libc::user_regs_struct) -> Result<(), nix::Error> {
+        ptrace::setregs(self.tid(), regs)
+    }
+
+    /// Returns the current x87/SSE register values of the inferior.
+    pub fn get_fp_registers(&self) -> Result<libc::user_fpregs_struct, nix::Error> {
+        let mut fpregs: libc::user_fpregs_struct = unsafe { std::mem::zeroed() };
+        // nix doesn't wrap PTRACE_GETFPREGS, so call it directly
+        let ret = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GETFPREGS,
+                libc::pid_t::from(self.tid()),
+                std::ptr::null_mut::<libc::c_void>(),
+                &mut fpregs as *mut libc::user_fpregs_struct as *mut libc::c_void,
+            )
+        };
+        nix::errno::Errno::result(ret)?;
+        Ok(fpregs)
+    }
+
+    /// Programs debug register `slot` (0-3) in every thread to trap when `len` bytes (1, 2, 4 or
+    /// 8, aligned to `len`) at `addr` are accessed as described by `kind`.
+    pub fn set_watchpoint(&mut self, slot: usize, addr: usize, len: usize, kind: WatchKind) -> Result<(), nix::Error> {
+        // x86 can't trap on reads alone, so read watchpoints trap on any access and the debugger
+        // skips the ones that changed the value
+        let rw_bits = match kind {
+            WatchKind::Write => 0b01,
+            WatchKind::Read | WatchKind::Access => 0b11,
+        };
+        let len_bits = match len {
+            1 => 0b00,
+            2 => 0b01,
+            4 => 0b11,
+            _ => 0b10,
+        };
+        self.debug_addrs[slot] = addr as u64;
+        let mut dr7 = self.debug_control;
+        dr7 &= !(0b1111 << (16 + slot * 4));
+        dr7 |= (rw_bits | (len_bits << 2)) << (16 + slot * 4);
+        // local enable bit
+        dr7 |= 1 << (slot * 2);
+        self.debug_control = dr7;
+        for thread in &self.threads {
+            write_debug_register(thread.tid, slot, addr as u64)?;
+            write_debug_register(thread.tid, DR_CONTROL, dr7)?;
+        }
+        Ok(())
+    }
+
+    /// Disables the watchpoint in debug register `slot`.
+    pub fn clear_watchpoint(&mut self, slot: usize) -> Result<(), nix::Error> {
+        self.debug_control &= !(0b11 << (slot * 2));
+        for thread in &self.threads {
+            write_debug_register(thread.tid, DR_CONTROL, self.debug_control)?;
         }
         Ok(())
     }
 
-    fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
-        let aligned_addr = align_addr_to_word(addr);
-        let byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
-        let orig_byte = (word >> 8 * byte_offset) & 0xff;
-        let masked_word = word & !(0xff << 8 * byte_offset);
-        let updated_word = masked_word | ((val as u64) << 8 * byte_offset);
-        ptrace::write(
-            self.pid(),
-            aligned_addr as ptrace::AddressType,
-            updated_word as *mut std::ffi::c_void,
-        )?;
-        Ok(orig_byte as u8)
+    /// Checks a thread's DR6 for a watchpoint that has triggered and resets it, since the CPU
+    /// never clears it by itself.
+    fn take_triggered_watchpoint(&self, tid: Pid) -> Result<Option<usize>, nix::Error> {
+        let dr6 = read_debug_register(tid, DR_STATUS)?;
+        if dr6 & 0b1111 == 0 {
+            return Ok(None);
+        }
+        write_debug_register(tid, DR_STATUS, 0)?;
+        // DR6 may also flag disabled slots whose address happened to match
+        let dr7 = self.debug_control;
+        Ok((0..4).find(|slot| dr6 & (1 << slot) != 0 && dr7 & (1 << (slot * 2)) != 0))
+    }
+
+    /// Reads the NUL-terminated string at `addr`, up to `max_len` bytes of it. Returns the string
+    /// (without the NUL) and whether its end was found.
+    pub fn read_string(&self, addr: usize, max_len: usize) -> Result<(Vec<u8>, bool), nix::Error> {
+        let mut bytes = Vec::new();
+        // read a word at a time, since the string may end right before unmapped memory
+        while bytes.len() < max_len {
+            let chunk_addr = addr + bytes.len();
+            let chunk_len = size_of::<usize>() - chunk_addr % size_of::<usize>();
+            let chunk = match self.read_memory(chunk_addr, chunk_len) {
+                Ok(chunk) => chunk,
+                Err(err) if bytes.is_empty() => return Err(err),
+                Err(_) => break,
+            };
+            for byte in chunk {
+                if byte == 0 {
+                    return Ok((bytes, true));
+                }
+                bytes.push(byte);
+            }
+        }
+        bytes.truncate(max_len);
+        Ok((bytes, false))
+    }
+
+    /// Reads `len` bytes of the inferior's memory, starting at `addr`.
+    pub fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let end = end_addr(addr, len)?;
+        let mut bytes = Vec::with_capacity(len);
+        for word_addr in (align_addr_to_word(addr)..end).step_by(size_of::<usize>()) {
+            let word = ptrace::read(self.tid(), word_addr as ptrace::AddressType)? as u64;
+            for (i, byte) in word.to_le_bytes().iter().enumerate() {
+                let byte_addr = word_addr + i;
+                if byte_addr >= addr && byte_addr < end {
+                    bytes.push(*byte);
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Works out where a variable is in the given stack frame: somewhere in memory, or (for
+    /// variables kept in registers or computed by the compiler) only as a value.
+    pub fn locate_variable(&self, var: &Variable, frame: &Frame, debug_data: &DwarfData) -> Result<Place, String> {
+        let frame_base = debug_data
+            .get_function_at_addr(frame.lookup_pc)
+            .and_then(|func| func.frame_base.as_ref());
+        let target = FrameTarget {
+            inferior: self,
+            frame,
+            load_bias: debug_data.load_bias(),
+        };
+        location::locate(&var.location, frame_base, &target)
+    }
+
+    /// Reads `len` bytes of a value from wherever it was located.
+    pub fn read_place(&self, place: &Place, len: usize) -> Result<Vec<u8>, String> {
+        match place {
+            Place::Memory(addr) => self
+                .read_memory(*addr, len)
+                .map_err(|_| format!("Cannot access memory at address {:#x}", addr)),
+            Place::Value(bytes) => {
+                let mut bytes = bytes.clone();
+                bytes.resize(len, 0);
+                Ok(bytes)
+            }
+            Place::OptimizedOut => Err("value has been optimized out".to_string()),
+        }
+    }
+
+    /// Writes a single byte into the inferior's memory and returns the byte that was there before.
+    pub fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
+        let orig_byte = self.read_memory(addr, 1)?[0];
+        self.write_memory(addr, &[val])?;
+        Ok(orig_byte)
+    }
+
+    /// Writes `bytes` into the inferior's memory at `addr`. ptrace writes whole words, so the
+    /// bytes around them in the first and last words are read and written back unchanged.
+    pub fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> Result<(), nix::Error> {
+        let end = end_addr(addr, bytes.len())?;
+        for word_addr in (align_addr_to_word(addr)..end).step_by(size_of::<usize>()) {
+            let word = ptrace::read(self.tid(), word_addr as ptrace::AddressType)? as u64;
+            let mut word_bytes = word.to_le_bytes();
+            for (i, byte) in word_bytes.iter_mut().enumerate() {
+                let byte_addr = word_addr + i;
+                if byte_addr >= addr && byte_addr < end {
+                    *byte = bytes[byte_addr - addr];
+                }
+            }
+            ptrace::write(
+                self.tid(),
+                word_addr as ptrace::AddressType,
+                u64::from_le_bytes(word_bytes) as *mut std::ffi::c_void,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Gives the location module access to the registers and memory of a stack frame.
+struct FrameTarget<'a> {
+    inferior: &'a Inferior,
+    frame: &'a Frame,
+    load_bias: usize,
+}
+
+impl location::Target for FrameTarget<'_> {
+    fn lookup_pc(&self) -> usize {
+        self.frame.lookup_pc
+    }
+
+    fn cfa(&self) -> usize {
+        // Frames that weren't unwound have no CFA, but it's %rbp + 16 once the function
+        // prologue has pushed %rbp
+        self.frame.cfa.unwrap_or(self.frame.rbp + 16)
+    }
+
+    fn load_bias(&self) -> usize {
+        self.load_bias
+    }
+
+    fn register(&self, register: u16) -> Option<Vec<u8>> {
+        let val = match registers::DWARF_REGISTERS.get(register as usize) {
+            Some(&"rbp") => self.frame.rbp as u64,
+            Some(&"rsp") => self.frame.rsp as u64,
+            Some(&"rip") => self.frame.pc as u64,
+            // the unwinder doesn't recover callers' other registers
+            _ if !self.frame.innermost => return None,
+            Some(name) => registers::get_register(&self.inferior.get_registers().ok()?, name)?,
+            None => {
+                let xmm = register.checked_sub(registers::DWARF_XMM0)? as usize;
+                let fpregs = self.inferior.get_fp_registers().ok()?;
+                let words = fpregs.xmm_space.get(xmm * 4..xmm * 4 + 4)?;
+                return Some(words.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect());
+            }
+        };
+        Some(val.to_le_bytes().to_vec())
+    }
+
+    fn read_memory(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        self.inferior.read_memory(addr, len).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nix::unistd::{fork, ForkResult};
+
+    /// What the traced process in `test_run_until_across_fork` calls once it has forked
+    #[inline(never)]
+    extern "C" fn after_fork() {}
+
+    /// Kills a process when the test is over, whether or not it passed, so that it isn't left
+    /// stopped
+    struct KillOnDrop(Pid);
+
+    impl Drop for KillOnDrop {
+        fn drop(&mut self) {
+            signal::kill(self.0, signal::Signal::SIGKILL).ok();
+            waitpid(self.0, None).ok();
+        }
+    }
+
+    #[test]
+    fn test_run_until_across_fork() {
+        let addr = after_fork as *const () as usize;
+        let orig_byte = unsafe { *(addr as *const u8) };
+        // a copy of this process, so that after_fork is at the same address in it
+        let pid = match fork().unwrap() {
+            ForkResult::Child => unsafe {
+                libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0);
+                libc::raise(libc::SIGSTOP);
+                if libc::fork() == 0 {
+                    libc::_exit(3);
+                }
+                after_fork();
+                libc::_exit(0);
+            },
+            ForkResult::Parent { child } => child,
+        };
+        let _guard = KillOnDrop(pid);
+        waitpid(pid, None).unwrap();
+        ptrace::setoptions(pid, trace_options()).unwrap();
+        let mut inferior = Inferior::with_pid(pid, None);
+        inferior.add_thread(pid, false);
+        let debug_data = DwarfData::from_file(std::env::current_exe().unwrap().to_str().unwrap()).unwrap();
+        let mut breakpoints = HashMap::new();
+
+        let child = match inferior.run_until(addr, 0, &debug_data, &[], &mut breakpoints) {
+            Ok(Status::Forked(child)) => child,
+            _ => panic!("expected the process to fork"),
+        };
+        let _child_guard = KillOnDrop(child);
+        // the temporary breakpoint is out of both processes until the step carries on
+        assert!(breakpoints.is_empty());
+        assert_eq!(inferior.read_memory(addr, 1).unwrap(), [orig_byte]);
+        let child = inferior.take_fork_child(child).unwrap();
+        assert_eq!(child.read_memory(addr, 1).unwrap(), [orig_byte]);
+        child.detach(&breakpoints).unwrap();
+
+        match inferior.resume_step(&debug_data, &[], &mut breakpoints) {
+            Ok(Status::Stopped(signal::Signal::SIGTRAP, rip)) => assert_eq!(rip, addr),
+            _ => panic!("expected the step to carry on to after_fork"),
+        }
+        assert_eq!(inferior.read_memory(addr, 1).unwrap(), [orig_byte]);
     }
 }
@@ -0,0 +1,245 @@
+//! Works out where a variable is from its DWARF location description. At -O0 that's nearly
+//! always a fixed address or an offset from the frame base, but optimized code keeps variables
+//! in registers, moves them around as the function runs (location lists), splits them into
+//! pieces, or only knows how to recompute them. This module runs those descriptions against a
+//! stopped program using gimli's expression evaluator.
+
+use crate::dwarf_data::Location;
+use gimli::{EndianSlice, EvaluationResult, LittleEndian};
+
+/// Where a variable's value can be found
+#[derive(Debug, Clone, PartialEq)]
+pub enum Place {
+    /// In memory at this address
+    Memory(usize),
+    /// Not in memory at all (in registers, or computed from them), so this is the value itself
+    Value(Vec<u8>),
+    /// The variable doesn't exist at the current pc
+    OptimizedOut,
+}
+
+/// The parts of a stopped program's stack frame that locations can refer to
+pub trait Target {
+    /// The pc to pick location list entries with (for callers, inside the call instruction)
+    fn lookup_pc(&self) -> usize;
+    /// The canonical frame address (DW_OP_call_frame_cfa)
+    fn cfa(&self) -> usize;
+    /// How far the program was moved from its link-time addresses when it was loaded
+    fn load_bias(&self) -> usize;
+    /// The value of a register, by DWARF register number, or None if it isn't known
+    fn register(&self, register: u16) -> Option<Vec<u8>>;
+    fn read_memory(&self, addr: usize, len: usize) -> Option<Vec<u8>>;
+}
+
+/// Finds where a variable with the given location is. `frame_base` is the DW_AT_frame_base of
+/// the function the frame is in, which DW_OP_fbreg offsets are relative to.
+pub fn locate(location: &Location, frame_base: Option<&Location>, target: &dyn Target) -> Result<Place, String> {
+    match location {
+        Location::Address(addr) => Ok(Place::Memory(*addr)),
+        Location::FramePointerOffset(offset) => {
+            let base = get_frame_base(frame_base, target)?;
+            Ok(Place::Memory((base as isize + offset) as usize))
+        }
+        Location::Expression(bytes, encoding) => evaluate(bytes, *encoding, frame_base, target),
+        Location::List(entries) => {
+            let pc = target.lookup_pc();
+            match entries.iter().find(|(start, end, _)| *start <= pc && pc < *end) {
+                Some((_, _, location)) => locate(location, frame_base, target),
+                // the variable isn't live here
+                None => Ok(Place::OptimizedOut),
+            }
+        }
+        Location::Value(bytes) => Ok(Place::Value(bytes.clone())),
+        Location::OptimizedOut => Ok(Place::OptimizedOut),
+    }
+}
+
+/// Evaluates the frame base. gcc describes it as the CFA, clang as the contents of %rbp.
+/// Functions without one are assumed to use the CFA.
+fn get_frame_base(frame_base: Option<&Location>, target: &dyn Target) -> Result<usize, String> {
+    let frame_base = match frame_base {
+        Some(frame_base) => frame_base,
+        None => return Ok(target.cfa()),
+    };
+    match locate(frame_base, None, target)? {
+        Place::Memory(addr) => Ok(addr),
+        Place::Value(bytes) => Ok(to_u64(&bytes) as usize),
+        Place::OptimizedOut => Err("The frame base is not available".to_string()),
+    }
+}
+
+fn evaluate(
+    bytes: &[u8],
+    encoding: gimli::Encoding,
+    frame_base: Option<&Location>,
+    target: &dyn Target,
+) -> Result<Place, String> {
+    let mut evaluation = gimli::Expression(EndianSlice::new(bytes, LittleEndian)).evaluation(encoding);
+    let mut result = evaluation.evaluate();
+    loop {
+        result = match result.map_err(|err| format!("Invalid DWARF expression: {}", err))? {
+            EvaluationResult::Complete => break,
+            // DW_OP_deref_type and DW_OP_regval_type want values of a base type from the DIEs,
+            // which gcc only emits for floating point and vector variables in optimized code
+            EvaluationResult::RequiresMemory { base_type, .. } | EvaluationResult::RequiresRegister { base_type, .. }
+                if base_type.0 != 0 =>
+            {
+                return Err("Unsupported typed DWARF location expression".to_string())
+            }
+            EvaluationResult::RequiresMemory { address, size, .. } => {
+                let bytes = target
+                    .read_memory(address as usize, size as usize)
+                    .ok_or_else(|| format!("Cannot access memory at address {:#x}", address))?;
+                evaluation.resume_with_memory(gimli::Value::Generic(to_u64(&bytes)))
+            }
+            EvaluationResult::RequiresRegister { register, .. } => {
+                let bytes = read_register(register.0, target)?;
+                evaluation.resume_with_register(gimli::Value::Generic(to_u64(&bytes)))
+            }
+            EvaluationResult::RequiresFrameBase => {
+                evaluation.resume_with_frame_base(get_frame_base(frame_base, target)? as u64)
+            }
+            EvaluationResult::RequiresCallFrameCfa => evaluation.resume_with_call_frame_cfa(target.cfa() as u64),
+            EvaluationResult::RequiresRelocatedAddress(addr) => {
+                evaluation.resume_with_relocated_address(addr.wrapping_add(target.load_bias() as u64))
+            }
+            // DW_OP_entry_value wants a register as it was when the function was called, which
+            // is long gone unless the caller happened to keep it
+            EvaluationResult::RequiresEntryValue(_) => return Ok(Place::OptimizedOut),
+            EvaluationResult::RequiresTls(_) => return Err("Cannot access thread-local variables".to_string()),
+            _ => return Err("Unsupported DWARF location expression".to_string()),
+        };
+    }
+    assemble(evaluation.result(), target)
+}
+
+/// Puts a variable together from the pieces an expression evaluated to.
+fn assemble(pieces: Vec<gimli::Piece<EndianSlice<LittleEndian>>>, target: &dyn Target) -> Result<Place, String> {
+    if pieces.is_empty() {
+        return Ok(Place::OptimizedOut);
+    }
+    if let [piece] = pieces.as_slice() {
+        if let gimli::Location::Address { address } = piece.location {
+            if piece.size_in_bits.is_none() {
+                return Ok(Place::Memory(address as usize));
+            }
+        }
+    }
+    let mut bytes = Vec::new();
+    for piece in pieces {
+        let size = match piece.size_in_bits {
+            Some(bits) if bits % 8 != 0 || piece.bit_offset.unwrap_or(0) != 0 => {
+                return Err("Cannot access variables split into bit pieces".to_string())
+            }
+            Some(bits) => Some((bits / 8) as usize),
+            None => None,
+        };
+        let mut value = match piece.location {
+            // gdb would show the rest of the variable, but a partial value is rarely useful
+            gimli::Location::Empty => return Ok(Place::OptimizedOut),
+            gimli::Location::Register { register } => read_register(register.0, target)?,
+            gimli::Location::Address { address } => target
+                .read_memory(address as usize, size.unwrap_or(0))
+                .ok_or_else(|| format!("Cannot access memory at address {:#x}", address))?,
+            gimli::Location::Value { value } => value
+                .to_u64(!0)
+                .map_err(|err| format!("Invalid DWARF expression: {}", err))?
+                .to_le_bytes()
+                .to_vec(),
+            gimli::Location::Bytes { value } => value.slice().to_vec(),
+            gimli::Location::ImplicitPointer { .. } => {
+                return Err("Cannot access values behind implicit pointers".to_string())
+            }
+        };
+        if let Some(size) = size {
+            value.resize(size, 0);
+        }
+        bytes.extend(value);
+    }
+    Ok(Place::Value(bytes))
+}
+
+fn read_register(register: u16, target: &dyn Target) -> Result<Vec<u8>, String> {
+    target.register(register).ok_or_else(|| {
+        let name = match crate::registers::DWARF_REGISTERS.get(register as usize) {
+            Some(name) => name.to_string(),
+            None => format!("DWARF register {}", register),
+        };
+        format!("The value of ${} is not available in this frame", name)
+    })
+}
+
+fn to_u64(bytes: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    let len = bytes.len().min(8);
+    word[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(word)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A frame with %rbx = 7 and a 16-byte stack at 0x1000 holding 0x2a at 0x1008
+    struct FakeTarget;
+
+    impl Target for FakeTarget {
+        fn lookup_pc(&self) -> usize {
+            0x401010
+        }
+        fn cfa(&self) -> usize {
+            0x1010
+        }
+        fn load_bias(&self) -> usize {
+            0
+        }
+        fn register(&self, register: u16) -> Option<Vec<u8>> {
+            match register {
+                3 => Some(7u64.to_le_bytes().to_vec()),
+                7 => Some(0x1000u64.to_le_bytes().to_vec()),
+                _ => None,
+            }
+        }
+        fn read_memory(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+            let mut stack = [0; 16];
+            stack[8] = 0x2a;
+            stack.get(addr.checked_sub(0x1000)?..addr.checked_sub(0x1000)? + len).map(|bytes| bytes.to_vec())
+        }
+    }
+
+    fn expression(bytes: &[u8]) -> Location {
+        let encoding = gimli::Encoding {
+            format: gimli::Format::Dwarf32,
+            version: 4,
+            address_size: 8,
+        };
+        Location::Expression(bytes.to_vec(), encoding)
+    }
+
+    #[test]
+    fn test_locate() {
+        let call_frame_cfa = expression(&[0x9c]);
+        // DW_OP_fbreg -8
+        let place = locate(&Location::FramePointerOffset(-8), Some(&call_frame_cfa), &FakeTarget);
+        assert_eq!(place, Ok(Place::Memory(0x1008)));
+        // DW_OP_reg3 (rbx)
+        assert_eq!(locate(&expression(&[0x53]), None, &FakeTarget), Ok(Place::Value(7u64.to_le_bytes().to_vec())));
+        // DW_OP_breg7 (rsp) 8; DW_OP_deref; DW_OP_lit1; DW_OP_plus; DW_OP_stack_value
+        let place = locate(&expression(&[0x77, 0x08, 0x06, 0x31, 0x22, 0x9f]), None, &FakeTarget);
+        assert_eq!(place, Ok(Place::Value(0x2bu64.to_le_bytes().to_vec())));
+        // DW_OP_reg3; DW_OP_piece 4; DW_OP_piece 4
+        assert_eq!(locate(&expression(&[0x53, 0x93, 0x04, 0x93, 0x04]), None, &FakeTarget), Ok(Place::OptimizedOut));
+        // DW_OP_regval_type rbx, with the generic type and with a base type DIE
+        assert_eq!(locate(&expression(&[0xa5, 0x03, 0x00, 0x9f]), None, &FakeTarget), Ok(Place::Value(7u64.to_le_bytes().to_vec())));
+        assert!(locate(&expression(&[0xa5, 0x03, 0x2a, 0x9f]), None, &FakeTarget).is_err());
+        // DW_OP_reg0 isn't known
+        assert!(locate(&expression(&[0x50]), None, &FakeTarget).is_err());
+        let list = Location::List(vec![
+            (0x401000, 0x401008, Location::Address(0x1000)),
+            (0x401008, 0x401020, expression(&[0x53, 0x93, 0x04, 0x77, 0x08, 0x93, 0x04])),
+        ]);
+        assert_eq!(locate(&list, None, &FakeTarget), Ok(Place::Value(vec![7, 0, 0, 0, 0x2a, 0, 0, 0])));
+        let list = Location::List(vec![(0x401000, 0x401008, Location::Address(0x1000))]);
+        assert_eq!(locate(&list, None, &FakeTarget), Ok(Place::OptimizedOut));
+    }
+}
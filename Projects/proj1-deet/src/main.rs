@@ -1,24 +1,82 @@
 mod debugger;
 mod debugger_command;
+mod disassembler;
 mod inferior;
+mod registers;
 mod dwarf_data;
+mod examine;
+mod expression;
 mod gimli_wrapper;
+mod location;
+mod script;
+mod signals;
+mod source;
+mod tui;
+mod unwind;
 
 use crate::debugger::Debugger;
+use crate::tui::Layout;
 use nix::sys::signal::{signal, SigHandler, Signal};
 use std::env;
 
+fn usage(program: &str) -> ! {
+    println!("Usage: {} [options] <target program>", program);
+    println!("       {} [options] -p <pid>", program);
+    println!("Options:");
+    println!("  -x <file>  run the commands in <file> first (may be given more than once)");
+    println!("  -batch     exit once those commands have run");
+    println!("  -tui       start with the source pane up instead of the plain command line");
+    std::process::exit(1);
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
-        std::process::exit(1);
+    let mut args = env::args();
+    let program = args.next().unwrap_or_else(|| "deet".to_string());
+    let mut scripts = Vec::new();
+    let mut batch = false;
+    let mut tui = false;
+    // Either `deet <target program>` or `deet -p <pid>` to attach to a running process
+    let mut target = None;
+    let mut pid = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-x" => scripts.push(args.next().unwrap_or_else(|| usage(&program))),
+            "-batch" => batch = true,
+            "-tui" => tui = true,
+            "-p" if pid.is_none() => {
+                let arg = args.next().unwrap_or_else(|| usage(&program));
+                match arg.parse::<i32>() {
+                    Ok(val) => pid = Some(val),
+                    Err(_) => {
+                        println!("Invalid pid {}", arg);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            _ if !arg.starts_with('-') && target.is_none() => target = Some(arg),
+            _ => usage(&program),
+        }
     }
-    let target = &args[1];
+    let target = match (target, pid) {
+        (Some(target), None) => target,
+        (None, Some(pid)) => format!("/proc/{}/exe", pid),
+        _ => usage(&program),
+    };
 
     // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
     // processes)
     unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
 
-    Debugger::new(target).run();
+    let mut debugger = Debugger::new(&target);
+    if tui && !batch {
+        debugger.enable_tui(Layout::Source);
+    }
+    if let Some(pid) = pid {
+        debugger.attach(pid);
+    }
+    // command files are run innermost first, so queue the last one first
+    for script in scripts.iter().rev() {
+        debugger.source(script);
+    }
+    debugger.run(batch);
 }
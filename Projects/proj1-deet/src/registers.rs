@@ -0,0 +1,120 @@
+use libc::user_regs_struct;
+use std::fmt::{Display, LowerExp};
+
+/// The registers `info registers` shows, in the order it shows them
+pub const GENERAL_REGISTERS: [&str; 24] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip", "eflags", "cs", "ss", "ds", "es", "fs", "gs",
+];
+
+/// The general purpose registers by DWARF register number, as assigned by the x86-64 psABI
+pub const DWARF_REGISTERS: [&str; 17] = [
+    "rax", "rdx", "rcx", "rbx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip",
+];
+/// DWARF register number of %xmm0; %xmm1-%xmm15 follow it
+pub const DWARF_XMM0: u16 = 17;
+
+/// Names of the flags in eflags, by bit
+const EFLAGS: [(u32, &str); 9] = [
+    (0, "CF"),
+    (2, "PF"),
+    (4, "AF"),
+    (6, "ZF"),
+    (7, "SF"),
+    (8, "TF"),
+    (9, "IF"),
+    (10, "DF"),
+    (11, "OF"),
+];
+
+/// Returns the field of `regs` holding the register called `name` (without the `$`). $pc, $sp
+/// and $fp are accepted as aliases of rip, rsp and rbp.
+fn register_mut<'a>(regs: &'a mut user_regs_struct, name: &str) -> Option<&'a mut u64> {
+    match name {
+        "rax" => Some(&mut regs.rax),
+        "rbx" => Some(&mut regs.rbx),
+        "rcx" => Some(&mut regs.rcx),
+        "rdx" => Some(&mut regs.rdx),
+        "rsi" => Some(&mut regs.rsi),
+        "rdi" => Some(&mut regs.rdi),
+        "rbp" | "fp" => Some(&mut regs.rbp),
+        "rsp" | "sp" => Some(&mut regs.rsp),
+        "r8" => Some(&mut regs.r8),
+        "r9" => Some(&mut regs.r9),
+        "r10" => Some(&mut regs.r10),
+        "r11" => Some(&mut regs.r11),
+        "r12" => Some(&mut regs.r12),
+        "r13" => Some(&mut regs.r13),
+        "r14" => Some(&mut regs.r14),
+        "r15" => Some(&mut regs.r15),
+        "rip" | "pc" => Some(&mut regs.rip),
+        "eflags" => Some(&mut regs.eflags),
+        "cs" => Some(&mut regs.cs),
+        "ss" => Some(&mut regs.ss),
+        "ds" => Some(&mut regs.ds),
+        "es" => Some(&mut regs.es),
+        "fs" => Some(&mut regs.fs),
+        "gs" => Some(&mut regs.gs),
+        "fs_base" => Some(&mut regs.fs_base),
+        "gs_base" => Some(&mut regs.gs_base),
+        _ => None,
+    }
+}
+
+pub fn get_register(regs: &user_regs_struct, name: &str) -> Option<u64> {
+    let mut regs = *regs;
+    register_mut(&mut regs, name).map(|val| *val)
+}
+
+/// Changes the register called `name` in `regs`. Returns false if there is no such register.
+pub fn set_register(regs: &mut user_regs_struct, name: &str, val: u64) -> bool {
+    match register_mut(regs, name) {
+        Some(reg) => {
+            *reg = val;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Lists the flags set in an eflags value, highest bit first, e.g. `[ IF ZF PF ]`.
+pub fn format_eflags(eflags: u64) -> String {
+    let flags: Vec<&str> = EFLAGS
+        .iter()
+        .rev()
+        .filter(|(bit, _)| eflags & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    format!("[ {} ]", flags.join(" "))
+}
+
+/// Formats a float from a vector register, switching to scientific notation for the very large and
+/// very small values that reinterpreted integers tend to produce.
+pub fn format_float<F: Into<f64> + Copy + Display + LowerExp>(val: F) -> String {
+    let magnitude = val.into().abs();
+    if magnitude == 0.0 || !magnitude.is_finite() || (1e-4..1e16).contains(&magnitude) {
+        format!("{}", val)
+    } else {
+        format!("{:e}", val)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_registers() {
+        let mut regs: user_regs_struct = unsafe { std::mem::zeroed() };
+        assert!(set_register(&mut regs, "rax", 5));
+        assert!(set_register(&mut regs, "pc", 0x401136));
+        assert!(!set_register(&mut regs, "xyz", 1));
+        assert_eq!(get_register(&regs, "rax"), Some(5));
+        assert_eq!(get_register(&regs, "rip"), Some(0x401136));
+        assert_eq!(format_eflags(0x246), "[ IF ZF PF ]");
+        assert_eq!(format_float(1.5), "1.5");
+        assert_eq!(format_float(3e-300), "3e-300");
+        assert_eq!(format_float(13.3929f32), "13.3929");
+    }
+}
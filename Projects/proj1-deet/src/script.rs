@@ -0,0 +1,75 @@
+//! Commands that come from somewhere other than the prompt: command files (`deet -x file` and
+//! `source file`) and the commands attached to breakpoints with `commands`. They're queued up and
+//! read in place of the prompt, one line at a time, so they go through the same parsing as
+//! typed commands.
+
+use std::fs;
+
+/// Lines of commands to run before reading from the prompt again
+pub struct Script {
+    /// Where the commands came from, for error messages: a file name, or `breakpoint N`
+    name: String,
+    lines: Vec<String>,
+    /// Index of the next line to run
+    position: usize,
+    /// Set for a breakpoint's commands, which are abandoned once the inferior resumes
+    pub from_breakpoint: bool,
+}
+
+impl Script {
+    fn new(name: String, lines: Vec<String>, from_breakpoint: bool) -> Script {
+        Script {
+            name,
+            lines,
+            position: 0,
+            from_breakpoint,
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Script, String> {
+        let contents = fs::read_to_string(path).map_err(|err| format!("{}: {}.", path, err))?;
+        Ok(Script::new(path.to_string(), contents.lines().map(String::from).collect(), false))
+    }
+
+    pub fn for_breakpoint(id: usize, commands: &[String]) -> Script {
+        Script::new(format!("breakpoint {}", id), commands.to_vec(), true)
+    }
+
+    /// Returns the next command, skipping blank lines and `#` comments, or None at the end.
+    pub fn next_line(&mut self) -> Option<String> {
+        while self.position < self.lines.len() {
+            let line = self.lines[self.position].trim();
+            self.position += 1;
+            if !line.is_empty() && !line.starts_with('#') {
+                return Some(line.to_string());
+            }
+        }
+        None
+    }
+
+    /// Where the line last returned is, as `name:line`.
+    pub fn location(&self) -> String {
+        format!("{}:{}", self.name, self.position)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_next_line() {
+        let lines = ["# set up", "break main", "", "  run  ", "print x"];
+        let mut script = Script::new("test.deet".to_string(), lines.iter().map(|s| s.to_string()).collect(), false);
+        assert_eq!(script.next_line().as_deref(), Some("break main"));
+        assert_eq!(script.location(), "test.deet:2");
+        assert_eq!(script.next_line().as_deref(), Some("run"));
+        assert_eq!(script.location(), "test.deet:4");
+        assert_eq!(script.next_line().as_deref(), Some("print x"));
+        assert_eq!(script.next_line(), None);
+        let mut script = Script::for_breakpoint(2, &["continue".to_string()]);
+        assert!(script.from_breakpoint);
+        assert_eq!(script.next_line().as_deref(), Some("continue"));
+        assert_eq!(script.location(), "breakpoint 2:1");
+    }
+}
@@ -0,0 +1,169 @@
+use nix::sys::signal::Signal;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::CStr;
+
+/// What deet does when the inferior receives a signal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SignalHandling {
+    /// Stop and tell the user, rather than carrying on silently
+    pub stop: bool,
+    /// Deliver the signal to the inferior when it resumes, rather than discarding it
+    pub pass: bool,
+}
+
+/// A change to a signal's handling, as given to the `handle` command
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HandleAction {
+    Stop,
+    NoStop,
+    Pass,
+    NoPass,
+}
+
+impl HandleAction {
+    pub fn parse(action: &str) -> Option<HandleAction> {
+        match action {
+            "stop" => Some(HandleAction::Stop),
+            "nostop" => Some(HandleAction::NoStop),
+            "pass" | "noignore" => Some(HandleAction::Pass),
+            "nopass" | "ignore" => Some(HandleAction::NoPass),
+            _ => None,
+        }
+    }
+}
+
+/// The handling of every signal. Signals that haven't been changed by the user are handled as
+/// gdb does by default.
+#[derive(Clone, Debug, Default)]
+pub struct SignalTable {
+    changed: HashMap<Signal, SignalHandling>,
+}
+
+impl SignalTable {
+    pub fn get(&self, signal: Signal) -> SignalHandling {
+        match self.changed.get(&signal) {
+            Some(handling) => *handling,
+            None => default_handling(signal),
+        }
+    }
+
+    pub fn apply(&mut self, signal: Signal, action: HandleAction) {
+        let mut handling = self.get(signal);
+        match action {
+            HandleAction::Stop => handling.stop = true,
+            HandleAction::NoStop => handling.stop = false,
+            HandleAction::Pass => handling.pass = true,
+            HandleAction::NoPass => handling.pass = false,
+        }
+        self.changed.insert(signal, handling);
+    }
+}
+
+fn default_handling(signal: Signal) -> SignalHandling {
+    match signal {
+        // these are part of the normal running of many programs
+        Signal::SIGALRM
+        | Signal::SIGURG
+        | Signal::SIGIO
+        | Signal::SIGVTALRM
+        | Signal::SIGPROF
+        | Signal::SIGCHLD
+        | Signal::SIGWINCH => SignalHandling { stop: false, pass: true },
+        // these are how the user and the debugger interrupt the program
+        Signal::SIGINT | Signal::SIGTRAP | Signal::SIGSTOP => SignalHandling { stop: true, pass: false },
+        _ => SignalHandling { stop: true, pass: true },
+    }
+}
+
+/// Parses a signal name, with or without the SIG prefix (`SIGUSR1`, `usr1`).
+pub fn parse_signal(name: &str) -> Option<Signal> {
+    let name = name.to_uppercase();
+    let name = if name.starts_with("SIG") { name } else { format!("SIG{}", name) };
+    name.parse().ok()
+}
+
+/// Returns the system's description of `signal`, e.g. "Segmentation fault".
+pub fn signal_description(signal: Signal) -> String {
+    unsafe { CStr::from_ptr(libc::strsignal(signal as libc::c_int)) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Explains where a signal came from, using the information the kernel recorded when it was
+/// sent: the faulting address for hardware exceptions, or the sender for signals sent by kill.
+pub fn describe_siginfo(siginfo: &libc::siginfo_t) -> Option<String> {
+    let signal = Signal::try_from(siginfo.si_signo).ok()?;
+    let fault = match signal {
+        Signal::SIGSEGV => match siginfo.si_code {
+            1 => Some("Address not mapped to object"),
+            2 => Some("Invalid permissions for mapped object"),
+            _ => None,
+        },
+        Signal::SIGBUS => match siginfo.si_code {
+            1 => Some("Invalid address alignment"),
+            2 => Some("Nonexistent physical address"),
+            3 => Some("Object-specific hardware error"),
+            _ => None,
+        },
+        Signal::SIGFPE => match siginfo.si_code {
+            1 => Some("Integer divide by zero"),
+            2 => Some("Integer overflow"),
+            3 => Some("Floating-point divide by zero"),
+            4 => Some("Floating-point overflow"),
+            5 => Some("Floating-point underflow"),
+            6 => Some("Floating-point inexact result"),
+            7 => Some("Invalid floating-point operation"),
+            8 => Some("Subscript out of range"),
+            _ => None,
+        },
+        Signal::SIGILL => match siginfo.si_code {
+            1 => Some("Illegal opcode"),
+            2 => Some("Illegal operand"),
+            3 => Some("Illegal addressing mode"),
+            4 => Some("Illegal trap"),
+            5 => Some("Privileged opcode"),
+            6 => Some("Privileged register"),
+            7 => Some("Coprocessor error"),
+            8 => Some("Internal stack error"),
+            _ => None,
+        },
+        _ => None,
+    };
+    if let Some(fault) = fault {
+        let addr = unsafe { siginfo.si_addr() } as usize;
+        return Some(format!("{} at address {:#x}", fault, addr));
+    }
+    // the sender's pid is the first field of the union that follows si_signo, si_errno,
+    // si_code and padding
+    let sender = unsafe { *(siginfo as *const libc::siginfo_t as *const libc::c_int).add(4) };
+    match siginfo.si_code {
+        libc::SI_USER => Some(format!("Sent by kill() from process {}", sender)),
+        libc::SI_TKILL => Some(format!("Sent by tkill() from process {}", sender)),
+        libc::SI_QUEUE => Some(format!("Sent by sigqueue() from process {}", sender)),
+        libc::SI_KERNEL => Some("Sent by the kernel".to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("SIGUSR1"), Some(Signal::SIGUSR1));
+        assert_eq!(parse_signal("segv"), Some(Signal::SIGSEGV));
+        assert_eq!(parse_signal("SIGBOGUS"), None);
+    }
+
+    #[test]
+    fn test_handle_actions() {
+        let mut table = SignalTable::default();
+        assert_eq!(table.get(Signal::SIGCHLD), SignalHandling { stop: false, pass: true });
+        table.apply(Signal::SIGCHLD, HandleAction::Stop);
+        table.apply(Signal::SIGCHLD, HandleAction::NoPass);
+        assert_eq!(table.get(Signal::SIGCHLD), SignalHandling { stop: true, pass: false });
+        assert_eq!(table.get(Signal::SIGUSR1), SignalHandling { stop: true, pass: true });
+    }
+}
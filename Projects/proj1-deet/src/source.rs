@@ -0,0 +1,97 @@
+//! Reading the program's source files, for `list` and for showing the line the inferior stopped
+//! at.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How many lines `list` shows at a time
+pub const LIST_SIZE: usize = 10;
+
+/// Finds source files. The debugging information names them by the paths they had when the
+/// program was compiled; when they've moved since, the `directory` command adds directories to
+/// look in instead.
+#[derive(Default)]
+pub struct SourcePath {
+    /// Most recently added first
+    directories: Vec<String>,
+}
+
+impl SourcePath {
+    pub fn add_directory(&mut self, dir: &str) {
+        let dir = dir.trim_end_matches('/').to_string();
+        self.directories.retain(|other| *other != dir);
+        self.directories.insert(0, dir);
+    }
+
+    pub fn reset(&mut self) {
+        self.directories.clear();
+    }
+
+    pub fn directories(&self) -> &[String] {
+        &self.directories
+    }
+
+    /// The paths tried for `file`, in order: the path itself, then, in each directory, the path
+    /// (if it's relative) and the file's name.
+    fn candidates(&self, file: &str) -> Vec<PathBuf> {
+        let mut candidates = vec![PathBuf::from(file)];
+        let file_name = Path::new(file).file_name();
+        for dir in &self.directories {
+            if Path::new(file).is_relative() {
+                candidates.push(Path::new(dir).join(file));
+            }
+            if let Some(file_name) = file_name {
+                candidates.push(Path::new(dir).join(file_name));
+            }
+        }
+        candidates
+    }
+
+    /// Reads the lines of `file`, or returns None if it can't be found.
+    pub fn read(&self, file: &str) -> Option<Vec<String>> {
+        self.candidates(file)
+            .iter()
+            .find_map(|path| fs::read(path).ok())
+            .map(|contents| String::from_utf8_lossy(&contents).lines().map(String::from).collect())
+    }
+}
+
+/// The first line of a listing centered on `line`.
+pub fn window_start(line: usize) -> usize {
+    line.saturating_sub(LIST_SIZE / 2).max(1)
+}
+
+/// Formats a line of a listing. The line the selected frame is executing is marked with `=>`,
+/// and lines with breakpoints with `b`.
+pub fn format_line(number: usize, text: &str, current: bool, breakpoint: bool) -> String {
+    format!(
+        "{}{} {:<5} {}",
+        if current { "=>" } else { "  " },
+        if breakpoint { "b" } else { " " },
+        number,
+        text
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_source_path() {
+        let mut source_path = SourcePath::default();
+        source_path.add_directory("/src/");
+        source_path.add_directory("/other");
+        source_path.add_directory("/src");
+        assert_eq!(source_path.directories(), ["/src", "/other"]);
+        let candidates = source_path.candidates("lib/main.c");
+        assert_eq!(candidates[0], Path::new("lib/main.c"));
+        assert_eq!(candidates[1], Path::new("/src/lib/main.c"));
+        assert_eq!(candidates[2], Path::new("/src/main.c"));
+        assert_eq!(source_path.candidates("/build/main.c")[1], Path::new("/src/main.c"));
+        assert_eq!(window_start(3), 1);
+        assert_eq!(window_start(20), 15);
+        assert_eq!(format_line(12, "int x;", true, true), "=>b 12    int x;");
+        assert_eq!(format_line(7, "", false, false), "    7     ");
+    }
+}
@@ -0,0 +1,258 @@
+//! An optional full-screen front-end (`layout`, `tui enable` or `deet -tui`): panes at the top of
+//! the terminal show the source around the current line, the disassembly or the registers, and
+//! the `(deet)` prompt and command output scroll underneath them. It's drawn with plain ANSI
+//! escape sequences, keeping the output below the panes with a scrolling region, so the
+//! line-oriented command loop works unchanged.
+
+use std::io::{self, Write};
+
+/// The smallest terminal the panes and a usable command area fit in
+const MIN_ROWS: usize = 12;
+/// Rows kept for the prompt and command output, at least
+const MIN_COMMAND_ROWS: usize = 6;
+
+/// Which panes are shown, as `layout` names them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    /// `src`: the source only
+    Source,
+    /// `asm`: the disassembly only
+    Assembly,
+    /// `split`: the source above the disassembly
+    Split,
+    /// `regs`: the registers above the source
+    Registers,
+}
+
+/// What a pane shows
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PaneKind {
+    Source,
+    Assembly,
+    Registers,
+}
+
+impl Layout {
+    pub fn from_name(name: &str) -> Option<Layout> {
+        match name {
+            "src" => Some(Layout::Source),
+            "asm" => Some(Layout::Assembly),
+            "split" => Some(Layout::Split),
+            "regs" => Some(Layout::Registers),
+            _ => None,
+        }
+    }
+
+    /// The panes, from top to bottom
+    pub fn panes(self) -> &'static [PaneKind] {
+        match self {
+            Layout::Source => &[PaneKind::Source],
+            Layout::Assembly => &[PaneKind::Assembly],
+            Layout::Split => &[PaneKind::Source, PaneKind::Assembly],
+            Layout::Registers => &[PaneKind::Registers, PaneKind::Source],
+        }
+    }
+}
+
+/// The contents of a pane, gathered by the debugger each time the panes are drawn
+pub struct Pane {
+    pub title: String,
+    pub lines: Vec<String>,
+    /// The line to keep in view, near the middle of the pane
+    pub focus: Option<usize>,
+    /// Whether to highlight the focused line, because the inferior is stopped there
+    pub highlight: bool,
+    /// Whether the lines are short entries (like registers) to lay out in as many columns as fit
+    pub tiled: bool,
+}
+
+impl Pane {
+    pub fn new(title: String, lines: Vec<String>) -> Pane {
+        Pane {
+            title,
+            lines,
+            focus: None,
+            highlight: false,
+            tiled: false,
+        }
+    }
+}
+
+pub struct Tui {
+    pub layout: Layout,
+}
+
+impl Tui {
+    /// Takes over the top of the terminal, or explains why it can't.
+    pub fn open(layout: Layout) -> Result<Tui, String> {
+        if unsafe { libc::isatty(libc::STDOUT_FILENO) } == 0 {
+            return Err("Cannot enable the TUI when output is not a terminal".to_string());
+        }
+        let (rows, _) = terminal_size();
+        if rows < MIN_ROWS {
+            return Err(format!("Cannot enable the TUI in a terminal of {} rows; it needs {}.", rows, MIN_ROWS));
+        }
+        // start from a clear screen with the cursor in the command area
+        print!("\x1b[2J\x1b[{};1H", rows);
+        io::stdout().flush().ok();
+        Ok(Tui { layout })
+    }
+
+    /// Redraws the panes, with `status` on the line below them. The size of the terminal is
+    /// checked every time, so the panes follow it when it's resized.
+    pub fn draw(&self, panes: &[Pane], status: &str) {
+        let (rows, columns) = terminal_size();
+        let pane_rows = pane_rows(rows);
+        // save the cursor, which is at the prompt, and put it back once the panes are drawn
+        let mut out = String::from("\x1b7");
+        for (i, line) in render(panes, status, pane_rows, columns).iter().enumerate() {
+            out.push_str(&format!("\x1b[{};1H\x1b[2K{}", i + 1, line));
+        }
+        // commands and their output scroll in the rows below the panes
+        out.push_str(&format!("\x1b[{};{}r\x1b8", pane_rows + 1, rows));
+        print!("{}", out);
+        io::stdout().flush().ok();
+    }
+}
+
+impl Drop for Tui {
+    /// Gives the whole terminal back to the command line. The panes are left to scroll away.
+    fn drop(&mut self) {
+        let (rows, _) = terminal_size();
+        print!("\x1b[r\x1b[{};1H", rows);
+        io::stdout().flush().ok();
+    }
+}
+
+/// The terminal's rows and columns, or a VT100's if it won't say.
+fn terminal_size() -> (usize, usize) {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_row > 0 {
+        (size.ws_row as usize, size.ws_col as usize)
+    } else {
+        (24, 80)
+    }
+}
+
+/// How many rows of a terminal go to the panes (including the status line), leaving about a
+/// third of it for commands.
+fn pane_rows(rows: usize) -> usize {
+    rows - (rows / 3).max(MIN_COMMAND_ROWS)
+}
+
+/// Lays out the panes and the status line in exactly `rows` lines of at most `columns`
+/// characters (not counting escape sequences).
+fn render(panes: &[Pane], status: &str, rows: usize, columns: usize) -> Vec<String> {
+    let mut rendered = Vec::new();
+    let pane_rows = rows - 1;
+    for (i, pane) in panes.iter().enumerate() {
+        let height = if i + 1 == panes.len() {
+            pane_rows - rendered.len()
+        } else {
+            pane_rows / panes.len()
+        };
+        rendered.extend(render_pane(pane, height, columns));
+    }
+    rendered.push(reverse(&pad(status, columns)));
+    rendered
+}
+
+/// Renders a pane as a title bar followed by `height - 1` lines of its contents.
+fn render_pane(pane: &Pane, height: usize, columns: usize) -> Vec<String> {
+    let mut rendered = vec![reverse(&pad(&format!(" {}", pane.title), columns))];
+    let body = height.saturating_sub(1);
+    let lines = if pane.tiled {
+        tile(&pane.lines, columns)
+    } else {
+        pane.lines.clone()
+    };
+    let start = match pane.focus {
+        Some(focus) if !pane.tiled => focus.saturating_sub(body / 2).min(lines.len().saturating_sub(body)),
+        _ => 0,
+    };
+    for i in start..start + body {
+        let line = match lines.get(i) {
+            Some(line) if pane.highlight && pane.focus == Some(i) => reverse(&pad(line, columns)),
+            Some(line) => fit(line, columns),
+            None => String::new(),
+        };
+        rendered.push(line);
+    }
+    rendered
+}
+
+/// Arranges short entries in rows of as many equally wide columns as fit.
+fn tile(entries: &[String], columns: usize) -> Vec<String> {
+    let width = entries.iter().map(|entry| entry.chars().count()).max().unwrap_or(0) + 2;
+    let per_row = (columns / width).max(1);
+    entries
+        .chunks(per_row)
+        .map(|row| {
+            let row: Vec<String> = row.iter().map(|entry| format!("{:<1$}", entry, width)).collect();
+            row.concat().trim_end().to_string()
+        })
+        .collect()
+}
+
+/// Cuts a line down to `columns` characters, expanding tabs to 8-column tab stops first.
+fn fit(line: &str, columns: usize) -> String {
+    let mut fitted = String::new();
+    let mut column = 0;
+    for c in line.chars() {
+        let (c, width) = if c == '\t' { (' ', 8 - column % 8) } else { (c, 1) };
+        for _ in 0..width {
+            if column == columns {
+                return fitted;
+            }
+            fitted.push(c);
+            column += 1;
+        }
+    }
+    fitted
+}
+
+/// Fits a line to exactly `columns` characters, so a highlight spans the whole row.
+fn pad(line: &str, columns: usize) -> String {
+    format!("{:<1$}", fit(line, columns), columns)
+}
+
+fn reverse(text: &str) -> String {
+    format!("\x1b[7m{}\x1b[0m", text)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        assert_eq!(Layout::from_name("regs"), Some(Layout::Registers));
+        assert_eq!(Layout::from_name("source"), None);
+        assert_eq!(pane_rows(24), 16);
+        assert_eq!(pane_rows(12), 6);
+        assert_eq!(fit("\tx = 1;", 20), "        x = 1;");
+        assert_eq!(fit("a\tbcdefghijk", 10), "a       bc");
+        let entries: Vec<String> = ["rax 0x1", "rbx 0x2", "rcx 0x3"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(tile(&entries, 20), ["rax 0x1  rbx 0x2", "rcx 0x3"]);
+
+        let lines: Vec<String> = (1..=20).map(|n| format!("line {}", n)).collect();
+        let pane = Pane {
+            focus: Some(9),
+            highlight: true,
+            ..Pane::new("main.c".to_string(), lines)
+        };
+        let rendered = render_pane(&pane, 5, 12);
+        assert_eq!(rendered[0], "\x1b[7m main.c     \x1b[0m");
+        assert_eq!(rendered[1..], ["line 8", "line 9", "\x1b[7mline 10     \x1b[0m", "line 11"]);
+        // near the end of the file, the pane is filled from the last line up
+        let pane = Pane {
+            focus: Some(19),
+            ..pane
+        };
+        assert_eq!(render_pane(&pane, 5, 12)[1], "line 17");
+        let rendered = render(&[pane, Pane::new("empty".to_string(), Vec::new())], "status", 8, 12);
+        assert_eq!(rendered.len(), 8);
+        assert_eq!(rendered[3], "\x1b[7m empty      \x1b[0m");
+        assert_eq!(rendered[7], "\x1b[7mstatus      \x1b[0m");
+    }
+}
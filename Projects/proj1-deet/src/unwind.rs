@@ -0,0 +1,249 @@
+//! Stack unwinding using the call frame information (CFI) that compilers emit in `.eh_frame` and
+//! `.debug_frame`. For every instruction, it describes where the caller's registers (and the
+//! return address) have been saved, so it works whether or not the code keeps a frame pointer.
+
+use gimli::{
+    BaseAddresses, CfaRule, DebugFrame, EhFrame, RegisterRule, RunTimeEndian,
+    UninitializedUnwindContext, UnwindSection, X86_64,
+};
+use object::{Object, ObjectSection, ObjectSegment, SymbolKind};
+
+/// A stack frame found while unwinding.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// Where the frame is executing. For callers, this is the return address.
+    pub pc: usize,
+    /// The address to look the frame's function and line up with. Return addresses point after
+    /// the call instruction, which may already belong to the next line or even function.
+    pub lookup_pc: usize,
+    pub rbp: usize,
+    pub rsp: usize,
+    /// Whether this is the frame the thread is executing in. Only %rip, %rbp and %rsp are
+    /// recovered for its callers; their other registers aren't known.
+    pub innermost: bool,
+    /// Functions inlined at `pc` get frames of their own, which share the registers of the
+    /// frame they were inlined into. This counts the inlined calls between the innermost
+    /// function at `pc` and this one (see DwarfData::get_inlined_frames).
+    pub inline_depth: usize,
+    /// The canonical frame address: the value %rsp had in the caller before its call
+    /// instruction, or None if the frame couldn't be unwound
+    pub cfa: Option<usize>,
+    /// The ELF symbol containing `pc`, for code without debugging information
+    pub symbol: Option<String>,
+    /// The file the code at `pc` was loaded from
+    pub module: Option<String>,
+}
+
+/// Where the caller's value of a register can be found
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SavedRegister {
+    /// The register hasn't been changed
+    Unchanged,
+    /// Saved in memory at CFA + offset
+    AtCfaOffset(i64),
+    /// The value is CFA + offset
+    CfaOffset(i64),
+    /// There is no value, e.g. the return address of the outermost frame
+    Undefined,
+    /// Described in a way we don't support
+    Unknown,
+}
+
+/// How to recover the caller's frame at a particular instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameRules {
+    /// The CFA is the value of this register (DWARF numbering) plus `cfa_offset`
+    pub cfa_register: u16,
+    pub cfa_offset: i64,
+    pub return_address: SavedRegister,
+    pub rbp: SavedRegister,
+}
+
+/// An object file (the program or a shared library) mapped into the inferior, with what we
+/// need from it to unwind through and name its code.
+pub struct Module {
+    endian: RunTimeEndian,
+    eh_frame: Vec<u8>,
+    eh_frame_addr: u64,
+    debug_frame: Vec<u8>,
+    text_addr: u64,
+    /// The link-time address of the start of the file
+    base_addr: u64,
+    /// Function symbols as (address, size, name), sorted by address
+    symbols: Vec<(u64, u64, String)>,
+}
+
+impl Module {
+    pub fn from_file(path: &str) -> Option<Module> {
+        let data = std::fs::read(path).ok()?;
+        let object = object::File::parse(&data).ok()?;
+        let endian = if object.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+        let section = |name: &str| {
+            object
+                .section_by_name(name)
+                .and_then(|section| Some((section.address(), section.data().ok()?.to_vec())))
+                .unwrap_or_default()
+        };
+        let (eh_frame_addr, eh_frame) = section(".eh_frame");
+        let (_, debug_frame) = section(".debug_frame");
+        let (text_addr, _) = section(".text");
+        let base_addr = object
+            .segments()
+            .filter(|segment| segment.file_range().0 == 0)
+            .map(|segment| segment.address())
+            .min()
+            .unwrap_or(0);
+        // stripped libraries only have their dynamic symbols left
+        let mut symbols: Vec<(u64, u64, String)> = object
+            .symbols()
+            .chain(object.dynamic_symbols())
+            .filter(|(_, symbol)| symbol.kind() == SymbolKind::Text && !symbol.is_undefined())
+            .filter_map(|(_, symbol)| Some((symbol.address(), symbol.size(), symbol.name()?.to_string())))
+            .collect();
+        symbols.sort();
+        symbols.dedup_by_key(|(addr, _, _)| *addr);
+        Some(Module {
+            endian,
+            eh_frame,
+            eh_frame_addr,
+            debug_frame,
+            text_addr,
+            base_addr,
+            symbols,
+        })
+    }
+
+    /// The link-time address that was loaded at the start of the file's first mapping.
+    pub fn base_addr(&self) -> u64 {
+        self.base_addr
+    }
+
+    /// Returns the name of the function symbol containing the link-time address `addr`.
+    pub fn symbol_at(&self, addr: u64) -> Option<&str> {
+        let index = match self.symbols.binary_search_by_key(&addr, |(start, _, _)| *start) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        let (start, size, name) = &self.symbols[index];
+        // some hand-written assembly symbols don't have a size
+        if addr < start + size.max(&1) {
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    /// Looks up how to unwind the frame executing the link-time address `addr`.
+    pub fn find_rules(&self, addr: u64) -> Option<FrameRules> {
+        let bases = BaseAddresses::default()
+            .set_eh_frame(self.eh_frame_addr)
+            .set_text(self.text_addr);
+        let mut ctx = UninitializedUnwindContext::new();
+        let eh_frame = EhFrame::new(&self.eh_frame, self.endian);
+        let row = match eh_frame.unwind_info_for_address(&bases, &mut ctx, addr, EhFrame::cie_from_offset) {
+            Ok(row) => row,
+            Err(_) => {
+                let debug_frame = DebugFrame::new(&self.debug_frame, self.endian);
+                debug_frame
+                    .unwind_info_for_address(&bases, &mut ctx, addr, DebugFrame::cie_from_offset)
+                    .ok()?
+            }
+        };
+        let (cfa_register, cfa_offset) = match row.cfa() {
+            CfaRule::RegisterAndOffset { register, offset } => (register.0, *offset),
+            CfaRule::Expression(_) => return None,
+        };
+        Some(FrameRules {
+            cfa_register,
+            cfa_offset,
+            return_address: saved_register(row.register(X86_64::RA), false),
+            // callee-saved registers without a rule haven't been touched
+            rbp: saved_register(row.register(X86_64::RBP), true),
+        })
+    }
+}
+
+fn saved_register<R: gimli::Reader>(rule: RegisterRule<R>, callee_saved: bool) -> SavedRegister {
+    match rule {
+        RegisterRule::Undefined if callee_saved => SavedRegister::Unchanged,
+        RegisterRule::Undefined => SavedRegister::Undefined,
+        RegisterRule::SameValue => SavedRegister::Unchanged,
+        RegisterRule::Offset(offset) => SavedRegister::AtCfaOffset(offset),
+        RegisterRule::ValOffset(offset) => SavedRegister::CfaOffset(offset),
+        _ => SavedRegister::Unknown,
+    }
+}
+
+/// DWARF numbers of the registers a CFA can be based on
+pub const RSP: u16 = X86_64::RSP.0;
+pub const RBP: u16 = X86_64::RBP.0;
+
+/// A file mapped into a process's address space, from /proc/<pid>/maps
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mapping {
+    pub start: usize,
+    pub end: usize,
+    pub offset: usize,
+    pub executable: bool,
+    pub path: String,
+}
+
+/// Parses the file-backed mappings listed in /proc/<pid>/maps.
+pub fn parse_maps(maps: &str) -> Vec<Mapping> {
+    maps.lines()
+        .filter_map(|line| {
+            // start-end perms offset dev inode path
+            let mut fields = line.split_whitespace();
+            let mut range = fields.next()?.split('-');
+            let start = usize::from_str_radix(range.next()?, 16).ok()?;
+            let end = usize::from_str_radix(range.next()?, 16).ok()?;
+            let executable = fields.next()?.contains('x');
+            let offset = usize::from_str_radix(fields.next()?, 16).ok()?;
+            let path = fields.nth(2)?;
+            if !path.starts_with('/') {
+                return None;
+            }
+            Some(Mapping {
+                start,
+                end,
+                offset,
+                executable,
+                path: path.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_maps() {
+        let maps = "\
+00400000-00401000 r--p 00000000 08:01 1234                               /tmp/samples/watch
+00401000-00402000 r-xp 00001000 08:01 1234                               /tmp/samples/watch
+7ffff7dd3000-7ffff7df5000 r--p 00000000 08:01 5678                       /usr/lib/libc.so.6
+7ffffffde000-7ffffffff000 rw-p 00000000 00:00 0                          [stack]
+7ffff7fc1000-7ffff7fc5000 rw-p 00000000 00:00 0 ";
+        let mappings = parse_maps(maps);
+        assert_eq!(mappings.len(), 3);
+        assert_eq!(
+            mappings[1],
+            Mapping {
+                start: 0x401000,
+                end: 0x402000,
+                offset: 0x1000,
+                executable: true,
+                path: "/tmp/samples/watch".to_string(),
+            }
+        );
+        assert_eq!(mappings[2].path, "/usr/lib/libc.so.6");
+        assert!(!mappings[2].executable);
+    }
+}